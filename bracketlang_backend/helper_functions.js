@@ -7,4 +7,7 @@ export function js_print(value) {
 export function js_get_time() {
     const date = new Date();
     return date.getTime();
-}
\ No newline at end of file
+}
+export function js_performance_now() {
+    return performance.now();
+}
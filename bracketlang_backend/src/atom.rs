@@ -0,0 +1,75 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    evaluator::{RuntimeError, apply},
+    variable_type::{DataType, Environment},
+};
+
+/// The value held by an atom, with the functions that check and watch it.
+pub struct AtomState {
+    pub value: RefCell<DataType>,
+    /// Called with every new value, which is refused unless it returns truthy
+    pub validator: RefCell<Option<DataType>>,
+    /// Called as `(f key atom old new)` after every change, in the order added
+    pub watches: RefCell<Vec<(DataType, DataType)>>,
+}
+
+impl AtomState {
+    pub fn new(value: DataType) -> AtomState {
+        Self {
+            value: RefCell::new(value),
+            validator: RefCell::new(None),
+            watches: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn get(&self) -> DataType {
+        self.value.borrow().clone()
+    }
+}
+
+/// Fails unless `validator` accepts `value`.
+pub fn validate(
+    validator: Option<&DataType>,
+    value: &DataType,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<(), RuntimeError> {
+    let Some(validator) = validator else {
+        return Ok(());
+    };
+
+    match apply(validator, std::slice::from_ref(value), env)? {
+        DataType::Bool(false) | DataType::Nil() => Err(RuntimeError {
+            msg: format!("Invalid reference state {:?}", value),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Gives `atom` the value `new` once its validator accepts it, then calls its
+/// watches. Returns the value it replaced.
+///
+/// Each call runs in a nested evaluation, so one that keeps changing the atom
+/// it watches fails once the runtime's nesting limit is reached.
+pub fn set(
+    atom: &Rc<AtomState>,
+    new: DataType,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<DataType, RuntimeError> {
+    let validator = atom.validator.borrow().clone();
+    validate(validator.as_ref(), &new, env)?;
+
+    let old = atom.value.replace(new.clone());
+
+    // Copied first so a watch can add or remove watches
+    let watches = atom.watches.borrow().clone();
+    for (key, watch) in watches {
+        apply(
+            &watch,
+            &[key, DataType::Atom(atom.clone()), old.clone(), new.clone()],
+            env,
+        )?;
+    }
+
+    Ok(old)
+}
@@ -0,0 +1,116 @@
+use crate::variable_type::DataType;
+
+/// A group of builtins that reach outside the interpreter, which a host can
+/// switch off before running code it doesn't trust.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Capability {
+    /// `prn` and `input`
+    Io,
+    /// Files and modules: `slurp`, `spit`, `require` and the rest
+    Fs,
+    /// `time-ms`, `now`, `nano-time` and the `time`, `bench` and `profile`
+    /// macros
+    Time,
+    /// `getenv`, `setenv`, `exit`, `sh` and `run-process`
+    Process,
+    /// `eval`, and so `load-file`
+    Eval,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::Io,
+        Capability::Fs,
+        Capability::Time,
+        Capability::Process,
+        Capability::Eval,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Io => "io",
+            Capability::Fs => "fs",
+            Capability::Time => "time",
+            Capability::Process => "process",
+            Capability::Eval => "eval",
+        }
+    }
+
+    /// The capability the builtin `name` needs, if any.
+    pub fn of_builtin(name: &str) -> Option<Capability> {
+        match name {
+            "prn" | "input" => Some(Capability::Io),
+            "slurp" | "spit" | "file-exists?" | "list-dir" | "mkdir" | "delete-file"
+            | "read-lines" | "open-reader" | "open-writer" => Some(Capability::Fs),
+            "time-ms" | "now" | "nano-time" | "time-fn" | "bench-fn" | "profile-fn" => {
+                Some(Capability::Time)
+            }
+            "getenv" | "setenv" | "exit" | "sh" | "run-process" => Some(Capability::Process),
+            _ => None,
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+/// A set of capabilities. The default has everything but `Process`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Capabilities(u8);
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::all().without(Capability::Process)
+    }
+}
+
+impl Capabilities {
+    pub fn none() -> Capabilities {
+        Capabilities(0)
+    }
+
+    pub fn all() -> Capabilities {
+        Capability::ALL
+            .iter()
+            .fold(Capabilities::none(), |all, capability| {
+                all.with(*capability)
+            })
+    }
+
+    pub fn contains(&self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    pub fn with(self, capability: Capability) -> Capabilities {
+        Capabilities(self.0 | capability.bit())
+    }
+
+    pub fn without(self, capability: Capability) -> Capabilities {
+        Capabilities(self.0 & !capability.bit())
+    }
+}
+
+/// Budgets for a single evaluation. Going over one stops the evaluation with
+/// an error that `try*` can't recover from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Limits {
+    /// Iterations of the evaluator's loop
+    pub max_steps: Option<u64>,
+    /// Roughly how many values may be created, counting every element of a
+    /// new collection and every character of a new string
+    pub max_allocations: Option<u64>,
+    /// Bytes written through `prn`
+    pub max_output: Option<usize>,
+}
+
+/// How much of the allocation budget `value` uses up.
+pub fn allocation_size(value: &DataType) -> u64 {
+    let size = match value {
+        DataType::List(items, _) | DataType::Vector(items, _) => items.len(),
+        DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => dict.len(),
+        DataType::String(string) => string.len(),
+        _ => 0,
+    };
+    1 + size as u64
+}
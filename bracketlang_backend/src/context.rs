@@ -0,0 +1,91 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    capability::Capability,
+    evaluator::{RuntimeError, apply},
+    filesystem::FileSystem,
+    runtime::{Runtime, default_filesystem, default_read_line, default_write},
+    variable_type::{DataType, Environment},
+};
+
+/// The signature of every native function.
+pub type NativeFn = fn(&mut Context, &[DataType]) -> Result<DataType, RuntimeError>;
+
+/// The interpreter as seen by a native function: the environment it was
+/// called from, a way to call back into the program, and the runtime's
+/// output sinks and limits.
+pub struct Context {
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Context {
+    pub fn new(env: Rc<RefCell<Environment>>) -> Context {
+        Context { env }
+    }
+
+    pub fn env(&self) -> Rc<RefCell<Environment>> {
+        self.env.clone()
+    }
+
+    /// The runtime shared by the program, unless this is a bare environment.
+    pub fn runtime(&self) -> Option<Rc<Runtime>> {
+        self.env.borrow().runtime()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<DataType> {
+        self.env.borrow().get(&name.to_string())
+    }
+
+    /// Calls any callable value with `args`.
+    pub fn call(
+        &mut self,
+        function: &DataType,
+        args: &[DataType],
+    ) -> Result<DataType, RuntimeError> {
+        apply(function, args, &self.env)
+    }
+
+    /// Writes `text` to the runtime's standard output.
+    pub fn write(&self, text: &str) -> Result<(), RuntimeError> {
+        let runtime = self.env.borrow().runtime();
+        match runtime {
+            Some(runtime) => runtime.write(text),
+            None => {
+                default_write(text);
+                Ok(())
+            }
+        }
+    }
+
+    /// Shows `prompt` and reads a line from the runtime's standard input.
+    pub fn read_line(&self, prompt: &str) -> Option<String> {
+        let runtime = self.env.borrow().runtime();
+        match runtime {
+            Some(runtime) => runtime.read_line(prompt),
+            None => default_read_line(prompt),
+        }
+    }
+
+    /// The file system the file builtins should go through.
+    pub fn filesystem(&self) -> Rc<dyn FileSystem> {
+        let runtime = self.env.borrow().runtime();
+        match runtime {
+            Some(runtime) => runtime.filesystem(),
+            None => default_filesystem(),
+        }
+    }
+
+    /// Fails unless the runtime allows `capability`, which `name` needs.
+    pub fn require(&self, capability: Capability, name: &str) -> Result<(), RuntimeError> {
+        let runtime = self.env.borrow().runtime();
+        match runtime {
+            Some(runtime) => runtime.require(capability, name),
+            None => Ok(()),
+        }
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        let runtime = self.env.borrow().runtime();
+        runtime?.max_depth()
+    }
+}
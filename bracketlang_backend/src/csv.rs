@@ -0,0 +1,308 @@
+use std::{collections::HashMap, io::BufRead};
+
+use crate::{evaluator::RuntimeError, json::key_name, variable_type::DataType};
+
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub quote: char,
+    /// Read the first row as column names and return the rest as dictionaries
+    pub header: bool,
+    /// Quote every field when writing, not only those that need it
+    pub quote_all: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            quote: '"',
+            header: false,
+            quote_all: false,
+        }
+    }
+}
+
+fn single_char(option: &str, value: &DataType) -> Result<char, RuntimeError> {
+    let DataType::String(string) = value else {
+        return Err(RuntimeError {
+            msg: format!("{} should be a string", option),
+        });
+    };
+
+    let mut chars = string.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(RuntimeError {
+            msg: format!("{} should be a single character", option),
+        }),
+    }
+}
+
+impl CsvOptions {
+    /// Reads `:delimiter`, `:quote`, `:header` and `:quote-all` from an
+    /// options dictionary.
+    pub fn from_dict(options: Option<&DataType>) -> Result<CsvOptions, RuntimeError> {
+        let mut result = CsvOptions::default();
+        let options = match options {
+            None => return Ok(result),
+            Some(DataType::Dictionary(options, _)) => options,
+            Some(_) => {
+                return Err(RuntimeError {
+                    msg: "Options should be a dict".to_string(),
+                });
+            }
+        };
+
+        if let Some(delimiter) = options.get(":delimiter") {
+            result.delimiter = single_char("delimiter", delimiter)?;
+        }
+        if let Some(quote) = options.get(":quote") {
+            result.quote = single_char("quote", quote)?;
+        }
+        result.header = options.get(":header") == Some(&DataType::Bool(true));
+        result.quote_all = options.get(":quote-all") == Some(&DataType::Bool(true));
+
+        Ok(result)
+    }
+}
+
+/// The rows of a CSV document, parsed one at a time as they are asked for.
+pub struct CsvRows<R: BufRead> {
+    input: R,
+    options: CsvOptions,
+    line: usize,
+    columns: Option<Vec<String>>,
+}
+
+impl<R: BufRead> CsvRows<R> {
+    pub fn new(input: R, options: CsvOptions) -> CsvRows<R> {
+        CsvRows {
+            input,
+            options,
+            line: 0,
+            columns: None,
+        }
+    }
+
+    fn read_line(&mut self, buffer: &mut String) -> Result<bool, RuntimeError> {
+        match self.input.read_line(buffer) {
+            Ok(0) => Ok(false),
+            Ok(_) => {
+                self.line += 1;
+                Ok(true)
+            }
+            Err(e) => Err(RuntimeError {
+                msg: format!("Couldn't read CSV: {}", e),
+            }),
+        }
+    }
+
+    /// Reads the next non-blank record, which may span several lines if a
+    /// quoted field contains a newline.
+    fn read_record(&mut self) -> Result<Option<Vec<String>>, RuntimeError> {
+        let mut text = String::new();
+
+        loop {
+            text.clear();
+            if !self.read_line(&mut text)? {
+                return Ok(None);
+            }
+            if !text.trim_end_matches(['\r', '\n']).is_empty() {
+                break;
+            }
+        }
+        let start_line = self.line;
+
+        let (delimiter, quote) = (self.options.delimiter, self.options.quote);
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut chars = text.chars().collect::<Vec<_>>().into_iter().peekable();
+        let mut column = 0;
+
+        loop {
+            column += 1;
+            let Some(c) = chars.next() else {
+                if !in_quotes {
+                    break;
+                }
+                // The quoted field carries on onto the next line
+                let mut next = String::new();
+                if !self.read_line(&mut next)? {
+                    return Err(RuntimeError {
+                        msg: format!("Unterminated quoted field starting on line {}", start_line),
+                    });
+                }
+                chars = next.chars().collect::<Vec<_>>().into_iter().peekable();
+                column = 0;
+                continue;
+            };
+
+            if in_quotes {
+                if c == quote {
+                    if chars.peek() == Some(&quote) {
+                        chars.next();
+                        field.push(quote);
+                    } else {
+                        in_quotes = false;
+                    }
+                } else {
+                    field.push(c);
+                }
+            } else if c == quote && field.is_empty() {
+                in_quotes = true;
+            } else if c == quote {
+                return Err(RuntimeError {
+                    msg: format!(
+                        "Unexpected quote in unquoted field at line {}, column {}",
+                        self.line, column
+                    ),
+                });
+            } else if c == delimiter {
+                fields.push(std::mem::take(&mut field));
+            } else if c == '\n' || (c == '\r' && chars.peek() == Some(&'\n')) {
+                continue;
+            } else {
+                field.push(c);
+            }
+        }
+        fields.push(field);
+
+        Ok(Some(fields))
+    }
+
+    fn next_row(&mut self) -> Result<Option<DataType>, RuntimeError> {
+        if self.options.header && self.columns.is_none() {
+            let Some(columns) = self.read_record()? else {
+                return Ok(None);
+            };
+            self.columns = Some(
+                columns
+                    .into_iter()
+                    .map(|column| format!("{:?}", DataType::Keyword(column)))
+                    .collect(),
+            );
+        }
+
+        let Some(record) = self.read_record()? else {
+            return Ok(None);
+        };
+
+        let Some(columns) = &self.columns else {
+            return Ok(Some(DataType::Vector(
+                record.into_iter().map(DataType::String).collect(),
+                None,
+            )));
+        };
+
+        if record.len() != columns.len() {
+            return Err(RuntimeError {
+                msg: format!(
+                    "Row on line {} has {} fields, but the header has {}",
+                    self.line,
+                    record.len(),
+                    columns.len()
+                ),
+            });
+        }
+        let dict: HashMap<String, DataType> = columns
+            .iter()
+            .cloned()
+            .zip(record.into_iter().map(DataType::String))
+            .collect();
+        Ok(Some(DataType::Dictionary(dict, None)))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvRows<R> {
+    type Item = Result<DataType, RuntimeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+fn write_field(value: &DataType, options: &CsvOptions, out: &mut String) {
+    let text = match value {
+        DataType::Nil() => String::new(),
+        DataType::String(string) => string.clone(),
+        DataType::Keyword(name) => name.clone(),
+        _ => format!("{:?}", value),
+    };
+
+    let needs_quotes = options.quote_all
+        || text.contains([options.delimiter, options.quote, '\n', '\r'])
+        || text.starts_with(' ')
+        || text.ends_with(' ');
+    if !needs_quotes {
+        out.push_str(&text);
+        return;
+    }
+
+    out.push(options.quote);
+    for c in text.chars() {
+        if c == options.quote {
+            out.push(c);
+        }
+        out.push(c);
+    }
+    out.push(options.quote);
+}
+
+fn write_row<'a>(
+    fields: impl Iterator<Item = &'a DataType>,
+    options: &CsvOptions,
+    out: &mut String,
+) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(options.delimiter);
+        }
+        write_field(field, options, out);
+    }
+    out.push('\n');
+}
+
+/// Writes rows of sequences, or of dictionaries under a header row made from
+/// the first row's keys.
+pub fn write(rows: &[DataType], options: &CsvOptions) -> Result<String, RuntimeError> {
+    let mut out = String::new();
+
+    let columns = match rows.first() {
+        Some(DataType::Dictionary(dict, _) | DataType::Record(_, dict, _)) => {
+            let mut keys = dict.keys().cloned().collect::<Vec<_>>();
+            keys.sort();
+
+            let names = keys
+                .iter()
+                .map(|key| DataType::String(key_name(key).to_string()))
+                .collect::<Vec<_>>();
+            write_row(names.iter(), options, &mut out);
+            Some(keys)
+        }
+        _ => None,
+    };
+
+    for row in rows {
+        match (row, &columns) {
+            (DataType::List(fields, _) | DataType::Vector(fields, _), None) => {
+                write_row(fields.iter(), options, &mut out);
+            }
+            (DataType::Dictionary(dict, _) | DataType::Record(_, dict, _), Some(columns)) => {
+                let nil = DataType::Nil();
+                write_row(
+                    columns.iter().map(|key| dict.get(key).unwrap_or(&nil)),
+                    options,
+                    &mut out,
+                );
+            }
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!("Can't write {:?} as a CSV row", row),
+                });
+            }
+        }
+    }
+
+    Ok(out)
+}
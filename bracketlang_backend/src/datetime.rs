@@ -0,0 +1,504 @@
+use std::collections::HashMap;
+
+use crate::{evaluator::RuntimeError, scanner::Scanner, variable_type::DataType};
+
+// Instants are milliseconds since the Unix epoch. There are no time zones:
+// calendar fields are read and written as UTC, and an offset given when
+// parsing is only used to convert to UTC.
+
+/// The pattern `format-time` uses when none is given.
+pub const ISO_PATTERN: &str = "yyyy-MM-dd'T'HH:mm:ss.SSS'Z'";
+
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// The year of the latest instant. Years further from zero than this can't
+/// hold an instant.
+const MAX_YEAR: i64 = 292_278_994;
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// Milliseconds since the Unix epoch.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn epoch_millis() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn epoch_millis() -> i64 {
+    crate::js_get_time() as i64
+}
+
+/// Nanoseconds from a fixed point, which only ever goes forward.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn monotonic_nanos() -> i128 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START
+        .get_or_init(std::time::Instant::now)
+        .elapsed()
+        .as_nanos() as i128
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn monotonic_nanos() -> i128 {
+    (crate::js_performance_now() * 1_000_000.0) as i128
+}
+
+/// The calendar fields of an instant.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Fields {
+    pub year: i64,
+    /// 1 to 12
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: i64,
+    pub millis: i64,
+}
+
+fn error(msg: String) -> RuntimeError {
+    RuntimeError { msg }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, or
+/// `None` if that doesn't fit in an `i64`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era.checked_mul(146_097)?
+        .checked_add(day_of_era)?
+        .checked_sub(719_468)
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl Fields {
+    pub fn of(instant: i64) -> Fields {
+        let (year, month, day) = civil_from_days(instant.div_euclid(MS_PER_DAY));
+        let time = instant.rem_euclid(MS_PER_DAY);
+
+        Fields {
+            year,
+            month,
+            day,
+            hour: time / 3_600_000,
+            minute: time / 60_000 % 60,
+            second: time / 1000 % 60,
+            millis: time % 1000,
+        }
+    }
+
+    /// The instant these fields describe, if they are in range.
+    pub fn to_instant(self) -> Result<i64, RuntimeError> {
+        let check = |name: &str, value: i64, min: i64, max: i64| {
+            if (min..=max).contains(&value) {
+                Ok(())
+            } else {
+                Err(error(format!("{} {} is out of range", name, value)))
+            }
+        };
+        check("Year", self.year, -MAX_YEAR, MAX_YEAR)?;
+        check("Month", self.month, 1, 12)?;
+        check("Day", self.day, 1, days_in_month(self.year, self.month))?;
+        check("Hour", self.hour, 0, 23)?;
+        check("Minute", self.minute, 0, 59)?;
+        check("Second", self.second, 0, 59)?;
+        check("Millisecond", self.millis, 0, 999)?;
+
+        let time = ((self.hour * 60 + self.minute) * 60 + self.second) * 1000 + self.millis;
+        days_from_civil(self.year, self.month, self.day)
+            .and_then(|days| days.checked_mul(MS_PER_DAY))
+            .and_then(|ms| ms.checked_add(time))
+            .ok_or_else(|| error(format!("Year {} is out of range", self.year)))
+    }
+
+    /// The day these fields fall on, which always fits for fields made by
+    /// `Fields::of`.
+    fn days(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+            .expect("The fields of an instant should have a year in range")
+    }
+
+    /// 1 for Monday through 7 for Sunday.
+    pub fn weekday(&self) -> i64 {
+        (self.days() + 3).rem_euclid(7) + 1
+    }
+
+    pub fn day_of_year(&self) -> i64 {
+        let first = Fields {
+            month: 1,
+            day: 1,
+            ..*self
+        };
+        self.days() - first.days() + 1
+    }
+
+    /// The fields as a dictionary with keyword keys.
+    pub fn to_dict(self) -> DataType {
+        let entries = [
+            ("year", self.year),
+            ("month", self.month),
+            ("day", self.day),
+            ("hour", self.hour),
+            ("minute", self.minute),
+            ("second", self.second),
+            ("ms", self.millis),
+            ("weekday", self.weekday()),
+            ("day-of-year", self.day_of_year()),
+        ];
+        let dict: HashMap<String, DataType> = entries
+            .into_iter()
+            .map(|(key, value)| (format!(":{}", key), DataType::Integer(value.into())))
+            .collect();
+        DataType::Dictionary(dict, None)
+    }
+}
+
+/// The length of a unit of time in milliseconds, or `None` for months and
+/// years, which vary.
+fn unit_length(unit: &str) -> Result<Option<i64>, RuntimeError> {
+    Ok(Some(match unit {
+        "ms" | "millis" => 1,
+        "seconds" => 1000,
+        "minutes" => 60_000,
+        "hours" => 3_600_000,
+        "days" => MS_PER_DAY,
+        "weeks" => 7 * MS_PER_DAY,
+        "months" | "years" => return Ok(None),
+        _ => return Err(error(format!("Unknown unit of time :{}", unit))),
+    }))
+}
+
+fn plus_months(instant: i64, months: i64) -> Result<i64, RuntimeError> {
+    let mut fields = Fields::of(instant);
+    let month = (fields.year * 12 + fields.month - 1)
+        .checked_add(months)
+        .ok_or_else(|| error("Instant is out of range".to_string()))?;
+    fields.year = month.div_euclid(12);
+    fields.month = month.rem_euclid(12) + 1;
+    // The 31st of January plus a month is the last day of February
+    fields.day = fields.day.min(days_in_month(fields.year, fields.month));
+    fields.to_instant()
+}
+
+/// Moves `instant` by `amount` of `unit`, keeping the time of day for months
+/// and years.
+pub fn plus(instant: i64, amount: i64, unit: &str) -> Result<i64, RuntimeError> {
+    let overflow = || error("Instant is out of range".to_string());
+
+    match unit_length(unit)? {
+        Some(length) => amount
+            .checked_mul(length)
+            .and_then(|ms| instant.checked_add(ms))
+            .ok_or_else(overflow),
+        None if unit == "months" => plus_months(instant, amount),
+        None => plus_months(instant, amount.checked_mul(12).ok_or_else(overflow)?),
+    }
+}
+
+/// How many whole `unit`s there are from `start` to `end`, negative if `end`
+/// comes first.
+pub fn between(start: i64, end: i64, unit: &str) -> Result<i64, RuntimeError> {
+    if let Some(length) = unit_length(unit)? {
+        let difference = end
+            .checked_sub(start)
+            .ok_or_else(|| error("Time between the instants is out of range".to_string()))?;
+        return Ok(difference / length);
+    }
+
+    let (from, to) = (Fields::of(start), Fields::of(end));
+    let mut months = (to.year - from.year) * 12 + to.month - from.month;
+    let overshoots = |months: i64| match plus_months(start, months) {
+        Ok(moved) if months > 0 => moved > end,
+        Ok(moved) => moved < end,
+        // Past the range of instants is past `end` too
+        Err(_) => true,
+    };
+    if months != 0 && overshoots(months) {
+        months -= months.signum();
+    }
+
+    Ok(if unit == "years" { months / 12 } else { months })
+}
+
+/// Reads an ISO 8601 date, optionally followed by a time and a UTC offset,
+/// such as `2024-02-29`, `2024-02-29T13:45:00.250Z` or
+/// `2024-02-29 13:45+01:00`.
+pub fn parse_iso(text: &str) -> Result<i64, RuntimeError> {
+    let mut scanner = Scanner::new(text);
+    let mut fields = Fields::of(0);
+
+    let negative = scanner.eat('-');
+    fields.year = digits(&mut scanner, 4, 6)?;
+    if negative {
+        fields.year = -fields.year;
+    }
+    scanner.expect('-')?;
+    fields.month = digits(&mut scanner, 2, 2)?;
+    scanner.expect('-')?;
+    fields.day = digits(&mut scanner, 2, 2)?;
+
+    let mut offset = 0;
+    if scanner.eat('T') || scanner.eat(' ') {
+        fields.hour = digits(&mut scanner, 2, 2)?;
+        scanner.expect(':')?;
+        fields.minute = digits(&mut scanner, 2, 2)?;
+        if scanner.eat(':') {
+            fields.second = digits(&mut scanner, 2, 2)?;
+            if scanner.eat('.') || scanner.eat(',') {
+                let fraction = scanner.take_while(|c| c.is_ascii_digit());
+                if fraction.is_empty() {
+                    return Err(scanner.error("Expected digits after decimal point"));
+                }
+                fields.millis = format!("{:0<3}", &fraction[..fraction.len().min(3)])
+                    .parse()
+                    .unwrap_or(0);
+            }
+        }
+
+        match scanner.peek() {
+            Some('Z') => {
+                scanner.next();
+            }
+            Some(sign @ ('+' | '-')) => {
+                scanner.next();
+                let hours = digits(&mut scanner, 2, 2)?;
+                scanner.eat(':');
+                let minutes = digits(&mut scanner, 2, 2)?;
+                offset = (hours * 60 + minutes) * 60_000;
+                if sign == '-' {
+                    offset = -offset;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !scanner.at_end() {
+        return Err(scanner.unexpected());
+    }
+    Ok(fields.to_instant()? - offset)
+}
+
+/// Reads between `min` and `max` digits.
+fn digits(scanner: &mut Scanner, min: usize, max: usize) -> Result<i64, RuntimeError> {
+    let start = scanner.position();
+    let mut text = String::new();
+    while text.len() < max
+        && let Some(c) = scanner.peek()
+        && c.is_ascii_digit()
+    {
+        text.push(c);
+        scanner.next();
+    }
+
+    if text.len() < min {
+        return Err(scanner.error_at(start, &format!("Expected {} digits", min)));
+    }
+    Ok(text.parse().unwrap_or(0))
+}
+
+/// A piece of a `format-time` pattern.
+enum Token {
+    /// A run of the same pattern letter, such as `yyyy`
+    Field(char, usize),
+    Literal(String),
+}
+
+/// Splits a pattern into runs of letters and literal text. Text inside single
+/// quotes is literal, and `''` is a quote.
+fn tokenize(pattern: &str) -> Result<Vec<Token>, RuntimeError> {
+    let mut scanner = Scanner::new(pattern);
+    let mut tokens = vec![];
+
+    while let Some(c) = scanner.peek() {
+        if c == '\'' {
+            scanner.next();
+            if scanner.eat('\'') {
+                tokens.push(Token::Literal("'".to_string()));
+                continue;
+            }
+            let text = scanner.take_while(|c| c != '\'');
+            if !scanner.eat('\'') {
+                return Err(scanner.error("Unterminated quote in time pattern"));
+            }
+            tokens.push(Token::Literal(text));
+        } else if c.is_ascii_alphabetic() {
+            let run = scanner.take_while(|next| next == c);
+            if !"yMdHmsSE".contains(c) {
+                return Err(error(format!("Unknown time pattern letter {}", c)));
+            }
+            tokens.push(Token::Field(c, run.len()));
+        } else {
+            scanner.next();
+            tokens.push(Token::Literal(c.to_string()));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Writes `instant` following `pattern`, whose letters stand for `y` year,
+/// `M` month (`MMM` for its name), `d` day, `H` hour, `m` minute, `s` second,
+/// `S` millisecond and `E` weekday name.
+pub fn format(instant: i64, pattern: &str) -> Result<String, RuntimeError> {
+    let fields = Fields::of(instant);
+    let mut out = String::new();
+
+    for token in tokenize(pattern)? {
+        let (letter, width) = match token {
+            Token::Literal(text) => {
+                out.push_str(&text);
+                continue;
+            }
+            Token::Field(letter, width) => (letter, width),
+        };
+
+        let number = match letter {
+            'y' if width == 2 => fields.year.rem_euclid(100),
+            'y' => fields.year,
+            'M' if width >= 3 => {
+                let name = MONTHS[fields.month as usize - 1];
+                out.push_str(if width == 3 { &name[..3] } else { name });
+                continue;
+            }
+            'M' => fields.month,
+            'd' => fields.day,
+            'H' => fields.hour,
+            'm' => fields.minute,
+            's' => fields.second,
+            'S' => fields.millis,
+            _ => {
+                let name = WEEKDAYS[fields.weekday() as usize - 1];
+                out.push_str(if width <= 3 { &name[..3] } else { name });
+                continue;
+            }
+        };
+        out.push_str(&format!("{:0width$}", number, width = width));
+    }
+
+    Ok(out)
+}
+
+/// Reads `text` written in `pattern`, the reverse of `format`. Fields missing
+/// from the pattern are taken from 1970-01-01T00:00:00.000.
+pub fn parse(text: &str, pattern: &str) -> Result<i64, RuntimeError> {
+    let mut scanner = Scanner::new(text);
+    let mut fields = Fields::of(0);
+
+    for token in tokenize(pattern)? {
+        let (letter, width) = match token {
+            Token::Literal(literal) => {
+                for c in literal.chars() {
+                    scanner.expect(c)?;
+                }
+                continue;
+            }
+            Token::Field(letter, width) => (letter, width),
+        };
+
+        if letter == 'E' || (letter == 'M' && width >= 3) {
+            let names = if letter == 'E' {
+                &WEEKDAYS[..]
+            } else {
+                &MONTHS[..]
+            };
+            let start = scanner.position();
+            let word = scanner.take_while(|c| c.is_ascii_alphabetic());
+            let index = names.iter().position(|name| {
+                name.eq_ignore_ascii_case(&word)
+                    || (word.len() == 3 && name[..3].eq_ignore_ascii_case(&word))
+            });
+            match index {
+                Some(index) if letter == 'M' => fields.month = index as i64 + 1,
+                Some(_) => {}
+                None => return Err(scanner.error_at(start, &format!("Unknown name {}", word))),
+            }
+            continue;
+        }
+
+        // A single letter accepts any number of digits, as `format` writes
+        let max = if width == 1 { 9 } else { width };
+        let value = digits(&mut scanner, width, max)?;
+        match letter {
+            'y' if width == 2 => fields.year = 2000 + value,
+            'y' => fields.year = value,
+            'M' => fields.month = value,
+            'd' => fields.day = value,
+            'H' => fields.hour = value,
+            'm' => fields.minute = value,
+            's' => fields.second = value,
+            _ => fields.millis = value,
+        }
+    }
+
+    if !scanner.at_end() {
+        return Err(scanner.unexpected());
+    }
+    fields.to_instant()
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use crate::{
+    datetime,
+    evaluator::RuntimeError,
+    scanner::{MAX_DEPTH, Scanner},
+    variable_type::DataType,
+};
+
+// There is no set type, so `#{...}` is read as a vector. `#inst "..."` is read
+// as an instant, other tagged values as the value that follows the tag, and
+// characters as one-character strings.
+
+pub fn read(text: &str) -> Result<DataType, RuntimeError> {
+    let mut scanner = Scanner::new(text);
+
+    skip_whitespace(&mut scanner)?;
+    let Some(value) = read_form(&mut scanner)? else {
+        return Err(scanner.unexpected());
+    };
+
+    skip_whitespace(&mut scanner)?;
+    if !scanner.at_end() {
+        return Err(scanner.unexpected());
+    }
+    Ok(value)
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || ".*+!-_?$%&=<>/:#'".contains(c)
+}
+
+/// Skips whitespace, commas, comments and forms discarded with `#_`.
+fn skip_whitespace(scanner: &mut Scanner) -> Result<(), RuntimeError> {
+    loop {
+        scanner.take_while(|c| c.is_whitespace() || c == ',');
+
+        match (scanner.peek(), scanner.peek_at(1)) {
+            (Some(';'), _) => {
+                scanner.take_while(|c| c != '\n');
+            }
+            (Some('#'), Some('_')) => {
+                scanner.next();
+                scanner.next();
+                skip_whitespace(scanner)?;
+                if read_form(scanner)?.is_none() {
+                    return Err(scanner.unexpected());
+                }
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Reads the next form, or returns `None` at a closing delimiter or the end of
+/// the input.
+fn read_form(scanner: &mut Scanner) -> Result<Option<DataType>, RuntimeError> {
+    let value = match scanner.peek() {
+        None | Some(')' | ']' | '}') => return Ok(None),
+        Some('(') => DataType::List(read_sequence(scanner, '(', ')')?, None),
+        Some('[') => DataType::Vector(read_sequence(scanner, '[', ']')?, None),
+        Some('{') => read_map(scanner)?,
+        Some('"') => read_string(scanner)?,
+        Some('\\') => read_character(scanner)?,
+        Some('#') => read_dispatch(scanner)?,
+        Some(':') => {
+            scanner.next();
+            let name = scanner.take_while(is_symbol_char);
+            if name.is_empty() {
+                return Err(scanner.error("Expected a keyword name"));
+            }
+            DataType::Keyword(name)
+        }
+        Some(c) if c.is_ascii_digit() => read_number(scanner)?,
+        Some('+' | '-') if scanner.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => {
+            read_number(scanner)?
+        }
+        Some(c) if is_symbol_char(c) => match scanner.take_while(is_symbol_char).as_str() {
+            "nil" => DataType::Nil(),
+            "true" => DataType::Bool(true),
+            "false" => DataType::Bool(false),
+            symbol => DataType::Symbol(symbol.to_string(), None),
+        },
+        Some(_) => return Err(scanner.unexpected()),
+    };
+
+    Ok(Some(value))
+}
+
+fn read_sequence(
+    scanner: &mut Scanner,
+    open: char,
+    close: char,
+) -> Result<Vec<DataType>, RuntimeError> {
+    scanner.enter()?;
+    scanner.expect(open)?;
+    let mut items = vec![];
+
+    loop {
+        skip_whitespace(scanner)?;
+        match read_form(scanner)? {
+            Some(item) => items.push(item),
+            None => {
+                scanner.expect(close)?;
+                scanner.leave();
+                return Ok(items);
+            }
+        }
+    }
+}
+
+fn read_map(scanner: &mut Scanner) -> Result<DataType, RuntimeError> {
+    let start = scanner.position();
+    let items = read_sequence(scanner, '{', '}')?;
+    if items.len() % 2 != 0 {
+        return Err(scanner.error_at(start, "Map literal has an odd number of forms"));
+    }
+
+    let dict: HashMap<String, DataType> = items
+        .chunks(2)
+        .map(|pair| (format!("{:?}", pair[0]), pair[1].clone()))
+        .collect();
+    Ok(DataType::Dictionary(dict, None))
+}
+
+fn read_dispatch(scanner: &mut Scanner) -> Result<DataType, RuntimeError> {
+    scanner.expect('#')?;
+
+    if scanner.peek() == Some('{') {
+        return Ok(DataType::Vector(read_sequence(scanner, '{', '}')?, None));
+    }
+
+    let tag = scanner.take_while(is_symbol_char);
+    if tag.is_empty() {
+        return Err(scanner.unexpected());
+    }
+
+    skip_whitespace(scanner)?;
+    let start = scanner.position();
+    match read_form(scanner)? {
+        Some(DataType::String(ref text)) if tag == "inst" => match datetime::parse_iso(text) {
+            Ok(instant) => Ok(DataType::Instant(instant)),
+            Err(e) => Err(scanner.error_at(start, &e.msg)),
+        },
+        Some(value) => Ok(value),
+        None => Err(scanner.error(&format!("Expected a value after #{}", tag))),
+    }
+}
+
+fn read_string(scanner: &mut Scanner) -> Result<DataType, RuntimeError> {
+    scanner.expect('"')?;
+    let mut string = String::new();
+
+    loop {
+        match scanner.next() {
+            Some('"') => return Ok(DataType::String(string)),
+            Some('\\') => match scanner.next() {
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some('n') => string.push('\n'),
+                Some('r') => string.push('\r'),
+                Some('t') => string.push('\t'),
+                _ => return Err(scanner.error("Invalid escape sequence")),
+            },
+            Some(c) => string.push(c),
+            None => return Err(scanner.error("Unterminated string")),
+        }
+    }
+}
+
+fn read_character(scanner: &mut Scanner) -> Result<DataType, RuntimeError> {
+    scanner.expect('\\')?;
+    let start = scanner.position();
+
+    let Some(first) = scanner.next() else {
+        return Err(scanner.error("Expected a character"));
+    };
+    let rest = scanner.take_while(|c| c.is_alphanumeric());
+
+    let c = match (first, rest.as_str()) {
+        (c, "") => c,
+        ('n', "ewline") => '\n',
+        ('s', "pace") => ' ',
+        ('t', "ab") => '\t',
+        ('r', "eturn") => '\r',
+        _ => {
+            return Err(scanner.error_at(start, &format!("Unknown character \\{}{}", first, rest)));
+        }
+    };
+    Ok(DataType::String(c.to_string()))
+}
+
+fn read_number(scanner: &mut Scanner) -> Result<DataType, RuntimeError> {
+    let start = scanner.position();
+    let mut text = scanner.take_while(|c| c.is_ascii_alphanumeric() || ".+-".contains(c));
+
+    let is_float = if let Some(float) = text.strip_suffix('M') {
+        text = float.to_string();
+        true
+    } else if let Some(integer) = text.strip_suffix('N') {
+        text = integer.to_string();
+        false
+    } else {
+        text.contains(['.', 'e', 'E'])
+    };
+
+    let value = if is_float {
+        text.parse().ok().map(DataType::Float)
+    } else {
+        text.parse().ok().map(DataType::Integer)
+    };
+    value.ok_or_else(|| scanner.error_at(start, &format!("Invalid number {}", text)))
+}
+
+/// Writes `value` as EDN, putting each element of a collection on its own
+/// line indented by `indent` spaces per level when given.
+pub fn write(value: &DataType, indent: Option<usize>) -> Result<String, RuntimeError> {
+    let mut out = String::new();
+    write_value(value, indent, 0, &mut out)?;
+    Ok(out)
+}
+
+fn write_string(string: &str, out: &mut String) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_items(
+    open: &str,
+    close: char,
+    items: &[String],
+    indent: Option<usize>,
+    depth: usize,
+    out: &mut String,
+) {
+    out.push_str(open);
+    for (i, item) in items.iter().enumerate() {
+        match indent {
+            Some(indent) => {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent * (depth + 1)));
+            }
+            None if i > 0 => out.push(' '),
+            None => {}
+        }
+        out.push_str(item);
+    }
+    if let Some(indent) = indent
+        && !items.is_empty()
+    {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent * depth));
+    }
+    out.push(close);
+}
+
+fn write_value(
+    value: &DataType,
+    indent: Option<usize>,
+    depth: usize,
+    out: &mut String,
+) -> Result<(), RuntimeError> {
+    if depth > MAX_DEPTH {
+        return Err(RuntimeError {
+            msg: "Collections are nested too deeply to write as EDN".to_string(),
+        });
+    }
+
+    let write_all = |items: &[DataType]| -> Result<Vec<String>, RuntimeError> {
+        items
+            .iter()
+            .map(|item| {
+                let mut out = String::new();
+                write_value(item, indent, depth + 1, &mut out)?;
+                Ok(out)
+            })
+            .collect()
+    };
+
+    match value {
+        DataType::Nil() => out.push_str("nil"),
+        DataType::Bool(value) => out.push_str(&value.to_string()),
+        DataType::Integer(num) => out.push_str(&num.to_string()),
+        DataType::Float(num) if num.is_finite() => out.push_str(&format!("{:?}", num)),
+        DataType::String(string) => write_string(string, out),
+        DataType::Keyword(name) => out.push_str(&format!(":{}", name)),
+        DataType::Symbol(name, _) => out.push_str(name),
+        DataType::Instant(_) => out.push_str(&format!("{:?}", value)),
+        DataType::List(items, _) => write_items("(", ')', &write_all(items)?, indent, depth, out),
+        DataType::Vector(items, _) => write_items("[", ']', &write_all(items)?, indent, depth, out),
+
+        DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => {
+            let open = match value {
+                DataType::Record(record_type, ..) => format!("#{}{{", record_type.name),
+                _ => "{".to_string(),
+            };
+
+            let mut keys = dict.keys().collect::<Vec<_>>();
+            keys.sort();
+            let mut entries = vec![];
+            for key in keys {
+                let mut entry = format!("{} ", key);
+                write_value(&dict[key], indent, depth + 1, &mut entry)?;
+                entries.push(entry);
+            }
+
+            write_items(&open, '}', &entries, indent, depth, out);
+        }
+
+        _ => {
+            return Err(RuntimeError {
+                msg: format!("Can't write {:?} as EDN", value),
+            });
+        }
+    }
+
+    Ok(())
+}
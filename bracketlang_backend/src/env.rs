@@ -1,28 +1,31 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fs;
+use std::io::{BufRead, Write};
 use std::rc::Rc;
 
+use crate::atom::{self, AtomState};
+use crate::capability::Capability;
+use crate::context::{Context, NativeFn};
+use crate::csv::{CsvOptions, CsvRows};
 use crate::evaluator::RuntimeError;
+use crate::io::Handle as FileHandle;
+use crate::{csv, datetime, edn, json};
 
-#[cfg(not(target_arch = "wasm32"))]
 use crate::read;
-#[cfg(target_arch = "wasm32")]
-use crate::{js_print, prompt, read};
-#[cfg(not(target_arch = "wasm32"))]
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::variable_type::DataType;
 use crate::variable_type::DataType::*;
+use crate::variable_type::{
+    DataType, Dispatch, Generator as GeneratorHandle, MultiFn, RecordType as RecordTypeInfo,
+};
 
 pub struct CoreFunction {
     pub id: &'static str,
-    pub func: fn(&[DataType]) -> Result<DataType, RuntimeError>,
+    pub func: NativeFn,
 }
 
 macro_rules! type_check {
     ($a:pat) => {
-        |values: &[DataType]| match values.first() {
+        |_: &mut Context, values: &[DataType]| match values.first() {
             Some($a) => Ok(DataType::Bool(true)),
             None => Err(RuntimeError {
                 msg: "No arguments given to data type check".to_string(),
@@ -33,7 +36,7 @@ macro_rules! type_check {
 }
 pub const ADDITION: CoreFunction = CoreFunction {
     id: "+",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Integer(num1 + num2)),
@@ -56,7 +59,7 @@ pub const ADDITION: CoreFunction = CoreFunction {
 
 pub const MODULO: CoreFunction = CoreFunction {
     id: "%",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Integer(num1 % num2)),
@@ -75,7 +78,7 @@ pub const MODULO: CoreFunction = CoreFunction {
 
 pub const MULTIPLICATION: CoreFunction = CoreFunction {
     id: "*",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Integer(num1 * num2)),
@@ -96,7 +99,7 @@ pub const MULTIPLICATION: CoreFunction = CoreFunction {
 
 pub const SUBTRACTION: CoreFunction = CoreFunction {
     id: "-",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Integer(num1 - num2)),
@@ -117,7 +120,7 @@ pub const SUBTRACTION: CoreFunction = CoreFunction {
 
 pub const DIVISION: CoreFunction = CoreFunction {
     id: "/",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(_)), Some(Integer(0))) => Err(RuntimeError {
@@ -144,32 +147,11 @@ pub const DIVISION: CoreFunction = CoreFunction {
     },
 };
 
-#[cfg(not(target_arch = "wasm32"))]
-pub const PRINT: CoreFunction = CoreFunction {
-    id: "prn",
-    func: |values: &[DataType]| {
-        println!(
-            "{}",
-            values
-                .iter()
-                .map(|value| match value {
-                    DataType::String(string) => format!("{}", string),
-                    _ => format!("{:?}", value),
-                })
-                .collect::<Vec<_>>()
-                .join(" ")
-        );
-
-        Ok(DataType::Nil())
-    },
-};
-
-#[cfg(target_arch = "wasm32")]
 pub const PRINT: CoreFunction = CoreFunction {
     id: "prn",
-    func: |values: &[DataType]| {
-        js_print(&format!(
-            "{}",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        ctx.write(&format!(
+            "{}\n",
             values
                 .iter()
                 .map(|value| match value {
@@ -178,7 +160,7 @@ pub const PRINT: CoreFunction = CoreFunction {
                 })
                 .collect::<Vec<_>>()
                 .join(" ")
-        ));
+        ))?;
 
         Ok(DataType::Nil())
     },
@@ -186,40 +168,42 @@ pub const PRINT: CoreFunction = CoreFunction {
 
 pub const LIST: CoreFunction = CoreFunction {
     id: "list",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let mut children = vec![];
         for value in values {
             children.push(value.clone());
         }
-        Ok(DataType::List(children))
+        Ok(DataType::List(children, None))
     },
 };
 
 pub const VECTOR: CoreFunction = CoreFunction {
     id: "vector",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let mut children = vec![];
         for value in values {
             children.push(value.clone());
         }
-        Ok(DataType::Vector(children))
+        Ok(DataType::Vector(children, None))
     },
 };
 
 pub const CHECK_LIST: CoreFunction = CoreFunction {
     id: "list?",
-    func: type_check!(DataType::List(_)),
+    func: type_check!(DataType::List(_, _)),
 };
 
 pub const LIST_EMPTY: CoreFunction = CoreFunction {
     id: "empty?",
-    func: |values: &[DataType]| {
-        if let Some(DataType::List(children)) = values.first() {
+    func: |_: &mut Context, values: &[DataType]| {
+        if let Some(DataType::List(children, _)) = values.first() {
             if children.len() == 0 {
                 Ok(DataType::Bool(true))
             } else {
                 Ok(DataType::Bool(false))
             }
+        } else if let Some(DataType::Generator(generator)) = values.first() {
+            Ok(DataType::Bool(generator.peek(0)?.is_none()))
         } else {
             Err(RuntimeError {
                 msg: "No arguments given to empty?".to_string(),
@@ -230,8 +214,13 @@ pub const LIST_EMPTY: CoreFunction = CoreFunction {
 
 pub const LIST_LEN: CoreFunction = CoreFunction {
     id: "count",
-    func: |values: &[DataType]| {
-        if let Some(DataType::List(children)) = values.first() {
+    func: |_: &mut Context, values: &[DataType]| {
+        let children = match values.first() {
+            Some(DataType::Generator(generator)) => generator.collect()?,
+            Some(DataType::List(children, _)) => children.clone(),
+            _ => vec![],
+        };
+        if let Some(DataType::List(..) | DataType::Generator(_)) = values.first() {
             let length = match children.len().try_into() {
                 Ok(l) => l,
                 Err(_) => {
@@ -251,7 +240,7 @@ pub const LIST_LEN: CoreFunction = CoreFunction {
 
 pub const EQUALS: CoreFunction = CoreFunction {
     id: "=",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let (Some(var1), Some(var2)) = (values.get(0), values.get(1)) else {
             return Err(RuntimeError {
                 msg: "Not enough arguments passed to =".to_string(),
@@ -265,13 +254,15 @@ pub const EQUALS: CoreFunction = CoreFunction {
 // TODO: Write a macro for these
 pub const GREATER_THAN: CoreFunction = CoreFunction {
     id: ">",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Bool(num1 > num2)),
 
                 (Some(Float(num1)), Some(Float(num2))) => Ok(Bool(num1 > num2)),
 
+                (Some(Instant(time1)), Some(Instant(time2))) => Ok(Bool(time1 > time2)),
+
                 _ => Err(RuntimeError {
                     msg: "Incorrect types for subtraction!".to_string(),
                 }),
@@ -286,13 +277,15 @@ pub const GREATER_THAN: CoreFunction = CoreFunction {
 
 pub const LESS_THAN: CoreFunction = CoreFunction {
     id: "<",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Bool(num1 < num2)),
 
                 (Some(Float(num1)), Some(Float(num2))) => Ok(Bool(num1 < num2)),
 
+                (Some(Instant(time1)), Some(Instant(time2))) => Ok(Bool(time1 < time2)),
+
                 _ => Err(RuntimeError {
                     msg: "Incorrect types for subtraction!".to_string(),
                 }),
@@ -307,13 +300,15 @@ pub const LESS_THAN: CoreFunction = CoreFunction {
 
 pub const GREATER_THAN_OR_EQUALS: CoreFunction = CoreFunction {
     id: ">=",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Bool(num1 >= num2)),
 
                 (Some(Float(num1)), Some(Float(num2))) => Ok(Bool(num1 >= num2)),
 
+                (Some(Instant(time1)), Some(Instant(time2))) => Ok(Bool(time1 >= time2)),
+
                 _ => Err(RuntimeError {
                     msg: "Incorrect types for subtraction!".to_string(),
                 }),
@@ -328,13 +323,15 @@ pub const GREATER_THAN_OR_EQUALS: CoreFunction = CoreFunction {
 
 pub const LESS_THAN_OR_EQUALS: CoreFunction = CoreFunction {
     id: "<=",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 2 {
             match (values.get(0), values.get(1)) {
                 (Some(Integer(num1)), Some(Integer(num2))) => Ok(Bool(num1 <= num2)),
 
                 (Some(Float(num1)), Some(Float(num2))) => Ok(Bool(num1 <= num2)),
 
+                (Some(Instant(time1)), Some(Instant(time2))) => Ok(Bool(time1 <= time2)),
+
                 _ => Err(RuntimeError {
                     msg: "Incorrect types for subtraction!".to_string(),
                 }),
@@ -349,7 +346,7 @@ pub const LESS_THAN_OR_EQUALS: CoreFunction = CoreFunction {
 
 pub const READ_STR: CoreFunction = CoreFunction {
     id: "read-string",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         if values.len() == 1 {
             match values.get(0) {
                 Some(String(str)) => match read(str.to_string()) {
@@ -371,10 +368,10 @@ pub const READ_STR: CoreFunction = CoreFunction {
 
 pub const SLURP: CoreFunction = CoreFunction {
     id: "slurp",
-    func: |values: &[DataType]| {
+    func: |ctx: &mut Context, values: &[DataType]| {
         if values.len() == 1 {
             match values.get(0) {
-                Some(String(path)) => match fs::read_to_string(path) {
+                Some(String(path)) => match ctx.filesystem().read_to_string(path) {
                     Ok(file) => Ok(DataType::String(file)),
                     Err(e) => Err(RuntimeError {
                         msg: format!("Couldn't load file: {}", e.to_string()),
@@ -395,7 +392,7 @@ pub const SLURP: CoreFunction = CoreFunction {
 
 pub const STR: CoreFunction = CoreFunction {
     id: "str",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let mut end_str = "".to_string();
         for value in values {
             let DataType::String(str) = value else {
@@ -411,16 +408,33 @@ pub const STR: CoreFunction = CoreFunction {
     },
 };
 
+/// `(atom value {:validator f})`
 pub const ATOM: CoreFunction = CoreFunction {
     id: "atom",
-    func: |values: &[DataType]| {
+    func: |ctx: &mut Context, values: &[DataType]| {
         let Some(val) = values.first() else {
             return Err(RuntimeError {
                 msg: "Not enough arguments to atom".to_string(),
             });
         };
 
-        Ok(DataType::Atom(Rc::new(RefCell::new(val.clone()))))
+        let validator = match values.get(1) {
+            None => None,
+            Some(Dictionary(options, _)) => match options.get(":validator") {
+                None | Some(Nil()) => None,
+                Some(validator) => Some(validator.clone()),
+            },
+            Some(_) => {
+                return Err(RuntimeError {
+                    msg: "Options should be a dict".to_string(),
+                });
+            }
+        };
+        atom::validate(validator.as_ref(), val, &ctx.env())?;
+
+        let state = AtomState::new(val.clone());
+        state.validator.replace(validator);
+        Ok(DataType::Atom(Rc::new(state)))
     },
 };
 
@@ -431,75 +445,137 @@ pub const CHECK_ATOM: CoreFunction = CoreFunction {
 
 pub const DEREF: CoreFunction = CoreFunction {
     id: "deref",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let Some(Atom(atom)) = values.first() else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments to deref".to_string(),
             });
         };
 
-        Ok((**atom).borrow().clone())
+        Ok(atom.get())
     },
 };
 
 pub const RESET_ATOM: CoreFunction = CoreFunction {
     id: "reset!",
-    func: |values: &[DataType]| {
+    func: |ctx: &mut Context, values: &[DataType]| {
         let Some(Atom(atom)) = values.first() else {
             return Err(RuntimeError {
-                msg: "Incorrect arguments to deref".to_string(),
+                msg: "Incorrect arguments to reset!".to_string(),
             });
         };
 
         let Some(val) = values.get(1) else {
             return Err(RuntimeError {
-                msg: "Incorrect arguments to deref".to_string(),
+                msg: "Incorrect arguments to reset!".to_string(),
             });
         };
 
-        atom.replace(val.clone());
+        atom::set(atom, val.clone(), &ctx.env())?;
 
         Ok(val.clone())
     },
 };
 
-pub const SWAP_ATOM: CoreFunction = CoreFunction {
-    id: "swap!",
-    func: |values: &[DataType]| {
-        let Some(Atom(atom_value)) = values.first() else {
+/// `(compare-and-set! atom old new)` sets the atom to `new` only if it still
+/// holds `old`, returning whether it did.
+pub const COMPARE_AND_SET: CoreFunction = CoreFunction {
+    id: "compare-and-set!",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let [Atom(atom), old, new] = values else {
             return Err(RuntimeError {
-                msg: "Incorrect arguments to deref".to_string(),
+                msg: "Incorrect arguments to compare-and-set!".to_string(),
             });
         };
 
-        let Some(Closure(func)) = values.get(1) else {
+        if atom.get() != *old {
+            return Ok(Bool(false));
+        }
+        atom::set(atom, new.clone(), &ctx.env())?;
+        Ok(Bool(true))
+    },
+};
+
+/// `(add-watch atom key f)`, replacing any watch already added under `key`.
+pub const ADD_WATCH: CoreFunction = CoreFunction {
+    id: "add-watch",
+    func: |_: &mut Context, values: &[DataType]| {
+        let [Atom(atom), key, function] = values else {
             return Err(RuntimeError {
-                msg: "Incorrect arguments to deref".to_string(),
+                msg: "Incorrect arguments to add-watch".to_string(),
             });
         };
 
-        let mut args: Vec<DataType> = vec![];
-        args.push(atom_value.borrow().clone());
-        for value in &values[2..] {
-            args.push(value.clone());
+        let mut watches = atom.watches.borrow_mut();
+        match watches.iter_mut().find(|(watch_key, _)| watch_key == key) {
+            Some(watch) => watch.1 = function.clone(),
+            None => watches.push((key.clone(), function.clone())),
         }
+        Ok(values[0].clone())
+    },
+};
+
+pub const REMOVE_WATCH: CoreFunction = CoreFunction {
+    id: "remove-watch",
+    func: |_: &mut Context, values: &[DataType]| {
+        let [Atom(atom), key] = values else {
+            return Err(RuntimeError {
+                msg: "Incorrect arguments to remove-watch".to_string(),
+            });
+        };
 
-        atom_value.replace(func.func(&args[0..])?);
+        atom.watches
+            .borrow_mut()
+            .retain(|(watch_key, _)| watch_key != key);
+        Ok(values[0].clone())
+    },
+};
+
+/// `(set-validator! atom f)`, or `nil` to remove it. The atom's current value
+/// has to pass the new validator.
+pub const SET_VALIDATOR: CoreFunction = CoreFunction {
+    id: "set-validator!",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let [Atom(atom), validator] = values else {
+            return Err(RuntimeError {
+                msg: "Incorrect arguments to set-validator!".to_string(),
+            });
+        };
+
+        let validator = match validator {
+            Nil() => None,
+            validator => Some(validator.clone()),
+        };
+        atom::validate(validator.as_ref(), &atom.get(), &ctx.env())?;
 
-        Ok(atom_value.borrow().clone())
+        atom.validator.replace(validator);
+        Ok(Nil())
+    },
+};
+
+pub const GET_VALIDATOR: CoreFunction = CoreFunction {
+    id: "get-validator",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(Atom(atom)) = values.first() else {
+            return Err(RuntimeError {
+                msg: "Incorrect arguments to get-validator".to_string(),
+            });
+        };
+
+        Ok(atom.validator.borrow().clone().unwrap_or(Nil()))
     },
 };
 
 pub const CONS: CoreFunction = CoreFunction {
     id: "cons",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let Some(value) = values.first() else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments to cons".to_string(),
             });
         };
 
-        let Some(DataType::List(list) | DataType::Vector(list)) = values.get(1) else {
+        let Some(DataType::List(list, _) | DataType::Vector(list, _)) = values.get(1) else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments to cons".to_string(),
             });
@@ -509,34 +585,46 @@ pub const CONS: CoreFunction = CoreFunction {
         new_list.push(value.clone());
         new_list.extend(list.iter().cloned());
 
-        Ok(DataType::List(new_list))
+        Ok(DataType::List(new_list, None))
     },
 };
 
 pub const CONCAT: CoreFunction = CoreFunction {
     id: "concat",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let mut result = vec![];
         for list in values {
-            let (DataType::List(list) | DataType::Vector(list)) = list else {
-                return Err(RuntimeError {
-                    msg: "Incorrect arguments to concat".to_string(),
-                });
-            };
-
-            for value in list {
-                result.push(value.clone());
+            match list {
+                DataType::List(list, _) | DataType::Vector(list, _) => {
+                    result.extend(list.iter().cloned());
+                }
+                DataType::Generator(generator) => result.extend(generator.collect()?),
+                _ => {
+                    return Err(RuntimeError {
+                        msg: "Incorrect arguments to concat".to_string(),
+                    });
+                }
             }
         }
 
-        Ok(DataType::List(result))
+        Ok(DataType::List(result, None))
     },
 };
 
 pub const NTH: CoreFunction = CoreFunction {
     id: "nth",
-    func: |values: &[DataType]| {
-        let (Some(List(list) | Vector(list)), Some(Integer(idx))) = (values.get(0), values.get(1))
+    func: |_: &mut Context, values: &[DataType]| {
+        if let (Some(Generator(generator)), Some(Integer(idx))) = (values.first(), values.get(1)) {
+            return match generator.peek(*idx as usize)? {
+                Some(v) => Ok(v),
+                None => Err(RuntimeError {
+                    msg: "Index out of bounds".to_string(),
+                }),
+            };
+        }
+
+        let (Some(List(list, _) | Vector(list, _)), Some(Integer(idx))) =
+            (values.get(0), values.get(1))
         else {
             return Err(RuntimeError {
                 msg: "Wrong arguments for nth".to_string(),
@@ -556,8 +644,17 @@ pub const NTH: CoreFunction = CoreFunction {
 
 pub const FIRST: CoreFunction = CoreFunction {
     id: "first",
-    func: |values: &[DataType]| {
-        let Some(List(list) | Vector(list)) = values.get(0) else {
+    func: |_: &mut Context, values: &[DataType]| {
+        if let Some(Generator(generator)) = values.first() {
+            return match generator.peek(0)? {
+                Some(v) => Ok(v),
+                None => Err(RuntimeError {
+                    msg: "Index out of bounds".to_string(),
+                }),
+            };
+        }
+
+        let Some(List(list, _) | Vector(list, _)) = values.get(0) else {
             return Err(RuntimeError {
                 msg: "Wrong arguments for first".to_string(),
             });
@@ -576,20 +673,24 @@ pub const FIRST: CoreFunction = CoreFunction {
 
 pub const REST: CoreFunction = CoreFunction {
     id: "rest",
-    func: |values: &[DataType]| {
-        let Some(List(list) | Vector(list)) = values.get(0) else {
+    func: |_: &mut Context, values: &[DataType]| {
+        if let Some(Generator(generator)) = values.first() {
+            return Ok(Generator(generator.rest()));
+        }
+
+        let Some(List(list, _) | Vector(list, _)) = values.get(0) else {
             return Err(RuntimeError {
                 msg: "Wrong arguments for rest".to_string(),
             });
         };
 
-        return Ok(DataType::List(list[1..].iter().cloned().collect()));
+        return Ok(DataType::List(list[1..].iter().cloned().collect(), None));
     },
 };
 
 pub const THROW: CoreFunction = CoreFunction {
     id: "throw",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let Some(String(string)) = values.get(0) else {
             return Err(RuntimeError {
                 msg: "Wrong arguments for throw".to_string(),
@@ -602,70 +703,6 @@ pub const THROW: CoreFunction = CoreFunction {
     },
 };
 
-pub const APPLY: CoreFunction = CoreFunction {
-    id: "apply",
-    func: |values: &[DataType]| {
-        if let Some(Closure(closure)) = values.get(0) {
-            let mut args = vec![];
-            for val in &values[1..] {
-                match val {
-                    List(data_types) | Vector(data_types) => {
-                        for val in data_types {
-                            args.push(val.clone());
-                        }
-                    }
-                    _ => args.push(val.clone()),
-                }
-            }
-            closure.func(&args)
-        } else if let Some(NativeFunction(func)) = values.get(0) {
-            let mut args = vec![];
-            for val in &values[1..] {
-                match val {
-                    List(data_types) | Vector(data_types) => {
-                        for val in data_types {
-                            args.push(val.clone());
-                        }
-                    }
-                    _ => args.push(val.clone()),
-                }
-            }
-            func.1(&args)
-        } else {
-            return Err(RuntimeError {
-                msg: "Wrong arguments for apply".to_string(),
-            });
-        }
-    },
-};
-
-pub const MAP: CoreFunction = CoreFunction {
-    id: "map",
-    func: |values: &[DataType]| {
-        if let (Some(Closure(closure)), Some(List(list) | Vector(list))) =
-            (values.get(0), values.get(1))
-        {
-            let mut result = vec![];
-            for val in list {
-                result.push(closure.func(&[val.clone()])?);
-            }
-            Ok(DataType::List(result))
-        } else if let (Some(NativeFunction(closure)), Some(List(list) | Vector(list))) =
-            (values.get(0), values.get(1))
-        {
-            let mut result = vec![];
-            for val in list {
-                result.push(closure.1(&[val.clone()])?);
-            }
-            Ok(DataType::List(result))
-        } else {
-            return Err(RuntimeError {
-                msg: "Wrong arguments for map".to_string(),
-            });
-        }
-    },
-};
-
 pub const CHECK_NIL: CoreFunction = CoreFunction {
     id: "nil?",
     func: type_check!(DataType::Nil()),
@@ -683,22 +720,22 @@ pub const CHECK_FALSE: CoreFunction = CoreFunction {
 
 pub const CHECK_SYMBOL: CoreFunction = CoreFunction {
     id: "symbol?",
-    func: type_check!(DataType::Symbol(_)),
+    func: type_check!(DataType::Symbol(_, _)),
 };
 
 pub const CHECK_VECTOR: CoreFunction = CoreFunction {
     id: "vector?",
-    func: type_check!(DataType::Vector(_)),
+    func: type_check!(DataType::Vector(_, _)),
 };
 
 pub const CHECK_SEQUENTIAL: CoreFunction = CoreFunction {
     id: "sequential?",
-    func: type_check!(DataType::Vector(_) | DataType::List(_)),
+    func: type_check!(DataType::Vector(_, _) | DataType::List(_, _) | DataType::Generator(_)),
 };
 
 pub const CHECK_DICTIONARY: CoreFunction = CoreFunction {
     id: "dict?",
-    func: type_check!(DataType::Dictionary(_)),
+    func: type_check!(DataType::Dictionary(_, _)),
 };
 
 pub const CHECK_STR: CoreFunction = CoreFunction {
@@ -718,12 +755,18 @@ pub const CHECK_FLOAT: CoreFunction = CoreFunction {
 
 pub const CHECK_FN: CoreFunction = CoreFunction {
     id: "func?",
-    func: type_check!(DataType::Closure(_) | DataType::NativeFunction(_)),
+    func: type_check!(
+        DataType::Closure(_)
+            | DataType::NativeFunction(_)
+            | DataType::Builtin(_)
+            | DataType::Continuation(_)
+            | DataType::MultiFn(_)
+    ),
 };
 
 pub const CHECK_MACRO: CoreFunction = CoreFunction {
     id: "macro?",
-    func: |values: &[DataType]| match values.first() {
+    func: |_: &mut Context, values: &[DataType]| match values.first() {
         Some(DataType::Closure(closure)) if closure.is_macro => Ok(DataType::Bool(true)),
         None => Err(RuntimeError {
             msg: "No arguments given to data type check".to_string(),
@@ -734,20 +777,81 @@ pub const CHECK_MACRO: CoreFunction = CoreFunction {
 
 pub const SYMBOL: CoreFunction = CoreFunction {
     id: "symbol",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let Some(String(val)) = values.first() else {
             return Err(RuntimeError {
                 msg: "Not enough arguments to symbol".to_string(),
             });
         };
 
-        Ok(DataType::Symbol(val.clone()))
+        Ok(DataType::Symbol(val.clone(), None))
+    },
+};
+
+pub const KEYWORD: CoreFunction = CoreFunction {
+    id: "keyword",
+    func: |_: &mut Context, values: &[DataType]| match values.first() {
+        Some(String(val)) => Ok(DataType::Keyword(val.clone())),
+        Some(Keyword(val)) => Ok(DataType::Keyword(val.clone())),
+        _ => Err(RuntimeError {
+            msg: "Not enough arguments to keyword".to_string(),
+        }),
+    },
+};
+
+pub const CHECK_KEYWORD: CoreFunction = CoreFunction {
+    id: "keyword?",
+    func: type_check!(DataType::Keyword(_)),
+};
+
+pub const META: CoreFunction = CoreFunction {
+    id: "meta",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(value) = values.first() else {
+            return Err(RuntimeError {
+                msg: "Not enough arguments to meta".to_string(),
+            });
+        };
+
+        Ok(value.meta())
+    },
+};
+
+pub const WITH_META: CoreFunction = CoreFunction {
+    id: "with-meta",
+    func: |_: &mut Context, values: &[DataType]| {
+        let (Some(value), Some(meta)) = (values.first(), values.get(1)) else {
+            return Err(RuntimeError {
+                msg: "Incorrect arguments to with-meta".to_string(),
+            });
+        };
+
+        value.with_meta(meta.clone())
+    },
+};
+
+pub const VARY_META: CoreFunction = CoreFunction {
+    id: "vary-meta",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let (Some(value), Some(function)) = (values.first(), values.get(1)) else {
+            return Err(RuntimeError {
+                msg: "Incorrect arguments to vary-meta".to_string(),
+            });
+        };
+
+        let meta = match value.meta() {
+            Nil() => Dictionary(HashMap::new(), None),
+            meta => meta,
+        };
+        let mut args = vec![meta];
+        args.extend(values[2..].iter().cloned());
+        value.with_meta(ctx.call(function, &args)?)
     },
 };
 
 pub const DICTIONARY: CoreFunction = CoreFunction {
     id: "dict",
-    func: |values: &[DataType]| {
+    func: |_: &mut Context, values: &[DataType]| {
         let mut i = 0;
         let mut result = HashMap::new();
 
@@ -764,19 +868,19 @@ pub const DICTIONARY: CoreFunction = CoreFunction {
             i += 2;
         }
 
-        Ok(Dictionary(result))
+        Ok(Dictionary(result, None))
     },
 };
 
 pub const ASSOC: CoreFunction = CoreFunction {
     id: "assoc",
-    func: |values: &[DataType]| {
-        let Some(Dictionary(dict)) = values.first() else {
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(Dictionary(dict, _) | Record(_, dict, _)) = values.first() else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments for assoc".to_string(),
             });
         };
-        let mut i = 0;
+        let mut i = 1;
         let mut result = dict.clone();
 
         loop {
@@ -792,19 +896,22 @@ pub const ASSOC: CoreFunction = CoreFunction {
             i += 2;
         }
 
-        Ok(Dictionary(result))
+        match values.first() {
+            Some(Record(record_type, ..)) => Ok(Record(record_type.clone(), result, None)),
+            _ => Ok(Dictionary(result, None)),
+        }
     },
 };
 
 pub const DISSOC: CoreFunction = CoreFunction {
     id: "dissoc",
-    func: |values: &[DataType]| {
-        let Some(Dictionary(dict)) = values.first() else {
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(Dictionary(dict, _) | Record(_, dict, _)) = values.first() else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments for dissoc".to_string(),
             });
         };
-        let mut i = 0;
+        let mut i = 1;
         let mut result = dict.clone();
 
         loop {
@@ -816,14 +923,142 @@ pub const DISSOC: CoreFunction = CoreFunction {
             i += 1;
         }
 
-        Ok(Dictionary(result))
+        // A record missing one of its fields is just a dictionary
+        match values.first() {
+            Some(Record(record_type, ..))
+                if record_type
+                    .fields
+                    .iter()
+                    .all(|field| result.contains_key(field)) =>
+            {
+                Ok(Record(record_type.clone(), result, None))
+            }
+            _ => Ok(Dictionary(result, None)),
+        }
+    },
+};
+
+/// `(transient coll)`, a copy of a vector or dict that `conj!` and `assoc!`
+/// change in place instead of copying it each time.
+pub const TRANSIENT: CoreFunction = CoreFunction {
+    id: "transient",
+    func: |_: &mut Context, values: &[DataType]| match values.first() {
+        Some(coll @ (Vector(..) | Dictionary(..))) => {
+            Ok(Transient(Rc::new(RefCell::new(Some(coll.clone())))))
+        }
+        _ => Err(RuntimeError {
+            msg: "transient expects a vector or dict".to_string(),
+        }),
+    },
+};
+
+/// Runs `update` on the collection inside a transient, which fails once
+/// `persistent!` has taken it.
+fn update_transient(
+    name: &str,
+    values: &[DataType],
+    update: impl FnOnce(&mut DataType, &[DataType]) -> Result<(), RuntimeError>,
+) -> Result<DataType, RuntimeError> {
+    let Some(Transient(transient)) = values.first() else {
+        return Err(RuntimeError {
+            msg: format!("{} expects a transient", name),
+        });
+    };
+
+    match transient.borrow_mut().as_mut() {
+        Some(coll) => update(coll, &values[1..])?,
+        None => {
+            return Err(RuntimeError {
+                msg: format!("{} called on a transient after persistent!", name),
+            });
+        }
+    }
+    Ok(values[0].clone())
+}
+
+/// `(conj! transient item ...)`. Items added to a dict are `[key value]`.
+pub const CONJ_TRANSIENT: CoreFunction = CoreFunction {
+    id: "conj!",
+    func: |_: &mut Context, values: &[DataType]| {
+        update_transient("conj!", values, |coll, items| {
+            for item in items {
+                match (&mut *coll, item) {
+                    (Vector(vector, _), item) => vector.push(item.clone()),
+                    (Dictionary(dict, _), Vector(pair, _)) if pair.len() == 2 => {
+                        dict.insert(format!("{:?}", pair[0]), pair[1].clone());
+                    }
+                    _ => {
+                        return Err(RuntimeError {
+                            msg: format!("Can't conj! {:?} onto a dict", item),
+                        });
+                    }
+                }
+            }
+            Ok(())
+        })
+    },
+};
+
+/// `(assoc! transient key value ...)`. A vector's keys are indexes, where its
+/// length appends.
+pub const ASSOC_TRANSIENT: CoreFunction = CoreFunction {
+    id: "assoc!",
+    func: |_: &mut Context, values: &[DataType]| {
+        update_transient("assoc!", values, |coll, pairs| {
+            if pairs.len() % 2 != 0 {
+                return Err(RuntimeError {
+                    msg: "No value to match key in assoc!".to_string(),
+                });
+            }
+
+            for pair in pairs.chunks(2) {
+                match (&mut *coll, &pair[0]) {
+                    (Dictionary(dict, _), key) => {
+                        dict.insert(format!("{:?}", key), pair[1].clone());
+                    }
+                    (Vector(vector, _), Integer(index))
+                        if *index >= 0 && *index as usize <= vector.len() =>
+                    {
+                        match vector.get_mut(*index as usize) {
+                            Some(item) => *item = pair[1].clone(),
+                            None => vector.push(pair[1].clone()),
+                        }
+                    }
+                    (_, key) => {
+                        return Err(RuntimeError {
+                            msg: format!("Index {:?} is out of bounds for assoc!", key),
+                        });
+                    }
+                }
+            }
+            Ok(())
+        })
+    },
+};
+
+/// `(persistent! transient)`, the finished collection. The transient can't
+/// be changed afterwards.
+pub const PERSISTENT: CoreFunction = CoreFunction {
+    id: "persistent!",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(Transient(transient)) = values.first() else {
+            return Err(RuntimeError {
+                msg: "persistent! expects a transient".to_string(),
+            });
+        };
+
+        transient.borrow_mut().take().ok_or_else(|| RuntimeError {
+            msg: "persistent! called twice on a transient".to_string(),
+        })
     },
 };
 
 pub const GET: CoreFunction = CoreFunction {
     id: "get",
-    func: |values: &[DataType]| {
-        let (Some(Dictionary(dict)), Some(key)) = (values.get(0), values.get(1)) else {
+    func: |_: &mut Context, values: &[DataType]| {
+        let (Some(Dictionary(dict, _) | Record(_, dict, _)), Some(key)) =
+            (values.get(0), values.get(1))
+        else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments for get".to_string(),
             });
@@ -839,8 +1074,10 @@ pub const GET: CoreFunction = CoreFunction {
 
 pub const CONTAINS: CoreFunction = CoreFunction {
     id: "contains",
-    func: |values: &[DataType]| {
-        let (Some(Dictionary(dict)), Some(key)) = (values.get(0), values.get(1)) else {
+    func: |_: &mut Context, values: &[DataType]| {
+        let (Some(Dictionary(dict, _) | Record(_, dict, _)), Some(key)) =
+            (values.get(0), values.get(1))
+        else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments for contains".to_string(),
             });
@@ -851,103 +1088,932 @@ pub const CONTAINS: CoreFunction = CoreFunction {
 
 pub const KEYS: CoreFunction = CoreFunction {
     id: "keys",
-    func: |values: &[DataType]| {
-        let Some(Dictionary(dict)) = values.get(0) else {
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(Dictionary(dict, _) | Record(_, dict, _)) = values.get(0) else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments for keys".to_string(),
             });
         };
-        Ok(List(dict.keys().cloned().map(|val| String(val)).collect()))
+        Ok(List(
+            dict.keys().cloned().map(|val| String(val)).collect(),
+            None,
+        ))
     },
 };
 
 pub const VALUES: CoreFunction = CoreFunction {
     id: "values",
-    func: |values: &[DataType]| {
-        let Some(Dictionary(dict)) = values.get(0) else {
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(Dictionary(dict, _) | Record(_, dict, _)) = values.get(0) else {
             return Err(RuntimeError {
                 msg: "Incorrect arguments for values".to_string(),
             });
         };
-        Ok(List(dict.values().cloned().collect()))
+        Ok(List(dict.values().cloned().collect(), None))
     },
 };
 
-#[cfg(not(target_arch = "wasm32"))]
 pub const TIME_MS: CoreFunction = CoreFunction {
     id: "time-ms",
-    func: |_values: &[DataType]| {
-        Ok(Integer(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis()
-                .try_into()
-                .unwrap(),
-        ))
+    func: |_: &mut Context, _values: &[DataType]| Ok(Integer(datetime::epoch_millis().into())),
+};
+
+pub const INPUT: CoreFunction = CoreFunction {
+    id: "input",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let prompt = match values.get(0) {
+            Some(DataType::String(string)) => string.as_str(),
+            _ => "",
+        };
+
+        Ok(DataType::String(ctx.read_line(prompt).unwrap_or_default()))
     },
 };
 
-#[cfg(target_arch = "wasm32")]
-pub const TIME_MS: CoreFunction = CoreFunction {
-    id: "time-ms",
-    func: |_values: &[DataType]| {
-        use crate::js_get_time;
+pub const GENERATOR: CoreFunction = CoreFunction {
+    id: "generator",
+    func: |_: &mut Context, values: &[DataType]| match values.first() {
+        Some(function @ (Closure(_) | NativeFunction(_) | Builtin(_))) => {
+            Ok(Generator(GeneratorHandle::new(function.clone())))
+        }
+        _ => Err(RuntimeError {
+            msg: "generator expects a function".to_string(),
+        }),
+    },
+};
+
+pub const CHECK_GENERATOR: CoreFunction = CoreFunction {
+    id: "generator?",
+    func: type_check!(DataType::Generator(_)),
+};
+
+pub const NEXT: CoreFunction = CoreFunction {
+    id: "next",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(Generator(generator)) = values.first() else {
+            return Err(RuntimeError {
+                msg: "Wrong arguments for next".to_string(),
+            });
+        };
 
-        Ok(Integer(js_get_time().into()))
+        Ok(generator.next()?.unwrap_or(DataType::Nil()))
     },
 };
 
-#[cfg(not(target_arch = "wasm32"))]
-pub const INPUT: CoreFunction = CoreFunction {
-    id: "input",
-    func: |values: &[DataType]| {
-        if let Some(DataType::String(string)) = values.get(0) {
-            use std::io::{Write, stdin, stdout};
-
-            print!("{}", string);
-            stdout()
-                .flush()
-                .expect("Flushing stdout should have worked.");
-            let mut user_input = std::string::String::new();
-
-            stdin()
-                .read_line(&mut user_input)
-                .expect("Didn't enter a correct string");
-            Ok(DataType::String(user_input))
-        } else {
-            use std::io::{Write, stdin, stdout};
+pub const TYPE: CoreFunction = CoreFunction {
+    id: "type",
+    func: |_: &mut Context, values: &[DataType]| match values.first() {
+        Some(value) => Ok(Keyword(value.type_name().to_string())),
+        None => Err(RuntimeError {
+            msg: "No arguments given to type".to_string(),
+        }),
+    },
+};
+
+pub const MULTI_FN: CoreFunction = CoreFunction {
+    id: "multi-fn",
+    func: |_: &mut Context, values: &[DataType]| {
+        let dispatch = match values {
+            [DataType::String(_)] => Dispatch::Type,
+            [DataType::String(_), dispatch] => Dispatch::Function(dispatch.clone()),
+            _ => {
+                return Err(RuntimeError {
+                    msg: "Wrong arguments for multi-fn".to_string(),
+                });
+            }
+        };
+        let Some(DataType::String(name)) = values.first() else {
+            unreachable!()
+        };
+
+        Ok(MultiFn(Rc::new(MultiFn {
+            name: name.clone(),
+            dispatch,
+            methods: RefCell::new(vec![]),
+        })))
+    },
+};
+
+pub const ADD_METHOD: CoreFunction = CoreFunction {
+    id: "add-method!",
+    func: |_: &mut Context, values: &[DataType]| {
+        let [MultiFn(multi), dispatch_value, method] = values else {
+            return Err(RuntimeError {
+                msg: "add-method! expects a multimethod, a dispatch value and a function"
+                    .to_string(),
+            });
+        };
+
+        multi.add_method(dispatch_value.clone(), method.clone());
+        Ok(MultiFn(multi.clone()))
+    },
+};
+
+pub const REMOVE_METHOD: CoreFunction = CoreFunction {
+    id: "remove-method",
+    func: |_: &mut Context, values: &[DataType]| {
+        let [MultiFn(multi), dispatch_value] = values else {
+            return Err(RuntimeError {
+                msg: "Wrong arguments for remove-method".to_string(),
+            });
+        };
+
+        multi
+            .methods
+            .borrow_mut()
+            .retain(|(value, _)| value != dispatch_value);
+        Ok(MultiFn(multi.clone()))
+    },
+};
 
-            stdout()
-                .flush()
-                .expect("Flushing stdout should have worked.");
-            let mut user_input = std::string::String::new();
+pub const GET_METHOD: CoreFunction = CoreFunction {
+    id: "get-method",
+    func: |_: &mut Context, values: &[DataType]| {
+        let [MultiFn(multi), dispatch_value] = values else {
+            return Err(RuntimeError {
+                msg: "Wrong arguments for get-method".to_string(),
+            });
+        };
 
-            stdin()
-                .read_line(&mut user_input)
-                .expect("Didn't enter a correct string");
-            Ok(DataType::String(user_input))
+        Ok(multi.get_method(dispatch_value).unwrap_or(Nil()))
+    },
+};
+
+pub const METHODS: CoreFunction = CoreFunction {
+    id: "methods",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(MultiFn(multi)) = values.first() else {
+            return Err(RuntimeError {
+                msg: "Wrong arguments for methods".to_string(),
+            });
+        };
+
+        let methods = multi
+            .methods
+            .borrow()
+            .iter()
+            .map(|(value, method)| (format!("{:?}", value), method.clone()))
+            .collect();
+        Ok(Dictionary(methods, None))
+    },
+};
+
+pub const PROTOCOL_METHOD: CoreFunction = CoreFunction {
+    id: "protocol-method",
+    func: |_: &mut Context, values: &[DataType]| {
+        let [Dictionary(protocol, _), method @ Keyword(_)] = values else {
+            return Err(RuntimeError {
+                msg: "Wrong arguments for protocol-method".to_string(),
+            });
+        };
+
+        let name = protocol.get(":name").cloned().unwrap_or(Nil());
+        match protocol.get(":methods") {
+            Some(Dictionary(methods, _)) => match methods.get(&format!("{:?}", method)) {
+                Some(multi @ MultiFn(_)) => Ok(multi.clone()),
+                _ => Err(RuntimeError {
+                    msg: format!("{:?} has no method {:?}", name, method),
+                }),
+            },
+            _ => Err(RuntimeError {
+                msg: format!("{:?} is not a protocol", name),
+            }),
         }
     },
 };
 
-#[cfg(target_arch = "wasm32")]
-pub const INPUT: CoreFunction = CoreFunction {
-    id: "input",
-    func: |values: &[DataType]| {
-        if let Some(DataType::String(string)) = values.get(0) {
-            let result = prompt(&string);
-            match result {
-                Some(res) => Ok(DataType::String(res)),
-                None => Ok(DataType::String("".to_string())),
+pub const RECORD_TYPE: CoreFunction = CoreFunction {
+    id: "record-type",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some((String(name), fields)) = values.split_first() else {
+            return Err(RuntimeError {
+                msg: "record-type expects a name and field keywords".to_string(),
+            });
+        };
+        let mut field_names = vec![];
+        for field in fields {
+            let Keyword(_) = field else {
+                return Err(RuntimeError {
+                    msg: format!("Record fields must be keywords, got {:?}", field),
+                });
+            };
+            field_names.push(format!("{:?}", field));
+        }
+
+        Ok(RecordType(Rc::new(RecordTypeInfo {
+            name: name.clone(),
+            fields: field_names,
+        })))
+    },
+};
+
+pub const MAKE_RECORD: CoreFunction = CoreFunction {
+    id: "make-record",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some((RecordType(record_type), field_values)) = values.split_first() else {
+            return Err(RuntimeError {
+                msg: "make-record expects a record type".to_string(),
+            });
+        };
+        if field_values.len() != record_type.fields.len() {
+            return Err(RuntimeError {
+                msg: format!(
+                    "{} expects {} fields, got {}",
+                    record_type.name,
+                    record_type.fields.len(),
+                    field_values.len()
+                ),
+            });
+        }
+
+        let fields = record_type
+            .fields
+            .iter()
+            .cloned()
+            .zip(field_values.iter().cloned())
+            .collect();
+        Ok(Record(record_type.clone(), fields, None))
+    },
+};
+
+pub const DICT_TO_RECORD: CoreFunction = CoreFunction {
+    id: "dict->record",
+    func: |_: &mut Context, values: &[DataType]| {
+        let [RecordType(record_type), Dictionary(dict, _)] = values else {
+            return Err(RuntimeError {
+                msg: "dict->record expects a record type and a dict".to_string(),
+            });
+        };
+
+        let mut fields = dict.clone();
+        for field in &record_type.fields {
+            fields.entry(field.clone()).or_insert(Nil());
+        }
+        Ok(Record(record_type.clone(), fields, None))
+    },
+};
+
+pub const CHECK_RECORD: CoreFunction = CoreFunction {
+    id: "record?",
+    func: |_: &mut Context, values: &[DataType]| match values {
+        [value] => Ok(Bool(matches!(value, Record(..)))),
+        [RecordType(record_type), value] => Ok(Bool(
+            matches!(value, Record(other, ..) if Rc::ptr_eq(record_type, other)),
+        )),
+        _ => Err(RuntimeError {
+            msg: "Wrong arguments for record?".to_string(),
+        }),
+    },
+};
+
+/// Reads `:pretty` and `:indent` from the options dictionary given to
+/// `json-stringify` and `edn-write`.
+fn indent_option(options: Option<&DataType>) -> Result<Option<usize>, RuntimeError> {
+    let options = match options {
+        None => return Ok(None),
+        Some(Dictionary(options, _)) => options,
+        Some(_) => {
+            return Err(RuntimeError {
+                msg: "Options should be a dict".to_string(),
+            });
+        }
+    };
+
+    match (options.get(":pretty"), options.get(":indent")) {
+        (_, Some(Integer(indent))) => match usize::try_from(*indent) {
+            Ok(indent) => Ok(Some(indent)),
+            Err(_) => Err(RuntimeError {
+                msg: format!("Invalid indent {}", indent),
+            }),
+        },
+        (Some(Bool(true)), _) => Ok(Some(2)),
+        _ => Ok(None),
+    }
+}
+
+pub const JSON_PARSE: CoreFunction = CoreFunction {
+    id: "json-parse",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(String(text)) = values.first() else {
+            return Err(RuntimeError {
+                msg: "json-parse expects a string".to_string(),
+            });
+        };
+        let keywordize = match values.get(1) {
+            Some(Dictionary(options, _)) => options.get(":keywordize") != Some(&Bool(false)),
+            _ => true,
+        };
+
+        json::parse(text, keywordize)
+    },
+};
+
+pub const JSON_STRINGIFY: CoreFunction = CoreFunction {
+    id: "json-stringify",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(value) = values.first() else {
+            return Err(RuntimeError {
+                msg: "No arguments given to json-stringify".to_string(),
+            });
+        };
+
+        Ok(String(json::stringify(
+            value,
+            indent_option(values.get(1))?,
+        )?))
+    },
+};
+
+pub const EDN_READ: CoreFunction = CoreFunction {
+    id: "edn-read",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(String(text)) = values.first() else {
+            return Err(RuntimeError {
+                msg: "edn-read expects a string".to_string(),
+            });
+        };
+
+        edn::read(text)
+    },
+};
+
+pub const EDN_WRITE: CoreFunction = CoreFunction {
+    id: "edn-write",
+    func: |_: &mut Context, values: &[DataType]| {
+        let Some(value) = values.first() else {
+            return Err(RuntimeError {
+                msg: "No arguments given to edn-write".to_string(),
+            });
+        };
+
+        Ok(String(edn::write(value, indent_option(values.get(1))?)?))
+    },
+};
+
+pub const CSV_READ: CoreFunction = CoreFunction {
+    id: "csv-read",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let Some(String(source)) = values.first() else {
+            return Err(RuntimeError {
+                msg: "csv-read expects a string".to_string(),
+            });
+        };
+        let options = CsvOptions::from_dict(values.get(1))?;
+        let from_file = match values.get(1) {
+            Some(Dictionary(options, _)) => options.get(":file") == Some(&Bool(true)),
+            _ => false,
+        };
+
+        if !from_file {
+            let text = std::io::Cursor::new(source.as_bytes());
+            let rows = CsvRows::new(text, options).collect::<Result<Vec<_>, _>>()?;
+            return Ok(Vector(rows, None));
+        }
+
+        // Files are read a row at a time, so they needn't fit in memory
+        ctx.require(Capability::Fs, "csv-read")?;
+        let file = ctx
+            .filesystem()
+            .open_read(source)
+            .map_err(|e| RuntimeError {
+                msg: format!("Couldn't load file: {}", e),
+            })?;
+        Ok(Generator(GeneratorHandle::streaming(CsvRows::new(
+            file, options,
+        ))))
+    },
+};
+
+pub const CSV_WRITE: CoreFunction = CoreFunction {
+    id: "csv-write",
+    func: |_: &mut Context, values: &[DataType]| {
+        let rows = match values.first() {
+            Some(List(rows, _) | Vector(rows, _)) => rows.clone(),
+            Some(Generator(generator)) => generator.collect()?,
+            _ => {
+                return Err(RuntimeError {
+                    msg: "csv-write expects a sequence of rows".to_string(),
+                });
+            }
+        };
+
+        Ok(String(csv::write(
+            &rows,
+            &CsvOptions::from_dict(values.get(1))?,
+        )?))
+    },
+};
+
+fn path_argument<'a>(name: &str, values: &'a [DataType]) -> Result<&'a str, RuntimeError> {
+    match values.first() {
+        Some(String(path)) => Ok(path),
+        _ => Err(RuntimeError {
+            msg: format!("{} expects a path", name),
+        }),
+    }
+}
+
+fn file_error(path: &str, e: std::io::Error) -> RuntimeError {
+    RuntimeError {
+        msg: format!("{}: {}", path, e),
+    }
+}
+
+/// Whether an options dictionary has `:append true`.
+fn append_option(options: Option<&DataType>) -> Result<bool, RuntimeError> {
+    match options {
+        None => Ok(false),
+        Some(Dictionary(options, _)) => Ok(options.get(":append") == Some(&Bool(true))),
+        Some(_) => Err(RuntimeError {
+            msg: "Options should be a dict".to_string(),
+        }),
+    }
+}
+
+fn handle_argument(
+    name: &str,
+    values: &[DataType],
+) -> Result<Rc<RefCell<FileHandle>>, RuntimeError> {
+    match values.first() {
+        Some(Handle(handle)) => Ok(handle.clone()),
+        _ => Err(RuntimeError {
+            msg: format!("{} expects a file handle", name),
+        }),
+    }
+}
+
+pub const SPIT: CoreFunction = CoreFunction {
+    id: "spit",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("spit", values)?;
+        let contents = match values.get(1) {
+            Some(String(string)) => string.clone(),
+            Some(value) => format!("{:?}", value),
+            None => {
+                return Err(RuntimeError {
+                    msg: "spit expects something to write".to_string(),
+                });
+            }
+        };
+        let append = append_option(values.get(2))?;
+
+        ctx.filesystem()
+            .write(path, &contents, append)
+            .map_err(|e| file_error(path, e))?;
+        Ok(Nil())
+    },
+};
+
+pub const FILE_EXISTS: CoreFunction = CoreFunction {
+    id: "file-exists?",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("file-exists?", values)?;
+        Ok(Bool(ctx.filesystem().exists(path)))
+    },
+};
+
+pub const LIST_DIR: CoreFunction = CoreFunction {
+    id: "list-dir",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("list-dir", values)?;
+        let names = ctx
+            .filesystem()
+            .list_dir(path)
+            .map_err(|e| file_error(path, e))?;
+        Ok(List(names.into_iter().map(String).collect(), None))
+    },
+};
+
+pub const MKDIR: CoreFunction = CoreFunction {
+    id: "mkdir",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("mkdir", values)?;
+        ctx.filesystem()
+            .create_dir(path)
+            .map_err(|e| file_error(path, e))?;
+        Ok(Nil())
+    },
+};
+
+pub const DELETE_FILE: CoreFunction = CoreFunction {
+    id: "delete-file",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("delete-file", values)?;
+        ctx.filesystem()
+            .remove(path)
+            .map_err(|e| file_error(path, e))?;
+        Ok(Nil())
+    },
+};
+
+pub const READ_LINES: CoreFunction = CoreFunction {
+    id: "read-lines",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("read-lines", values)?.to_string();
+        let file = ctx
+            .filesystem()
+            .open_read(&path)
+            .map_err(|e| file_error(&path, e))?;
+
+        let lines = BufRead::lines(file).map(move |line| match line {
+            Ok(line) => Ok(String(line)),
+            Err(e) => Err(file_error(&path, e)),
+        });
+        Ok(Generator(GeneratorHandle::streaming(lines)))
+    },
+};
+
+pub const OPEN_READER: CoreFunction = CoreFunction {
+    id: "open-reader",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("open-reader", values)?;
+        let file = ctx
+            .filesystem()
+            .open_read(path)
+            .map_err(|e| file_error(path, e))?;
+        Ok(Handle(Rc::new(RefCell::new(FileHandle::Reader(file)))))
+    },
+};
+
+pub const OPEN_WRITER: CoreFunction = CoreFunction {
+    id: "open-writer",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let path = path_argument("open-writer", values)?;
+        let append = append_option(values.get(1))?;
+        let file = ctx
+            .filesystem()
+            .open_write(path, append)
+            .map_err(|e| file_error(path, e))?;
+        Ok(Handle(Rc::new(RefCell::new(FileHandle::Writer(file)))))
+    },
+};
+
+/// Reads the next line from a reader without its line ending, or returns nil
+/// at the end of the file.
+pub const READ_LINE: CoreFunction = CoreFunction {
+    id: "read-line",
+    func: |_: &mut Context, values: &[DataType]| {
+        let handle = handle_argument("read-line", values)?;
+        let mut handle = handle.borrow_mut();
+        let FileHandle::Reader(reader) = &mut *handle else {
+            return Err(RuntimeError {
+                msg: "read-line expects an open reader".to_string(),
+            });
+        };
+
+        let mut line = std::string::String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => Ok(Nil()),
+            Ok(_) => {
+                let end = line.trim_end_matches(['\r', '\n']).len();
+                line.truncate(end);
+                Ok(String(line))
             }
+            Err(e) => Err(RuntimeError {
+                msg: format!("Couldn't read file: {}", e),
+            }),
+        }
+    },
+};
+
+pub const WRITE_STRING: CoreFunction = CoreFunction {
+    id: "write-string",
+    func: |_: &mut Context, values: &[DataType]| {
+        let handle = handle_argument("write-string", values)?;
+        let mut handle = handle.borrow_mut();
+        let FileHandle::Writer(writer) = &mut *handle else {
+            return Err(RuntimeError {
+                msg: "write-string expects an open writer".to_string(),
+            });
+        };
+
+        let text = match values.get(1) {
+            Some(String(string)) => string.clone(),
+            Some(value) => format!("{:?}", value),
+            None => std::string::String::new(),
+        };
+        writer
+            .write_all(text.as_bytes())
+            .map_err(|e| RuntimeError {
+                msg: format!("Couldn't write file: {}", e),
+            })?;
+        Ok(Nil())
+    },
+};
+
+pub const CLOSE: CoreFunction = CoreFunction {
+    id: "close",
+    func: |_: &mut Context, values: &[DataType]| {
+        handle_argument("close", values)?.borrow_mut().close()?;
+        Ok(Nil())
+    },
+};
+
+fn instant_argument(name: &str, values: &[DataType], index: usize) -> Result<i64, RuntimeError> {
+    match values.get(index) {
+        Some(Instant(instant)) => Ok(*instant),
+        _ => Err(RuntimeError {
+            msg: format!("{} expects an instant as argument {}", name, index + 1),
+        }),
+    }
+}
+
+fn integer_argument(name: &str, values: &[DataType], index: usize) -> Result<i64, RuntimeError> {
+    match values.get(index) {
+        Some(Integer(num)) => i64::try_from(*num).map_err(|_| RuntimeError {
+            msg: format!("{} is out of range for {}", num, name),
+        }),
+        _ => Err(RuntimeError {
+            msg: format!("{} expects an integer as argument {}", name, index + 1),
+        }),
+    }
+}
+
+fn unit_argument<'a>(name: &str, value: Option<&'a DataType>) -> Result<&'a str, RuntimeError> {
+    match value {
+        None => Ok("ms"),
+        Some(Keyword(unit)) => Ok(unit),
+        Some(_) => Err(RuntimeError {
+            msg: format!("{} expects a keyword unit such as :days", name),
+        }),
+    }
+}
+
+pub const NOW: CoreFunction = CoreFunction {
+    id: "now",
+    func: |_: &mut Context, _values: &[DataType]| Ok(Instant(datetime::epoch_millis())),
+};
+
+pub const NANO_TIME: CoreFunction = CoreFunction {
+    id: "nano-time",
+    func: |_: &mut Context, _values: &[DataType]| Ok(Integer(datetime::monotonic_nanos())),
+};
+
+/// `(instant ms)`, the instant `ms` milliseconds after the Unix epoch.
+pub const INSTANT: CoreFunction = CoreFunction {
+    id: "instant",
+    func: |_: &mut Context, values: &[DataType]| {
+        Ok(Instant(integer_argument("instant", values, 0)?))
+    },
+};
+
+pub const INSTANT_MS: CoreFunction = CoreFunction {
+    id: "inst-ms",
+    func: |_: &mut Context, values: &[DataType]| {
+        Ok(Integer(instant_argument("inst-ms", values, 0)?.into()))
+    },
+};
+
+pub const CHECK_INSTANT: CoreFunction = CoreFunction {
+    id: "instant?",
+    func: type_check!(DataType::Instant(_)),
+};
+
+/// `(date-time year month day [hour minute second ms])`
+pub const DATE_TIME: CoreFunction = CoreFunction {
+    id: "date-time",
+    func: |_: &mut Context, values: &[DataType]| {
+        if !(3..=7).contains(&values.len()) {
+            return Err(RuntimeError {
+                msg: "date-time expects between 3 and 7 arguments".to_string(),
+            });
+        }
+        let field = |index: usize| match values.get(index) {
+            None => Ok(0),
+            Some(_) => integer_argument("date-time", values, index),
+        };
+
+        let fields = datetime::Fields {
+            year: field(0)?,
+            month: field(1)?,
+            day: field(2)?,
+            hour: field(3)?,
+            minute: field(4)?,
+            second: field(5)?,
+            millis: field(6)?,
+        };
+        Ok(Instant(fields.to_instant()?))
+    },
+};
+
+/// `(parse-time text [pattern])`, reading ISO 8601 when no pattern is given.
+pub const PARSE_TIME: CoreFunction = CoreFunction {
+    id: "parse-time",
+    func: |_: &mut Context, values: &[DataType]| {
+        let instant = match (values.first(), values.get(1)) {
+            (Some(String(text)), None) => datetime::parse_iso(text)?,
+            (Some(String(text)), Some(String(pattern))) => datetime::parse(text, pattern)?,
+            _ => {
+                return Err(RuntimeError {
+                    msg: "parse-time expects a string and an optional pattern".to_string(),
+                });
+            }
+        };
+        Ok(Instant(instant))
+    },
+};
+
+pub const FORMAT_TIME: CoreFunction = CoreFunction {
+    id: "format-time",
+    func: |_: &mut Context, values: &[DataType]| {
+        let instant = instant_argument("format-time", values, 0)?;
+        let pattern = match values.get(1) {
+            None => datetime::ISO_PATTERN,
+            Some(String(pattern)) => pattern,
+            Some(_) => {
+                return Err(RuntimeError {
+                    msg: "format-time expects a string pattern".to_string(),
+                });
+            }
+        };
+        Ok(String(datetime::format(instant, pattern)?))
+    },
+};
+
+/// `(time-plus instant amount [unit])`, where the unit defaults to `:ms`.
+pub const TIME_PLUS: CoreFunction = CoreFunction {
+    id: "time-plus",
+    func: |_: &mut Context, values: &[DataType]| {
+        let instant = instant_argument("time-plus", values, 0)?;
+        let amount = integer_argument("time-plus", values, 1)?;
+        let unit = unit_argument("time-plus", values.get(2))?;
+        Ok(Instant(datetime::plus(instant, amount, unit)?))
+    },
+};
+
+/// `(time-between start end [unit])`, counting whole units.
+pub const TIME_BETWEEN: CoreFunction = CoreFunction {
+    id: "time-between",
+    func: |_: &mut Context, values: &[DataType]| {
+        let start = instant_argument("time-between", values, 0)?;
+        let end = instant_argument("time-between", values, 1)?;
+        let unit = unit_argument("time-between", values.get(2))?;
+        Ok(Integer(datetime::between(start, end, unit)?.into()))
+    },
+};
+
+pub const TIME_FIELDS: CoreFunction = CoreFunction {
+    id: "time-fields",
+    func: |_: &mut Context, values: &[DataType]| {
+        let instant = instant_argument("time-fields", values, 0)?;
+        Ok(datetime::Fields::of(instant).to_dict())
+    },
+};
+
+fn elapsed_ms(start: i128) -> f64 {
+    (datetime::monotonic_nanos() - start) as f64 / 1_000_000.0
+}
+
+fn options_argument(
+    values: &[DataType],
+    index: usize,
+) -> Result<HashMap<std::string::String, DataType>, RuntimeError> {
+    match values.get(index) {
+        None | Some(Nil()) => Ok(HashMap::new()),
+        Some(Dictionary(options, _)) => Ok(options.clone()),
+        Some(_) => Err(RuntimeError {
+            msg: "Options should be a dict".to_string(),
+        }),
+    }
+}
+
+fn count_option(
+    options: &HashMap<std::string::String, DataType>,
+    key: &str,
+    default: usize,
+) -> Result<usize, RuntimeError> {
+    match options.get(key) {
+        None => Ok(default),
+        Some(Integer(count)) if *count >= 0 => usize::try_from(*count).map_err(|_| RuntimeError {
+            msg: format!("{} is too large", key),
+        }),
+        Some(_) => Err(RuntimeError {
+            msg: format!("{} should be a non-negative integer", key),
+        }),
+    }
+}
+
+/// `(time-fn f)` calls `f` and prints how long it took. The `time` macro
+/// wraps an expression for it.
+pub const TIME_FN: CoreFunction = CoreFunction {
+    id: "time-fn",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let Some(function) = values.first() else {
+            return Err(RuntimeError {
+                msg: "time-fn expects a function".to_string(),
+            });
+        };
+
+        let start = datetime::monotonic_nanos();
+        let value = ctx.call(function, &[])?;
+        ctx.write(&format!("Elapsed time: {:.3} ms\n", elapsed_ms(start)))?;
+        Ok(value)
+    },
+};
+
+/// `(bench-fn f {:warmup 5 :iterations 100})` calls `f` repeatedly and
+/// returns, as well as prints, statistics about the timed calls in ms.
+pub const BENCH_FN: CoreFunction = CoreFunction {
+    id: "bench-fn",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let Some(function) = values.first() else {
+            return Err(RuntimeError {
+                msg: "bench-fn expects a function".to_string(),
+            });
+        };
+        let options = options_argument(values, 1)?;
+        let warmup = count_option(&options, ":warmup", 5)?;
+        let iterations = count_option(&options, ":iterations", 100)?;
+        if iterations == 0 {
+            return Err(RuntimeError {
+                msg: "bench-fn needs at least one iteration".to_string(),
+            });
+        }
+
+        for _ in 0..warmup {
+            ctx.call(function, &[])?;
+        }
+        let mut times = vec![];
+        for _ in 0..iterations {
+            let start = datetime::monotonic_nanos();
+            ctx.call(function, &[])?;
+            times.push(elapsed_ms(start));
+        }
+
+        times.sort_by(f64::total_cmp);
+        let count = times.len() as f64;
+        let mean = times.iter().sum::<f64>() / count;
+        let median = if times.len() % 2 == 0 {
+            (times[times.len() / 2 - 1] + times[times.len() / 2]) / 2.0
         } else {
-            let result = prompt("");
+            times[times.len() / 2]
+        };
+        let variance = times.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / count;
+        let (min, max) = (times[0], times[times.len() - 1]);
+
+        ctx.write(&format!(
+            "Bench: {} iterations, mean {:.3} ms, median {:.3} ms, min {:.3} ms, max {:.3} ms, stddev {:.3} ms\n",
+            iterations,
+            mean,
+            median,
+            min,
+            max,
+            variance.sqrt()
+        ))?;
+
+        let mut result = HashMap::new();
+        result.insert(":iterations".to_string(), Integer(iterations as i128));
+        result.insert(":mean".to_string(), Float(mean));
+        result.insert(":median".to_string(), Float(median));
+        result.insert(":min".to_string(), Float(min));
+        result.insert(":max".to_string(), Float(max));
+        result.insert(":stddev".to_string(), Float(variance.sqrt()));
+        Ok(Dictionary(result, None))
+    },
+};
 
-            match result {
-                Some(res) => Ok(DataType::String(res)),
-                None => Ok(DataType::String("".to_string())),
+/// `(profile-fn f {:folded "path"})` calls `f` while timing every named
+/// function it calls, then prints a table of them, or writes the call
+/// stacks to `path` for a flame graph.
+pub const PROFILE_FN: CoreFunction = CoreFunction {
+    id: "profile-fn",
+    func: |ctx: &mut Context, values: &[DataType]| {
+        let Some(function) = values.first() else {
+            return Err(RuntimeError {
+                msg: "profile-fn expects a function".to_string(),
+            });
+        };
+        let options = options_argument(values, 1)?;
+        let folded = match options.get(":folded") {
+            None | Some(Nil()) => None,
+            Some(String(path)) => {
+                ctx.require(Capability::Fs, "profile-fn with :folded")?;
+                Some(path.clone())
             }
+            Some(_) => {
+                return Err(RuntimeError {
+                    msg: ":folded should be a path".to_string(),
+                });
+            }
+        };
+        let Some(runtime) = ctx.runtime() else {
+            return ctx.call(function, &[]);
+        };
+
+        runtime.start_profiling()?;
+        let value = ctx.call(function, &[]);
+        let Some(profiler) = runtime.stop_profiling() else {
+            return value;
+        };
+        let value = value?;
+
+        match folded {
+            Some(path) => ctx
+                .filesystem()
+                .write(&path, &profiler.folded(), false)
+                .map_err(|e| file_error(&path, e))?,
+            None => ctx.write(&profiler.report())?,
         }
+        Ok(value)
     },
 };
@@ -1,403 +1,1578 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
-use crate::variable_type::{Closure, DataType, Environment};
+use crate::atom::{self, AtomState};
+use crate::capability::{Capability, allocation_size};
+use crate::context::Context;
+use crate::io::expand_with_open;
+use crate::multimethod::{
+    expand_defmethod, expand_defmulti, expand_defprotocol, expand_extend_type,
+};
+use crate::namespace::{eval_ns, eval_require, marks_private, resolve_qualified};
+use crate::pattern::{MatchClause, match_pattern, parse_clauses};
+use crate::record::expand_defrecord;
+use crate::runtime::Runtime;
+use crate::variable_type::{
+    Builtin, Closure, DataType, Dispatch, Environment, Generator, GeneratorState, Metadata,
+    MultiFn, Resume,
+};
 
 #[derive(Debug)]
 pub struct RuntimeError {
     pub msg: String,
 }
 
-pub fn eval<'a>(
-    ast: &'a DataType,
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// What a `recur` in tail position jumps back to.
+enum RecurTarget {
+    Loop {
+        names: Vec<String>,
+        body: DataType,
+        env: Rc<RefCell<Environment>>,
+    },
+    Closure(Closure),
+}
+
+/// The environment an expression is evaluated in, along with the one `eval`
+/// evaluates its argument in.
+#[derive(Clone)]
+struct Scope {
+    env: Rc<RefCell<Environment>>,
+    repl_env: Rc<RefCell<Environment>>,
+}
+
+/// The next thing for the evaluator to do.
+enum Control {
+    Eval(DataType, Scope, Option<Rc<RecurTarget>>),
+    Return(DataType),
+}
+
+/// What a list of evaluated elements is turned into once all of them are done.
+#[derive(Clone)]
+enum Collect {
+    Call(Option<Rc<RecurTarget>>),
+    Recur(Option<Rc<RecurTarget>>),
+    Vector(Metadata),
+    Dictionary(Vec<String>, Metadata),
+}
+
+#[derive(Clone)]
+enum LetKind {
+    Let,
+    Loop {
+        names: Vec<String>,
+        env: Rc<RefCell<Environment>>,
+    },
+}
+
+/// A `match` form part way through trying its clauses against `value`.
+#[derive(Clone)]
+struct MatchState {
+    value: DataType,
+    clauses: Rc<Vec<MatchClause>>,
+    index: usize,
+    scope: Scope,
+    recur: Option<Rc<RecurTarget>>,
+}
+
+/// Work left to do once the value currently being evaluated is known. Each
+/// frame receives that value when it is popped off the stack.
+#[derive(Clone)]
+enum Frame {
+    Collect {
+        kind: Collect,
+        forms: Vec<DataType>,
+        values: Vec<DataType>,
+        scope: Scope,
+    },
+    If {
+        then: Option<DataType>,
+        otherwise: Option<DataType>,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    },
+    Do {
+        forms: Vec<DataType>,
+        index: usize,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    },
+    Let {
+        kind: LetKind,
+        bindings: Vec<DataType>,
+        index: usize,
+        body: DataType,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    },
+    Define {
+        sym: String,
+        meta: Metadata,
+        is_macro: bool,
+        scope: Scope,
+    },
+    Bind {
+        sym: String,
+        value: DataType,
+        lenient: bool,
+        env: Rc<RefCell<Environment>>,
+    },
+    MergeMeta {
+        value: DataType,
+    },
+    TryHandler {
+        body: DataType,
+        scope: Scope,
+    },
+    Catch {
+        handler: DataType,
+        env: Rc<RefCell<Environment>>,
+    },
+    Quasiquote {
+        items: Vec<DataType>,
+        index: usize,
+        result: Vec<DataType>,
+        splice: bool,
+        scope: Scope,
+    },
+    Eval {
+        scope: Scope,
+    },
+    Expand {
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    },
+    Map {
+        function: DataType,
+        items: Vec<DataType>,
+        results: Vec<DataType>,
+        env: Rc<RefCell<Environment>>,
+    },
+    Swap {
+        atom: Rc<AtomState>,
+        /// Whether to return both the old and new values, for `swap-vals!`
+        vals: bool,
+        env: Rc<RefCell<Environment>>,
+    },
+    MatchValue {
+        clauses: Rc<Vec<MatchClause>>,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    },
+    MatchCheck {
+        state: MatchState,
+        env: Rc<RefCell<Environment>>,
+        checks: Vec<(DataType, DataType)>,
+        guard: Option<DataType>,
+        body: DataType,
+    },
+    Dispatch {
+        multi: Rc<MultiFn>,
+        args: Vec<DataType>,
+        env: Rc<RefCell<Environment>>,
+    },
+    Reset,
+    Yield,
+    Generator(Rc<RefCell<GeneratorState>>),
+    /// Marks where a profiled call to a named closure returns
+    Profile {
+        depth: usize,
+    },
+}
+
+/// The rest of a computation up to the enclosing `reset`, captured by `shift`.
+/// Calling it runs those frames again with the argument as the result of the
+/// `shift` form.
+pub struct Continuation {
+    frames: Vec<Frame>,
+}
+
+/// Evaluates expressions using a stack of frames on the heap rather than
+/// recursing on the Rust stack, so how deep a program can recurse is only
+/// bounded by memory, or the runtime's maximum depth if it has one.
+struct Machine {
+    stack: Vec<Frame>,
+    max_depth: Option<usize>,
+    /// Frames held by the machines this one is running inside of, which count
+    /// towards the maximum depth too.
+    base: usize,
+    /// Where steps and allocations are counted against the runtime's limits.
+    runtime: Option<Rc<Runtime>>,
+    /// Where a generator's function is first called from.
+    env: Rc<RefCell<Environment>>,
+}
+
+pub fn eval(
+    ast: &DataType,
     current_env: Rc<RefCell<Environment>>,
     repl_env: Rc<RefCell<Environment>>,
 ) -> Result<DataType, RuntimeError> {
-    let mut ast = Box::new(ast.clone()); // TODO: Lots of memory usage here...
-    let mut current_env: Rc<RefCell<Environment>> = current_env;
-
-    loop {
-        match *ast {
-            DataType::List(children) => {
-                match children.first() {
-                    Some(DataType::Symbol(val)) if *val == "def!".to_string() => {
-                        return eval_def(&children[1..], current_env.clone(), repl_env.clone());
-                    }
+    check_recur(ast, None, false, &current_env, 0)?;
 
-                    Some(DataType::Symbol(val)) if *val == "defmacro!".to_string() => {
-                        return eval_defmacro(
-                            &children[1..],
-                            current_env.clone(),
-                            repl_env.clone(),
-                        );
-                    }
+    let mut machine = Machine::new(&current_env);
+    let scope = Scope {
+        env: current_env,
+        repl_env,
+    };
 
-                    Some(DataType::Symbol(val)) if *val == "try*".to_string() => {
-                        return eval_try(&children[1..], current_env.clone(), repl_env.clone());
-                    }
+    machine.run(Control::Eval(ast.clone(), scope, None))
+}
 
-                    Some(DataType::Symbol(val)) if *val == "quote".to_string() => {
-                        return eval_quote(&children[1..]);
-                    }
+/// Calls `function` with `args` from outside the evaluator, e.g. from a native
+/// function.
+pub fn apply(
+    function: &DataType,
+    args: &[DataType],
+    env: &Rc<RefCell<Environment>>,
+) -> Result<DataType, RuntimeError> {
+    let mut machine = Machine::new(env);
+    let control = machine.call(function.clone(), args.to_vec(), env.clone())?;
 
-                    Some(DataType::Symbol(val)) if *val == "quasiquote".to_string() => {
-                        return eval_quasiquote(
-                            &children[1..],
-                            current_env.clone(),
-                            repl_env.clone(),
-                        );
-                    }
+    machine.run(control)
+}
 
-                    Some(DataType::Symbol(val)) if *val == "let*".to_string() => {
-                        match prepare_tail_call_let(&children[1..], current_env) {
-                            Ok((new_ast, new_env)) => {
-                                ast = Box::new(new_ast.clone());
-                                current_env = Rc::new(RefCell::new(new_env));
-                                continue;
-                            }
-                            Err(e) => return Err(e),
-                        };
-                    }
+impl Machine {
+    fn new(env: &Rc<RefCell<Environment>>) -> Machine {
+        let runtime = env.borrow().runtime();
+        let (max_depth, base) = match &runtime {
+            Some(runtime) => (runtime.max_depth(), runtime.depth()),
+            None => (None, 0),
+        };
 
-                    Some(DataType::Symbol(val)) if *val == "do".to_string() => {
-                        match prepare_tail_call_do(
-                            &children[1..],
-                            current_env.clone(),
-                            repl_env.clone(),
-                        ) {
-                            Ok(new_ast) => {
-                                ast = Box::new(new_ast.clone());
-                                continue;
-                            }
-                            Err(e) => return Err(e),
-                        };
-                    }
+        Self {
+            stack: vec![],
+            max_depth,
+            base,
+            runtime,
+            env: env.clone(),
+        }
+    }
 
-                    Some(DataType::Symbol(val)) if *val == "if".to_string() => {
-                        match prepare_tail_call_if(
-                            &children[1..],
-                            current_env.clone(),
-                            repl_env.clone(),
-                        ) {
-                            Ok(new_ast) => {
-                                ast = Box::new(new_ast.clone());
-                                continue;
-                            }
-                            Err(e) => return Err(e),
-                        };
-                    }
+    fn run(&mut self, control: Control) -> Result<DataType, RuntimeError> {
+        let Some(runtime) = self.runtime.clone() else {
+            return self.run_steps(control);
+        };
 
-                    Some(DataType::Symbol(val)) if *val == "fn*".to_string() => {
-                        return eval_closure(&children[1..], current_env.clone(), repl_env.clone());
-                    }
+        runtime.enter_nested()?;
+        let result = self.run_steps(control);
+        runtime.exit_nested(self.base);
+        result
+    }
+
+    fn run_steps(&mut self, control: Control) -> Result<DataType, RuntimeError> {
+        let mut control = control;
+
+        loop {
+            if let Some(runtime) = &self.runtime {
+                runtime.count_step()?;
+                // Seen by any machine started during this step
+                runtime.set_depth(self.base + self.stack.len());
+            }
+
+            let next = match control {
+                Control::Eval(ast, scope, recur) => self.eval_form(ast, scope, recur),
+                Control::Return(value) => match self.stack.pop() {
+                    Some(frame) => self.resume(frame, value),
+                    None => return Ok(value),
+                },
+            };
+
+            control = match next {
+                Ok(next) => next,
+                Err(e) => self.unwind(e)?,
+            };
+        }
+    }
+
+    fn push(&mut self, frame: Frame) -> Result<(), RuntimeError> {
+        if let Some(max_depth) = self.max_depth
+            && self.base + self.stack.len() >= max_depth
+        {
+            return Err(RuntimeError {
+                msg: "maximum recursion depth exceeded".to_string(),
+            });
+        }
+
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    fn allocate(&self, size: u64) -> Result<(), RuntimeError> {
+        match &self.runtime {
+            Some(runtime) => runtime.count_allocation(size),
+            None => Ok(()),
+        }
+    }
+
+    /// Times a call to the closure `name` if the profiler is running. A call
+    /// straight back into the same closure, as loops make, is counted
+    /// without a frame of its own so that the stack doesn't grow.
+    fn profile_call(&mut self, name: &Rc<str>) -> Result<(), RuntimeError> {
+        let Some(runtime) = self.runtime.clone() else {
+            return Ok(());
+        };
+        if !runtime.profiling() {
+            return Ok(());
+        }
+
+        if let Some(Frame::Profile { depth }) = self.stack.last()
+            && runtime.profiled_name(*depth).as_ref() == Some(name)
+        {
+            runtime.profile_count_call(name);
+            return Ok(());
+        }
+
+        let depth = runtime.profile_enter(name);
+        self.push(Frame::Profile { depth })
+    }
+
+    fn require(&self, capability: Capability, name: &str) -> Result<(), RuntimeError> {
+        match &self.runtime {
+            Some(runtime) => runtime.require(capability, name),
+            None => Ok(()),
+        }
+    }
+
+    /// Pops frames until a `try*` handler is found and calls it with the error.
+    /// Errors from going over a limit aren't handed to any handler.
+    fn unwind(&mut self, error: RuntimeError) -> Result<Control, RuntimeError> {
+        let mut error = error;
+        if self
+            .runtime
+            .as_ref()
+            .is_some_and(|runtime| runtime.over_limit())
+        {
+            return Err(error);
+        }
+
+        while let Some(frame) = self.stack.pop() {
+            if let Frame::Profile { depth } = frame
+                && let Some(runtime) = &self.runtime
+            {
+                runtime.profile_exit(depth);
+            }
+            if let Frame::Catch { handler, env } = frame {
+                match self.call(handler, vec![DataType::String(error.msg)], env) {
+                    Ok(control) => return Ok(control),
+                    Err(e) => error = e,
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    fn eval_form(
+        &mut self,
+        ast: DataType,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    ) -> Result<Control, RuntimeError> {
+        let mut ast = ast;
+        match &mut ast {
+            DataType::List(children, meta) => {
+                self.eval_list(std::mem::take(children), meta.take(), scope, recur)
+            }
+
+            DataType::Vector(forms, meta) => self.collect(
+                Collect::Vector(meta.take()),
+                std::mem::take(forms),
+                vec![],
+                scope,
+            ),
+
+            DataType::Dictionary(dict, meta) => {
+                let (keys, forms) = dict.drain().unzip();
+                self.collect(Collect::Dictionary(keys, meta.take()), forms, vec![], scope)
+            }
+
+            DataType::Symbol(sym, _) => {
+                if let Some(val) = scope.env.borrow().get(sym) {
+                    return Ok(Control::Return(val));
+                }
+
+                if let Some(val) = resolve_qualified(sym, &scope.env)? {
+                    return Ok(Control::Return(val));
+                }
+
+                Err(RuntimeError {
+                    msg: format!("Unknown symbol: {}", sym),
+                })
+            }
+
+            _ => Ok(Control::Return(ast)),
+        }
+    }
+
+    fn eval_list(
+        &mut self,
+        children: Vec<DataType>,
+        list_meta: Metadata,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    ) -> Result<Control, RuntimeError> {
+        let special_form = match children.first() {
+            Some(DataType::Symbol(val, _)) => val.as_str(),
+            Some(_) => "",
+            None => {
+                return Err(RuntimeError {
+                    msg: "Cannot call list as function!".to_string(),
+                });
+            }
+        };
+        let args = &children[1..];
+
+        match special_form {
+            "def!" | "defmacro!" => {
+                let (Some(DataType::Symbol(sym, meta)), Some(val)) = (args.first(), args.get(1))
+                else {
+                    return Err(RuntimeError {
+                        msg: format!("Incorrect usage of {}", special_form),
+                    });
+                };
+
+                self.push(Frame::Define {
+                    sym: sym.clone(),
+                    meta: meta.clone(),
+                    is_macro: special_form == "defmacro!",
+                    scope: scope.clone(),
+                })?;
+                Ok(Control::Eval(val.clone(), scope, None))
+            }
+
+            "try*" => {
+                let (Some(body), Some(handler)) = (args.first(), args.get(1)) else {
+                    return Err(RuntimeError {
+                        msg: "Incorrect usage of try!".to_string(),
+                    });
+                };
+
+                self.push(Frame::TryHandler {
+                    body: body.clone(),
+                    scope: scope.clone(),
+                })?;
+                Ok(Control::Eval(handler.clone(), scope, None))
+            }
+
+            "quote" => match args.first() {
+                Some(val) => Ok(Control::Return(val.clone())),
+                None => Err(RuntimeError {
+                    msg: "Incorrect usage of quote".to_string(),
+                }),
+            },
+
+            "quasiquote" => match args.first() {
+                Some(DataType::List(items, _)) => self.quasiquote(items.clone(), 0, vec![], scope),
+                _ => Err(RuntimeError {
+                    msg: "Incorrect usage of quote".to_string(),
+                }),
+            },
+
+            "let*" => {
+                let (Some(DataType::List(bindings, _) | DataType::Vector(bindings, _)), Some(body)) =
+                    (args.first(), args.get(1))
+                else {
+                    return Err(RuntimeError {
+                        msg: "Incorrect arguments for let*".to_string(),
+                    });
+                };
+
+                let scope = Scope {
+                    env: Rc::new(RefCell::new(Environment::new(Some(scope.env)))),
+                    repl_env: scope.repl_env,
+                };
+                self.bind_next(
+                    LetKind::Let,
+                    bindings.clone(),
+                    0,
+                    body.clone(),
+                    scope,
+                    recur,
+                )
+            }
+
+            "loop" => {
+                let (Some(DataType::List(bindings, _) | DataType::Vector(bindings, _)), Some(body)) =
+                    (args.first(), args.get(1))
+                else {
+                    return Err(RuntimeError {
+                        msg: "Incorrect arguments for loop".to_string(),
+                    });
+                };
 
-                    Some(DataType::Symbol(val)) if *val == "eval".to_string() => {
-                        let Some(new_ast) = children.get(1) else {
+                let mut names = vec![];
+                for binding in bindings.chunks(2) {
+                    match binding {
+                        [DataType::Symbol(name, _), _] => names.push(name.clone()),
+                        _ => {
                             return Err(RuntimeError {
-                                msg: "No value given to eval".to_string(),
+                                msg: "Each symbol in a loop should have a value".to_string(),
                             });
-                        };
-                        let evaled_new_ast = eval(new_ast, current_env.clone(), repl_env.clone())?;
-                        ast = Box::new(evaled_new_ast.clone());
-                        current_env = repl_env.clone();
-                        continue;
+                        }
                     }
+                }
 
-                    Some(DataType::Closure(function)) if function.is_macro => {
-                        let new_ast = function.func(&children[1..])?;
-                        ast = Box::new(new_ast.clone());
-                        println!("{:?}", new_ast);
-                        continue;
-                    }
+                let kind = LetKind::Loop {
+                    names,
+                    env: scope.env.clone(),
+                };
+                let scope = Scope {
+                    env: Rc::new(RefCell::new(Environment::new(Some(scope.env)))),
+                    repl_env: scope.repl_env,
+                };
+                self.bind_next(kind, bindings.clone(), 0, body.clone(), scope, None)
+            }
+
+            "recur" => self.collect(Collect::Recur(recur), args.to_vec(), vec![], scope),
+
+            "do" => {
+                if args.is_empty() {
+                    return Err(RuntimeError {
+                        msg: "No arguments given for do".to_string(),
+                    });
+                }
+
+                self.do_next(args.to_vec(), 0, scope, recur)
+            }
+
+            "if" => {
+                let Some(condition) = args.first() else {
+                    return Err(RuntimeError {
+                        msg: "No condition for if expression".to_string(),
+                    });
+                };
+
+                self.push(Frame::If {
+                    then: args.get(1).cloned(),
+                    otherwise: args.get(2).cloned(),
+                    scope: scope.clone(),
+                    recur,
+                })?;
+                Ok(Control::Eval(condition.clone(), scope, None))
+            }
+
+            "fn*" => {
+                self.allocate(1)?;
+                let closure = eval_closure(args, scope.env.clone(), scope.repl_env.clone())?;
+                self.merge_meta(closure, list_meta, scope)
+            }
+
+            "defmulti" => Ok(Control::Eval(expand_defmulti(args)?, scope, recur)),
+
+            "defmethod" => Ok(Control::Eval(expand_defmethod(args)?, scope, recur)),
 
-                    _ => {}
+            "defprotocol" => Ok(Control::Eval(expand_defprotocol(args)?, scope, recur)),
+
+            "extend-type" => Ok(Control::Eval(expand_extend_type(args)?, scope, recur)),
+
+            "defrecord" => Ok(Control::Eval(expand_defrecord(args, true)?, scope, recur)),
+
+            "deftype" => Ok(Control::Eval(expand_defrecord(args, false)?, scope, recur)),
+
+            "with-open" => Ok(Control::Eval(expand_with_open(args)?, scope, recur)),
+
+            "match" => {
+                let Some(value) = args.first() else {
+                    return Err(RuntimeError {
+                        msg: "No value given to match".to_string(),
+                    });
                 };
 
-                if let Some(first) = children.first() {
-                    let first_evaluated = eval(first, current_env.clone(), repl_env.clone());
+                self.push(Frame::MatchValue {
+                    clauses: Rc::new(parse_clauses(&args[1..])?),
+                    scope: scope.clone(),
+                    recur,
+                })?;
+                Ok(Control::Eval(value.clone(), scope, None))
+            }
 
-                    match first_evaluated {
-                        Ok(DataType::Closure(function)) if function.is_macro => {
-                            let new_ast = function.func(&children[1..])?;
-                            ast = Box::new(new_ast.clone());
-                            continue;
+            "reset" => {
+                let Some(body) = args.first() else {
+                    return Err(RuntimeError {
+                        msg: "No body given to reset".to_string(),
+                    });
+                };
+
+                self.push(Frame::Reset)?;
+                Ok(Control::Eval(body.clone(), scope, None))
+            }
+
+            "shift" => {
+                let (Some(DataType::Symbol(name, _)), Some(body)) = (args.first(), args.get(1))
+                else {
+                    return Err(RuntimeError {
+                        msg: "Incorrect usage of shift".to_string(),
+                    });
+                };
+
+                let Some(reset) = self
+                    .stack
+                    .iter()
+                    .rposition(|frame| matches!(frame, Frame::Reset))
+                else {
+                    return Err(RuntimeError {
+                        msg: "shift used outside of reset".to_string(),
+                    });
+                };
+
+                let frames = self.stack.split_off(reset + 1);
+                let mut env = Environment::new(Some(scope.env));
+                env.set(
+                    name.clone(),
+                    DataType::Continuation(Rc::new(Continuation { frames })),
+                );
+
+                let scope = Scope {
+                    env: Rc::new(RefCell::new(env)),
+                    repl_env: scope.repl_env,
+                };
+                Ok(Control::Eval(body.clone(), scope, None))
+            }
+
+            "yield" => {
+                let Some(value) = args.first() else {
+                    return Err(RuntimeError {
+                        msg: "No value given to yield".to_string(),
+                    });
+                };
+
+                self.push(Frame::Yield)?;
+                Ok(Control::Eval(value.clone(), scope, None))
+            }
+
+            "ns" => Ok(Control::Return(eval_ns(args, scope.env)?)),
+
+            "require" => {
+                self.require(Capability::Fs, "require")?;
+                Ok(Control::Return(eval_require(args, scope.env)?))
+            }
+
+            "eval" => {
+                self.require(Capability::Eval, "eval")?;
+                let Some(new_ast) = args.first() else {
+                    return Err(RuntimeError {
+                        msg: "No value given to eval".to_string(),
+                    });
+                };
+
+                self.push(Frame::Eval {
+                    scope: scope.clone(),
+                })?;
+                Ok(Control::Eval(new_ast.clone(), scope, None))
+            }
+
+            _ => self.collect(Collect::Call(recur), children, vec![], scope),
+        }
+    }
+
+    /// Hands `value` to the frame that was waiting for it.
+    fn resume(&mut self, frame: Frame, value: DataType) -> Result<Control, RuntimeError> {
+        match frame {
+            Frame::Collect {
+                kind,
+                forms,
+                values,
+                scope,
+            } => {
+                // The head of a call is evaluated first so macros get their
+                // arguments unevaluated
+                if let Collect::Call(ref recur) = kind
+                    && values.is_empty()
+                    && let DataType::Closure(ref function) = value
+                    && function.is_macro
+                {
+                    let env = scope.env.clone();
+                    self.push(Frame::Expand {
+                        scope,
+                        recur: recur.clone(),
+                    })?;
+                    return self.call(value, forms[1..].to_vec(), env);
+                }
+
+                let mut values = values;
+                values.push(value);
+                self.collect(kind, forms, values, scope)
+            }
+
+            Frame::If {
+                then,
+                otherwise,
+                scope,
+                recur,
+            } => {
+                let branch = match value {
+                    DataType::Bool(false) | DataType::Nil() => otherwise.unwrap_or(DataType::Nil()),
+                    _ => match then {
+                        Some(then) => then,
+                        None => {
+                            return Err(RuntimeError {
+                                msg: "No body for if expression".to_string(),
+                            });
                         }
-                        _ => {}
-                    }
+                    },
+                };
+
+                Ok(Control::Eval(branch, scope, recur))
+            }
+
+            Frame::Do {
+                forms,
+                index,
+                scope,
+                recur,
+            } => self.do_next(forms, index + 1, scope, recur),
+
+            Frame::Let {
+                kind,
+                bindings,
+                index,
+                body,
+                scope,
+                recur,
+            } => {
+                if let Some(DataType::Symbol(name, _)) = bindings.get(index) {
+                    scope.env.borrow_mut().set(name.clone(), value);
                 }
 
-                let evaluated: Vec<DataType> = children
-                    .iter()
-                    .map(|child| eval(child, current_env.clone(), repl_env.clone()))
-                    .collect::<Result<_, RuntimeError>>()?;
-
-                match evaluated.first() {
-                    Some(DataType::Closure(function)) => {
-                        let (new_ast, new_env) = function.prepare_tail_call(&evaluated[1..])?;
-                        ast = Box::new(new_ast.clone());
-                        current_env = new_env.clone();
-                        continue;
+                self.bind_next(kind, bindings, index + 2, body, scope, recur)
+            }
+
+            Frame::Define {
+                sym,
+                meta,
+                is_macro,
+                scope,
+            } => {
+                let value = match &value {
+                    DataType::Closure(closure) if is_macro => DataType::Closure(Closure {
+                        is_macro: true,
+                        name: closure.name.clone().or_else(|| Some(sym.as_str().into())),
+                        ..closure.clone()
+                    }),
+                    DataType::Closure(closure) if closure.name.is_none() => {
+                        DataType::Closure(Closure {
+                            name: Some(sym.as_str().into()),
+                            ..closure.clone()
+                        })
+                    }
+                    _ if is_macro => {
+                        return Err(RuntimeError {
+                            msg: "Expected closure for macro".to_string(),
+                        });
                     }
+                    _ => value,
+                };
 
-                    Some(DataType::NativeFunction(function)) => {
-                        return Ok(function.1(&evaluated[1..])?);
+                // Metadata on the name is carried over to the value, which
+                // def! skips for values that can't hold it. `^:private` is
+                // kept on the binding instead, so it works for any value
+                match meta {
+                    Some(meta) => {
+                        self.push(Frame::Bind {
+                            sym,
+                            value,
+                            lenient: !is_macro,
+                            env: scope.env.clone(),
+                        })?;
+                        Ok(Control::Eval((*meta).clone(), scope, None))
+                    }
+                    None => {
+                        scope.env.borrow_mut().define(sym, value.clone(), false);
+                        Ok(Control::Return(value))
                     }
+                }
+            }
+
+            Frame::Bind {
+                sym,
+                value: target,
+                lenient,
+                env,
+            } => {
+                let private = marks_private(&value);
+                let target = match target.merge_meta(value) {
+                    Ok(target) => target,
+                    Err(_) if lenient => target,
+                    Err(e) => return Err(e),
+                };
+
+                env.borrow_mut().define(sym, target.clone(), private);
+                Ok(Control::Return(target))
+            }
+
+            Frame::MergeMeta { value: target } => Ok(Control::Return(target.merge_meta(value)?)),
+
+            Frame::TryHandler { body, scope } => {
+                let DataType::Closure(_) = value else {
+                    return Err(RuntimeError {
+                        msg: "Incorrect usage of try!".to_string(),
+                    });
+                };
+
+                self.push(Frame::Catch {
+                    handler: value,
+                    env: scope.env.clone(),
+                })?;
+                Ok(Control::Eval(body, scope, None))
+            }
 
-                    None | Some(_) => {
+            Frame::Catch { .. } => Ok(Control::Return(value)),
+
+            Frame::Quasiquote {
+                items,
+                index,
+                result,
+                splice,
+                scope,
+            } => {
+                let mut result = result;
+                if splice {
+                    let mut value = value;
+                    let DataType::List(values, _) = &mut value else {
                         return Err(RuntimeError {
-                            msg: format!("Cannot call list as function!"),
+                            msg: "List not given to splice-unquote".to_string(),
                         });
-                    }
+                    };
+                    result.append(values);
+                } else {
+                    result.push(value);
+                }
+
+                self.quasiquote(items, index + 1, result, scope)
+            }
+
+            Frame::Eval { scope } => {
+                check_recur(&value, None, false, &scope.repl_env, 0)?;
+                let scope = Scope {
+                    env: scope.repl_env.clone(),
+                    repl_env: scope.repl_env,
                 };
+                Ok(Control::Eval(value, scope, None))
             }
 
-            DataType::Vector(list) => {
-                let evaluated: Vec<DataType> = list
-                    .iter()
-                    .map(|child| eval(child, current_env.clone(), repl_env.clone()))
-                    .collect::<Result<_, RuntimeError>>()?;
+            Frame::Expand { scope, recur } => Ok(Control::Eval(value, scope, recur)),
+
+            Frame::Map {
+                function,
+                items,
+                results,
+                env,
+            } => {
+                let mut results = results;
+                results.push(value);
+                self.map_next(function, items, results, env)
+            }
 
-                return Ok(DataType::Vector(evaluated));
+            Frame::Swap { atom, vals, env } => {
+                let old = atom::set(&atom, value.clone(), &env)?;
+                if vals {
+                    Ok(Control::Return(DataType::Vector(vec![old, value], None)))
+                } else {
+                    Ok(Control::Return(value))
+                }
             }
 
-            DataType::Dictionary(dict) => {
-                let evaluated: HashMap<String, DataType> = dict
+            Frame::MatchValue {
+                clauses,
+                scope,
+                recur,
+            } => self.match_clauses(MatchState {
+                value,
+                clauses,
+                index: 0,
+                scope,
+                recur,
+            }),
+
+            Frame::MatchCheck {
+                state,
+                env,
+                checks,
+                guard,
+                body,
+            } => match value {
+                DataType::Bool(false) | DataType::Nil() => self.match_clauses(MatchState {
+                    index: state.index + 1,
+                    ..state
+                }),
+                _ => self.match_checks(state, env, checks, guard, body),
+            },
+
+            Frame::Dispatch { multi, args, env } => self.call_method(&multi, &value, args, env),
+
+            Frame::Reset => Ok(Control::Return(value)),
+
+            Frame::Profile { depth } => {
+                if let Some(runtime) = &self.runtime {
+                    runtime.profile_exit(depth);
+                }
+                Ok(Control::Return(value))
+            }
+
+            // Suspends the generator, leaving the rest of its body to run the
+            // next time a value is needed
+            Frame::Yield => {
+                let Some(boundary) = self
+                    .stack
                     .iter()
-                    .map(
-                        |child| match eval(child.1, current_env.clone(), repl_env.clone()) {
-                            Ok(result) => Ok((child.0.clone(), result)),
-                            Err(err) => Err(err),
-                        },
-                    )
-                    .collect::<Result<HashMap<String, DataType>, RuntimeError>>()?;
+                    .rposition(|frame| matches!(frame, Frame::Generator(_)))
+                else {
+                    return Err(RuntimeError {
+                        msg: "yield used outside of a generator".to_string(),
+                    });
+                };
 
-                return Ok(DataType::Dictionary(evaluated));
+                let frames = self.stack.split_off(boundary + 1);
+                let Some(Frame::Generator(state)) = self.stack.pop() else {
+                    unreachable!()
+                };
+
+                let mut state = state.borrow_mut();
+                state.values.push(value);
+                state.resume = Resume::Suspended(Rc::new(Continuation { frames }));
+                Ok(Control::Return(DataType::Nil()))
             }
 
-            DataType::Symbol(sym) => {
-                if let Some(val) = current_env.borrow_mut().get(&sym) {
-                    return Ok(val);
-                } else {
+            Frame::Generator(state) => {
+                state.borrow_mut().resume = Resume::Finished;
+                Ok(Control::Return(DataType::Nil()))
+            }
+        }
+    }
+
+    /// Evaluates the remaining `forms` one at a time, then does whatever
+    /// `kind` says with the results.
+    fn collect(
+        &mut self,
+        kind: Collect,
+        forms: Vec<DataType>,
+        values: Vec<DataType>,
+        scope: Scope,
+    ) -> Result<Control, RuntimeError> {
+        if let Some(next) = forms.get(values.len()).cloned() {
+            self.push(Frame::Collect {
+                kind,
+                forms,
+                values,
+                scope: scope.clone(),
+            })?;
+            return Ok(Control::Eval(next, scope, None));
+        }
+
+        match kind {
+            Collect::Call(_) => {
+                let mut values = values;
+                let function = values.remove(0);
+                self.call(function, values, scope.env)
+            }
+
+            Collect::Recur(target) => {
+                // `recur` outside of tail position gets no target, so this
+                // also catches any the check before evaluation couldn't see
+                let Some(target) = target else {
                     return Err(RuntimeError {
-                        msg: format!("Unknown symbol: {}", sym),
+                        msg: "Can only recur from tail position of loop or fn*".to_string(),
                     });
                 };
+
+                let (names, body, outer_env) = match *target {
+                    RecurTarget::Loop {
+                        ref names,
+                        ref body,
+                        ref env,
+                    } => (names.clone(), body.clone(), env.clone()),
+                    RecurTarget::Closure(ref closure) => (
+                        closure.recur_names(),
+                        (*closure.ast).clone(),
+                        closure.env.clone(),
+                    ),
+                };
+
+                let scope = Scope {
+                    env: Rc::new(RefCell::new(bind_recur(&names, values, outer_env)?)),
+                    repl_env: scope.repl_env,
+                };
+                Ok(Control::Eval(body, scope, Some(target)))
+            }
+
+            Collect::Vector(meta) => {
+                self.allocate(1 + values.len() as u64)?;
+                self.merge_meta(DataType::Vector(values, None), meta, scope)
             }
 
-            _ => return Ok((*ast).clone()),
+            Collect::Dictionary(keys, meta) => {
+                self.allocate(1 + values.len() as u64)?;
+                let dict = keys.into_iter().zip(values).collect();
+                self.merge_meta(DataType::Dictionary(dict, None), meta, scope)
+            }
         }
     }
-}
 
-fn eval_def(
-    args: &[DataType],
-    env: Rc<RefCell<Environment>>,
-    repl_env: Rc<RefCell<Environment>>,
-) -> Result<DataType, RuntimeError> {
-    if let (Some(DataType::Symbol(sym)), Some(val)) = (args.get(0), args.get(1)) {
-        let evaluated_val = eval(val, env.clone(), repl_env)?;
-        env.borrow_mut().set(sym.to_owned(), evaluated_val.clone());
+    /// Evaluates the metadata attached to a form and merges it into `value`.
+    fn merge_meta(
+        &mut self,
+        value: DataType,
+        meta: Metadata,
+        scope: Scope,
+    ) -> Result<Control, RuntimeError> {
+        match meta {
+            Some(meta) => {
+                self.push(Frame::MergeMeta { value })?;
+                Ok(Control::Eval((*meta).clone(), scope, None))
+            }
+            None => Ok(Control::Return(value)),
+        }
+    }
 
-        return Ok(evaluated_val);
-    } else {
-        return Err(RuntimeError {
-            msg: "Incorrect usage of def!".to_string(),
-        });
+    fn do_next(
+        &mut self,
+        forms: Vec<DataType>,
+        index: usize,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    ) -> Result<Control, RuntimeError> {
+        let form = forms[index].clone();
+        if index == forms.len() - 1 {
+            return Ok(Control::Eval(form, scope, recur));
+        }
+
+        self.push(Frame::Do {
+            forms,
+            index,
+            scope: scope.clone(),
+            recur,
+        })?;
+        Ok(Control::Eval(form, scope, None))
     }
-}
 
-fn eval_defmacro(
-    args: &[DataType],
-    env: Rc<RefCell<Environment>>,
-    repl_env: Rc<RefCell<Environment>>,
-) -> Result<DataType, RuntimeError> {
-    if let (Some(DataType::Symbol(sym)), Some(val)) = (args.get(0), args.get(1)) {
-        if let DataType::Closure(cl) = eval(&val, env.clone(), repl_env.clone())? {
-            let val = DataType::Closure(Closure {
-                is_macro: true,
-                ..cl.clone()
-            });
+    /// Evaluates the next binding of a `let*` or `loop`, or its body once they
+    /// are all bound.
+    fn bind_next(
+        &mut self,
+        kind: LetKind,
+        bindings: Vec<DataType>,
+        index: usize,
+        body: DataType,
+        scope: Scope,
+        recur: Option<Rc<RecurTarget>>,
+    ) -> Result<Control, RuntimeError> {
+        match (bindings.get(index), bindings.get(index + 1)) {
+            (None, _) => match kind {
+                LetKind::Let => Ok(Control::Eval(body, scope, recur)),
+                LetKind::Loop { names, env } => {
+                    let target = RecurTarget::Loop {
+                        names,
+                        body: body.clone(),
+                        env,
+                    };
+                    Ok(Control::Eval(body, scope, Some(Rc::new(target))))
+                }
+            },
+
+            (Some(DataType::Symbol(_, _)), Some(value)) => {
+                let value = value.clone();
+                self.push(Frame::Let {
+                    kind,
+                    bindings,
+                    index,
+                    body,
+                    scope: scope.clone(),
+                    recur,
+                })?;
+                Ok(Control::Eval(value, scope, None))
+            }
 
-            env.borrow_mut().set(sym.to_owned(), val.clone());
+            (Some(DataType::Symbol(_, _)), None) => Err(RuntimeError {
+                msg: "Each symbol in a let* environment should have a value".to_string(),
+            }),
 
-            return Ok(val);
-        } else {
-            return Err(RuntimeError {
-                msg: "Expected closure for macro".to_string(),
-            });
+            (Some(_), _) => Err(RuntimeError {
+                msg: "Invalid symbol to set in let*".to_string(),
+            }),
         }
-    } else {
-        return Err(RuntimeError {
-            msg: "Incorrect usage of defmacro!".to_string(),
-        });
     }
-}
 
-fn eval_try(
-    args: &[DataType],
-    env: Rc<RefCell<Environment>>,
-    repl_env: Rc<RefCell<Environment>>,
-) -> Result<DataType, RuntimeError> {
-    if let (Some(ast), Some(closure)) = (args.get(0), args.get(1)) {
-        let Ok(DataType::Closure(closure)) = eval(closure, env.clone(), repl_env.clone()) else {
-            return Err(RuntimeError {
-                msg: "Incorrect usage of try!".to_string(),
-            });
-        };
+    /// Copies quasiquoted items into `result` up to the next `unquote` or
+    /// `splice-unquote`, which is evaluated before carrying on.
+    fn quasiquote(
+        &mut self,
+        items: Vec<DataType>,
+        index: usize,
+        result: Vec<DataType>,
+        scope: Scope,
+    ) -> Result<Control, RuntimeError> {
+        let mut index = index;
+        let mut result = result;
 
-        let result = eval(ast, env.clone(), repl_env.clone());
+        while let Some(item) = items.get(index) {
+            if let DataType::List(inner_values, _) = item
+                && let (Some(DataType::Symbol(check, _)), Some(inner_value)) =
+                    (inner_values.first(), inner_values.get(1))
+                && (check == "unquote" || check == "splice-unquote")
+            {
+                let inner_value = inner_value.clone();
+                self.push(Frame::Quasiquote {
+                    splice: check == "splice-unquote",
+                    items,
+                    index,
+                    result,
+                    scope: scope.clone(),
+                })?;
+                return Ok(Control::Eval(inner_value, scope, None));
+            }
 
-        match result {
-            Ok(v) => Ok(v),
-            Err(err) => {
-                let x = [DataType::String(err.msg)];
-                closure.func(&x)
+            result.push(item.clone());
+            index += 1;
+        }
+
+        Ok(Control::Return(DataType::List(result, None)))
+    }
+
+    /// Tries the clauses of a `match` from `state.index` onwards.
+    fn match_clauses(&mut self, state: MatchState) -> Result<Control, RuntimeError> {
+        for index in state.index..state.clauses.len() {
+            let clause = &state.clauses[index];
+            let Some(pattern_match) = match_pattern(&clause.pattern, &state.value)? else {
+                continue;
+            };
+
+            let mut env = Environment::new(Some(state.scope.env.clone()));
+            for (name, value) in pattern_match.bindings {
+                env.set(name, value);
             }
+
+            let mut checks = pattern_match.checks;
+            checks.reverse();
+            let (guard, body) = (clause.guard.clone(), clause.body.clone());
+
+            return self.match_checks(
+                MatchState { index, ..state },
+                Rc::new(RefCell::new(env)),
+                checks,
+                guard,
+                body,
+            );
         }
-    } else {
-        return Err(RuntimeError {
-            msg: "Incorrect usage of try!".to_string(),
-        });
+
+        Err(RuntimeError {
+            msg: format!("No match for {:?}", state.value),
+        })
     }
-}
 
-fn eval_quote(args: &[DataType]) -> Result<DataType, RuntimeError> {
-    if let Some(val) = args.get(0) {
-        return Ok(val.clone());
-    } else {
-        return Err(RuntimeError {
-            msg: "Incorrect usage of quote".to_string(),
-        });
+    /// Calls the remaining type guard predicates, then the `:when` guard,
+    /// before evaluating the body of the matched clause.
+    fn match_checks(
+        &mut self,
+        state: MatchState,
+        env: Rc<RefCell<Environment>>,
+        checks: Vec<(DataType, DataType)>,
+        guard: Option<DataType>,
+        body: DataType,
+    ) -> Result<Control, RuntimeError> {
+        let mut checks = checks;
+        let mut guard = guard;
+        let scope = Scope {
+            env: env.clone(),
+            repl_env: state.scope.repl_env.clone(),
+        };
+
+        let check = if let Some((predicate, value)) = checks.pop() {
+            let quoted = DataType::List(
+                vec![DataType::Symbol("quote".to_string(), None), value],
+                None,
+            );
+            DataType::List(vec![predicate, quoted], None)
+        } else if let Some(guard) = guard.take() {
+            guard
+        } else {
+            return Ok(Control::Eval(body, scope, state.recur));
+        };
+
+        self.push(Frame::MatchCheck {
+            state,
+            env,
+            checks,
+            guard,
+            body,
+        })?;
+        Ok(Control::Eval(check, scope, None))
     }
-}
 
-fn eval_quasiquote(
-    args: &[DataType],
-    env: Rc<RefCell<Environment>>,
-    repl_env: Rc<RefCell<Environment>>,
-) -> Result<DataType, RuntimeError> {
-    if let Some(DataType::List(list)) = args.get(0) {
-        let mut result = vec![];
-        for value in list {
-            if let DataType::List(inner_values) = value {
-                match (inner_values.get(0), inner_values.get(1)) {
-                    (Some(DataType::Symbol(check)), Some(inner_value)) if check == "unquote" => {
-                        result.push(eval(inner_value, env.clone(), repl_env.clone())?);
-                    }
-                    (Some(DataType::Symbol(check)), Some(inner_values))
-                        if check == "splice-unquote" =>
-                    {
-                        let Ok(DataType::List(inner_values)) =
-                            eval(inner_values, env.clone(), repl_env.clone())
-                        else {
-                            return Err(RuntimeError {
-                                msg: "List not given to splice-unquote".to_string(),
-                            });
-                        };
-                        for inner_value in inner_values {
-                            result.push(inner_value);
-                        }
-                    }
-                    _ => {
-                        result.push(value.clone());
-                    }
+    /// Calls `function` from `env`, running closures in tail position so that
+    /// calling one doesn't grow the stack.
+    fn call(
+        &mut self,
+        function: DataType,
+        args: Vec<DataType>,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Control, RuntimeError> {
+        match &function {
+            DataType::Closure(closure) => {
+                let closure = closure.clone();
+                self.allocate(1 + args.len() as u64)?;
+                if let Some(name) = &closure.name {
+                    self.profile_call(name)?;
                 }
-            } else {
-                result.push(value.clone());
+                let (ast, env) = closure.prepare_tail_call(&args)?;
+                let ast = ast.clone();
+                let scope = Scope {
+                    env,
+                    repl_env: closure.repl_env.clone(),
+                };
+
+                Ok(Control::Eval(
+                    ast,
+                    scope,
+                    Some(Rc::new(RecurTarget::Closure(closure))),
+                ))
             }
-        }
-        Ok(DataType::List(result))
-    } else {
-        return Err(RuntimeError {
-            msg: "Incorrect usage of quote".to_string(),
-        });
-    }
-}
 
-fn prepare_tail_call_let<'a>(
-    args: &'a [DataType],
-    env: Rc<RefCell<Environment>>,
-) -> Result<(&'a DataType, Environment), RuntimeError> {
-    if let (Some(DataType::List(children)), Some(data)) = (args.get(0), args.get(1)) {
-        let mut new_env = Environment::new(Some(env.clone()));
-        let mut i = 0;
+            DataType::NativeFunction(function) => {
+                let mut context = Context::new(env);
+                let value = function.call(&mut context, &args)?;
+                self.allocate(allocation_size(&value))?;
+                Ok(Control::Return(value))
+            }
 
-        loop {
-            match children.get(i) {
-                Some(DataType::Symbol(val1)) => {
-                    if let Some(val2) = children.get(i + 1) {
-                        new_env.set(val1.to_owned(), val2.clone());
-                    } else {
+            DataType::Builtin(builtin) => self.call_builtin(*builtin, args, env),
+
+            DataType::MultiFn(multi) => match multi.dispatch {
+                Dispatch::Type => {
+                    let Some(first) = args.first() else {
                         return Err(RuntimeError {
-                            msg: "Each symbol in a let* environment should have a value"
-                                .to_string(),
+                            msg: format!("No arguments given to {}", multi.name),
                         });
                     };
+
+                    let dispatch_value = DataType::Keyword(first.type_name().to_string());
+                    self.call_method(multi, &dispatch_value, args, env)
+                }
+                Dispatch::Function(ref dispatch) => {
+                    let dispatch = dispatch.clone();
+                    self.push(Frame::Dispatch {
+                        multi: multi.clone(),
+                        args: args.clone(),
+                        env: env.clone(),
+                    })?;
+                    self.call(dispatch, args, env)
+                }
+            },
+
+            DataType::Continuation(continuation) => {
+                let [value] = <[DataType; 1]>::try_from(args).map_err(|args| RuntimeError {
+                    msg: format!("Continuations take 1 argument, got {}", args.len()),
+                })?;
+
+                self.push(Frame::Reset)?;
+                for frame in &continuation.frames {
+                    self.push(frame.clone())?;
                 }
+                Ok(Control::Return(value))
+            }
 
-                Some(_) => {
+            _ => Err(RuntimeError {
+                msg: "Cannot call list as function!".to_string(),
+            }),
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        multi: &MultiFn,
+        dispatch_value: &DataType,
+        args: Vec<DataType>,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Control, RuntimeError> {
+        match multi.get_method(dispatch_value) {
+            Some(method) => self.call(method, args, env),
+            None => Err(RuntimeError {
+                msg: format!(
+                    "No method in multimethod {} for dispatch value {:?}",
+                    multi.name, dispatch_value
+                ),
+            }),
+        }
+    }
+
+    fn call_builtin(
+        &mut self,
+        builtin: Builtin,
+        args: Vec<DataType>,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Control, RuntimeError> {
+        match builtin {
+            Builtin::Apply => {
+                let Some((function, rest)) = args.split_first() else {
                     return Err(RuntimeError {
-                        msg: "Invalid symbol to set in let*".to_string(),
+                        msg: "Wrong arguments for apply".to_string(),
                     });
-                }
+                };
 
-                None => {
-                    break;
+                let mut call_args = vec![];
+                for val in rest {
+                    match val {
+                        DataType::List(values, _) | DataType::Vector(values, _) => {
+                            call_args.extend(values.iter().cloned());
+                        }
+                        DataType::Generator(generator) => call_args.extend(generator.collect()?),
+                        _ => call_args.push(val.clone()),
+                    }
                 }
+
+                self.call(function.clone(), call_args, env)
+            }
+
+            Builtin::Map => {
+                let (Some(function), Some(items)) = (args.first(), args.get(1)) else {
+                    return Err(RuntimeError {
+                        msg: "Wrong arguments for map".to_string(),
+                    });
+                };
+                let mut items = match items {
+                    DataType::List(items, _) | DataType::Vector(items, _) => items.clone(),
+                    DataType::Generator(generator) => generator.collect()?,
+                    _ => {
+                        return Err(RuntimeError {
+                            msg: "Wrong arguments for map".to_string(),
+                        });
+                    }
+                };
+                items.reverse();
+                self.map_next(function.clone(), items, vec![], env)
             }
 
-            i += 2;
+            Builtin::Swap | Builtin::SwapVals => {
+                let (Some(DataType::Atom(atom)), Some(function)) = (args.first(), args.get(1))
+                else {
+                    return Err(RuntimeError {
+                        msg: format!("Incorrect arguments to {}", builtin.name()),
+                    });
+                };
+
+                let mut call_args = vec![atom.get()];
+                call_args.extend(args[2..].iter().cloned());
+
+                self.push(Frame::Swap {
+                    atom: atom.clone(),
+                    vals: builtin == Builtin::SwapVals,
+                    env: env.clone(),
+                })?;
+                self.call(function.clone(), call_args, env)
+            }
         }
-        return Ok((data, new_env));
-    } else {
-        return Err(RuntimeError {
-            msg: "Incorrect arguments for let*".to_string(),
-        });
     }
-}
 
-fn prepare_tail_call_do<'a>(
-    args: &'a [DataType],
-    env: Rc<RefCell<Environment>>,
-    repl_env: Rc<RefCell<Environment>>,
-) -> Result<&'a DataType, RuntimeError> {
-    for child in &args[..args.len() - 1] {
-        eval(child, env.clone(), repl_env.clone())?;
+    /// Calls `function` on the next item, where `items` is kept in reverse so
+    /// the next one can be popped off the end.
+    fn map_next(
+        &mut self,
+        function: DataType,
+        items: Vec<DataType>,
+        results: Vec<DataType>,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Control, RuntimeError> {
+        let mut items = items;
+        let Some(item) = items.pop() else {
+            return Ok(Control::Return(DataType::List(results, None)));
+        };
+
+        self.push(Frame::Map {
+            function: function.clone(),
+            items,
+            results,
+            env: env.clone(),
+        })?;
+        self.call(function, vec![item], env)
     }
-    if let Some(final_child) = args.last() {
-        Ok(final_child)
-    } else {
+}
+
+fn bind_recur(
+    names: &[String],
+    args: Vec<DataType>,
+    outer_env: Rc<RefCell<Environment>>,
+) -> Result<Environment, RuntimeError> {
+    if names.len() != args.len() {
         return Err(RuntimeError {
-            msg: "No arguments given for do".to_string(),
+            msg: format!(
+                "Mismatched argument count to recur, expected {} got {}",
+                names.len(),
+                args.len()
+            ),
         });
     }
+
+    let mut new_env = Environment::new(Some(outer_env));
+    for (name, arg) in names.iter().zip(args) {
+        new_env.set(name.clone(), arg);
+    }
+
+    Ok(new_env)
 }
 
-fn prepare_tail_call_if<'a>(
-    args: &'a [DataType],
-    env: Rc<RefCell<Environment>>,
-    repl_env: Rc<RefCell<Environment>>,
-) -> Result<&'a DataType, RuntimeError> {
-    let Some(condition) = args.get(0) else {
-        return Err(RuntimeError {
-            msg: "No condition for if expression".to_string(),
-        });
-    };
-    match eval(condition, env.clone(), repl_env.clone())? {
-        DataType::Bool(false) | DataType::Nil() => {
-            if let Some(arg) = args.get(2) {
-                return Ok(arg);
-            } else {
-                return Ok(&DataType::Nil());
+/// How far into nested forms `check_recur` looks before leaving the rest to
+/// the evaluator, so that deeply nested data can't overflow the Rust stack.
+const MAX_CHECKED_DEPTH: usize = 1_000;
+
+/// Checks, before a form is run, that every `recur` in it is in tail position
+/// of a `loop` or `fn*` and passes the right number of arguments. `arity` is
+/// that of the innermost `loop` or `fn*`, if any.
+///
+/// Macros aren't expanded here, so their arguments are skipped. A `recur` in
+/// the code they produce is caught by the evaluator when it's reached.
+fn check_recur(
+    ast: &DataType,
+    arity: Option<usize>,
+    tail: bool,
+    env: &Rc<RefCell<Environment>>,
+    depth: usize,
+) -> Result<(), RuntimeError> {
+    if depth > MAX_CHECKED_DEPTH {
+        return Ok(());
+    }
+
+    let children = match ast {
+        DataType::List(children, _) => children,
+        DataType::Vector(children, _) => {
+            for child in children {
+                check_recur(child, arity, false, env, depth + 1)?;
             }
+            return Ok(());
         }
-        _ => {
-            if let Some(arg) = args.get(1) {
-                return Ok(arg);
-            } else {
+        DataType::Dictionary(dict, _) => {
+            for child in dict.values() {
+                check_recur(child, arity, false, env, depth + 1)?;
+            }
+            return Ok(());
+        }
+        _ => return Ok(()),
+    };
+
+    let Some(DataType::Symbol(head, _)) = children.first() else {
+        for child in children {
+            check_recur(child, arity, false, env, depth + 1)?;
+        }
+        return Ok(());
+    };
+
+    match head.as_str() {
+        "recur" => {
+            let Some(arity) = arity else {
+                return Err(RuntimeError {
+                    msg: "recur used outside of loop or fn*".to_string(),
+                });
+            };
+            if !tail {
+                return Err(RuntimeError {
+                    msg: "Can only recur from tail position".to_string(),
+                });
+            }
+            if children.len() - 1 != arity {
                 return Err(RuntimeError {
-                    msg: "No body for if expression".to_string(),
+                    msg: format!(
+                        "Mismatched argument count to recur, expected {} got {}",
+                        arity,
+                        children.len() - 1
+                    ),
                 });
             }
+            for child in &children[1..] {
+                check_recur(child, Some(arity), false, env, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        "quote" | "quasiquote" | "defmethod" | "extend-type" | "defrecord" | "deftype"
+        | "with-open" => Ok(()),
+
+        "fn*" => match (children.get(1), children.get(2)) {
+            (Some(DataType::List(params, _)), Some(body)) => {
+                let arity = params
+                    .iter()
+                    .filter(|param| !matches!(param, DataType::Symbol(name, _) if name == "&"))
+                    .count();
+                check_recur(body, Some(arity), true, env, depth + 1)
+            }
+            _ => Ok(()),
+        },
+
+        "match" => {
+            if let Some(value) = children.get(1) {
+                check_recur(value, arity, false, env, depth + 1)?;
+            }
+            for clause in parse_clauses(children.get(2..).unwrap_or_default())? {
+                if let Some(guard) = clause.guard {
+                    check_recur(&guard, arity, false, env, depth + 1)?;
+                }
+                check_recur(&clause.body, arity, tail, env, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        "loop" | "let*" => {
+            let mut count = 0;
+            if let Some(DataType::List(bindings, _) | DataType::Vector(bindings, _)) =
+                children.get(1)
+            {
+                count = bindings.len() / 2;
+                for value in bindings.iter().skip(1).step_by(2) {
+                    check_recur(value, arity, false, env, depth + 1)?;
+                }
+            }
+            match children.get(2) {
+                Some(body) if head == "let*" => check_recur(body, arity, tail, env, depth + 1),
+                Some(body) => check_recur(body, Some(count), true, env, depth + 1),
+                None => Ok(()),
+            }
+        }
+
+        "def!" | "defmacro!" => match children.get(2) {
+            Some(value) => check_recur(value, arity, false, env, depth + 1),
+            None => Ok(()),
+        },
+
+        // Neither the body nor the handler is in tail position, since the
+        // try has to stay around to catch what they throw
+        "try*" => {
+            for child in &children[1..] {
+                check_recur(child, arity, false, env, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        "if" => {
+            for (i, child) in children[1..].iter().enumerate() {
+                check_recur(child, arity, tail && i > 0, env, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        "do" => {
+            let last = children.len() - 1;
+            for (i, child) in children.iter().enumerate().skip(1) {
+                check_recur(child, arity, tail && i == last, env, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        _ => {
+            // Only a known function's arguments are certain to be evaluated
+            // as they are; anything else may be a macro
+            match env.borrow().get(head) {
+                Some(DataType::Closure(ref function)) if !function.is_macro => {}
+                Some(DataType::NativeFunction(_) | DataType::Builtin(_)) => {}
+                _ => return Ok(()),
+            }
+
+            for child in &children[1..] {
+                check_recur(child, arity, false, env, depth + 1)?;
+            }
+            Ok(())
         }
     }
 }
@@ -407,11 +1582,11 @@ fn eval_closure(
     env: Rc<RefCell<Environment>>,
     repl_env: Rc<RefCell<Environment>>,
 ) -> Result<DataType, RuntimeError> {
-    if let Some(DataType::List(params)) = args.get(0) {
+    if let Some(DataType::List(params, _)) = args.first() {
         let param_names = params
             .iter()
             .map(|param| {
-                if let DataType::Symbol(param_name) = param {
+                if let DataType::Symbol(param_name, _) = param {
                     Ok(param_name.clone())
                 } else {
                     Err(RuntimeError {
@@ -429,56 +1604,159 @@ fn eval_closure(
             });
         };
 
-        return Ok(DataType::Closure(Closure {
+        Ok(DataType::Closure(Closure {
+            name: None,
             ast: Box::new(closure_body_ref.clone()),
             params: param_names,
             env: closure_env.clone(),
             repl_env: repl_env.clone(),
             is_macro: false,
-        }));
+            meta: None,
+        }))
     } else {
-        return Err(RuntimeError {
+        Err(RuntimeError {
             msg: "Expected parameter list for function".to_string(),
-        });
+        })
+    }
+}
+
+impl Generator {
+    /// The value `offset` places after this handle's position, running the
+    /// generator until it has been produced. Returns `None` if the generator
+    /// finishes first.
+    pub fn peek(&self, offset: usize) -> Result<Option<DataType>, RuntimeError> {
+        let index = self.position.get() + offset;
+
+        loop {
+            if let Some(value) = self.state.borrow().values.get(index) {
+                return Ok(Some(value.clone()));
+            }
+
+            if !resume_generator(&self.state)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Takes the next value, moving this handle past it.
+    pub fn next(&self) -> Result<Option<DataType>, RuntimeError> {
+        let value = self.peek(0)?;
+        if value.is_some() {
+            self.position.set(self.position.get() + 1);
+        }
+
+        Ok(value)
+    }
+
+    /// Runs the generator to the end, returning every value from this
+    /// handle's position onwards.
+    pub fn collect(&self) -> Result<Vec<DataType>, RuntimeError> {
+        while resume_generator(&self.state)? {}
+
+        let values = &self.state.borrow().values;
+        Ok(values
+            .get(self.position.get()..)
+            .unwrap_or_default()
+            .to_vec())
     }
 }
 
+/// Runs a generator until it yields another value, returning false if it
+/// finished instead.
+fn resume_generator(state: &Rc<RefCell<GeneratorState>>) -> Result<bool, RuntimeError> {
+    let resume = std::mem::replace(&mut state.borrow_mut().resume, Resume::Running);
+    match resume {
+        Resume::Finished => {
+            state.borrow_mut().resume = Resume::Finished;
+            return Ok(false);
+        }
+        Resume::Running => {
+            return Err(RuntimeError {
+                msg: "Generator is already running".to_string(),
+            });
+        }
+        Resume::Iterator(mut iter) => {
+            let next = iter.next();
+            let mut state = state.borrow_mut();
+            return match next {
+                Some(Ok(value)) => {
+                    state.values.push(value);
+                    state.resume = Resume::Iterator(iter);
+                    Ok(true)
+                }
+                Some(Err(e)) => {
+                    state.resume = Resume::Finished;
+                    Err(e)
+                }
+                None => {
+                    state.resume = Resume::Finished;
+                    Ok(false)
+                }
+            };
+        }
+        _ => {}
+    }
+    let function = state.borrow().function.clone();
+    let produced = state.borrow().values.len();
+
+    let mut machine = match function {
+        DataType::Closure(ref closure) => Machine::new(&closure.env),
+        _ => Machine::new(&Rc::new(RefCell::new(Environment::new(None)))),
+    };
+    machine.stack.push(Frame::Generator(state.clone()));
+
+    let control = match resume {
+        Resume::Suspended(continuation) => {
+            machine.stack.extend(continuation.frames.iter().cloned());
+            Ok(Control::Return(DataType::Nil()))
+        }
+        _ => {
+            let env = machine.env.clone();
+            machine.call(function, vec![], env)
+        }
+    };
+
+    if let Err(e) = control.and_then(|control| machine.run(control)) {
+        state.borrow_mut().resume = Resume::Finished;
+        return Err(e);
+    }
+
+    Ok(state.borrow().values.len() > produced)
+}
+
 impl Closure {
     pub fn func(&self, args: &[DataType]) -> Result<DataType, RuntimeError> {
-        let (ast, environment) = self.prepare_tail_call(args)?;
+        apply(&DataType::Closure(self.clone()), args, &self.env)
+    }
 
-        eval(ast, environment, self.repl_env.clone())
+    /// Parameter names as rebound by `recur`, where a variadic parameter
+    /// takes a single list.
+    fn recur_names(&self) -> Vec<String> {
+        self.params
+            .iter()
+            .filter(|name| *name != "&")
+            .cloned()
+            .collect()
     }
 
     pub fn prepare_tail_call(
         &self,
         args: &[DataType],
     ) -> Result<(&DataType, Rc<RefCell<Environment>>), RuntimeError> {
+        let mut call_env = Environment::new(Some(self.env.clone()));
         let mut i = 0;
 
         loop {
             let (name, param) = match (self.params.get(i), args.get(i)) {
-                (Some(ampersand), Some(_)) if ampersand == "&" => {
+                (Some(ampersand), _) if ampersand == "&" => {
                     let Some(name) = self.params.get(i + 1) else {
                         return Err(RuntimeError {
                             msg: "& found in closure without variadic argument name".to_string(),
                         });
                     };
 
-                    let mut children = vec![];
-                    let mut i2 = 0;
-
-                    loop {
-                        let Some(param) = args.get(i2) else {
-                            break;
-                        };
-                        children.push(param.clone());
-                        i2 += 1;
-                    }
-
-                    self.env
-                        .borrow_mut()
-                        .set(name.to_owned(), DataType::List(children));
+                    let children = args.get(i..).unwrap_or_default().to_vec();
+                    call_env.set(name.to_owned(), DataType::List(children, None));
 
                     break;
                 }
@@ -496,10 +1774,10 @@ impl Closure {
                 }
             };
 
-            self.env.borrow_mut().set(name.to_owned(), param.clone());
+            call_env.set(name.to_owned(), param.clone());
             i += 1;
         }
 
-        Ok((&self.ast, self.env.clone()))
+        Ok((&self.ast, Rc::new(RefCell::new(call_env))))
     }
 }
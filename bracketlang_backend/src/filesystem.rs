@@ -0,0 +1,251 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, Cursor, Write},
+    rc::Rc,
+};
+
+/// Everything the file builtins need from the file system, so that a host or
+/// the wasm build can swap in its own.
+pub trait FileSystem {
+    fn read_to_string(&self, path: &str) -> io::Result<String>;
+    fn open_read(&self, path: &str) -> io::Result<Box<dyn BufRead>>;
+    fn open_write(&self, path: &str, append: bool) -> io::Result<Box<dyn Write>>;
+    fn exists(&self, path: &str) -> bool;
+    /// The names of the entries in the directory `path`, sorted.
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>>;
+    /// Creates the directory `path` along with any missing parents.
+    fn create_dir(&self, path: &str) -> io::Result<()>;
+    /// Deletes a file or an empty directory.
+    fn remove(&self, path: &str) -> io::Result<()>;
+
+    fn write(&self, path: &str, contents: &str, append: bool) -> io::Result<()> {
+        self.open_write(path, append)?
+            .write_all(contents.as_bytes())
+    }
+}
+
+/// The host's real file system.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct OsFileSystem;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSystem for OsFileSystem {
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn open_read(&self, path: &str) -> io::Result<Box<dyn BufRead>> {
+        Ok(Box::new(io::BufReader::new(std::fs::File::open(path)?)))
+    }
+
+    fn open_write(&self, path: &str, append: bool) -> io::Result<Box<dyn Write>> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(Box::new(io::BufWriter::new(file)))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        std::path::Path::new(path).exists()
+    }
+
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in std::fs::read_dir(path)? {
+            names.push(entry?.file_name().to_string_lossy().to_string());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        if std::path::Path::new(path).is_dir() {
+            std::fs::remove_dir(path)
+        } else {
+            std::fs::remove_file(path)
+        }
+    }
+}
+
+/// A file system held entirely in memory, used by the wasm build. Clones
+/// share the same files.
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    files: Rc<RefCell<BTreeMap<String, Vec<u8>>>>,
+    dirs: Rc<RefCell<BTreeSet<String>>>,
+}
+
+thread_local! {
+    static SHARED: MemoryFileSystem = MemoryFileSystem::new();
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+}
+
+/// Removes `.` components and redundant slashes, so `./a//b` and `a/b` name
+/// the same file.
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> MemoryFileSystem {
+        Self::default()
+    }
+
+    /// The file system every wasm environment starts with, which the page
+    /// fills in through `vfs_write`.
+    pub fn shared() -> MemoryFileSystem {
+        SHARED.with(MemoryFileSystem::clone)
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        let prefix = format!("{}/", path);
+        path.is_empty()
+            || self.dirs.borrow().contains(path)
+            || self
+                .files
+                .borrow()
+                .keys()
+                .any(|file| file.starts_with(&prefix))
+    }
+
+    /// Every file and directory, as normalized paths.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = self.files.borrow().keys().cloned().collect::<Vec<_>>();
+        paths.extend(self.dirs.borrow().iter().cloned());
+        paths.sort();
+        paths
+    }
+}
+
+/// Writes straight through to a file in a `MemoryFileSystem`.
+struct MemoryWriter {
+    files: Rc<RefCell<BTreeMap<String, Vec<u8>>>>,
+    path: String,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.files
+            .borrow_mut()
+            .entry(self.path.clone())
+            .or_default()
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        match self.files.borrow().get(&normalize(path)) {
+            Some(contents) => String::from_utf8(contents.clone())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn open_read(&self, path: &str) -> io::Result<Box<dyn BufRead>> {
+        match self.files.borrow().get(&normalize(path)) {
+            Some(contents) => Ok(Box::new(Cursor::new(contents.clone()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn open_write(&self, path: &str, append: bool) -> io::Result<Box<dyn Write>> {
+        let path = normalize(path);
+        if self.is_dir(&path) {
+            return Err(io::Error::other(format!("{} is a directory", path)));
+        }
+
+        let mut files = self.files.borrow_mut();
+        let contents = files.entry(path.clone()).or_default();
+        if !append {
+            contents.clear();
+        }
+
+        Ok(Box::new(MemoryWriter {
+            files: self.files.clone(),
+            path,
+        }))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        let path = normalize(path);
+        self.files.borrow().contains_key(&path) || self.is_dir(&path)
+    }
+
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let path = normalize(path);
+        if !self.is_dir(&path) {
+            return Err(not_found(&path));
+        }
+
+        let prefix = if path.is_empty() {
+            path
+        } else {
+            format!("{}/", path)
+        };
+        let mut names = BTreeSet::new();
+        for entry in self.paths() {
+            if let Some(rest) = entry.strip_prefix(&prefix)
+                && let Some(name) = rest.split('/').next()
+                && !name.is_empty()
+            {
+                names.insert(name.to_string());
+            }
+        }
+
+        Ok(names.into_iter().collect())
+    }
+
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        let path = normalize(path);
+        if self.files.borrow().contains_key(&path) {
+            return Err(io::Error::other(format!("{} is a file", path)));
+        }
+
+        let mut dirs = self.dirs.borrow_mut();
+        let mut parent = String::new();
+        for part in path.split('/') {
+            if !parent.is_empty() {
+                parent.push('/');
+            }
+            parent.push_str(part);
+            dirs.insert(parent.clone());
+        }
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        let path = normalize(path);
+        if self.files.borrow_mut().remove(&path).is_some() {
+            return Ok(());
+        }
+
+        if !self.is_dir(&path) {
+            return Err(not_found(&path));
+        }
+        if !self.list_dir(&path)?.is_empty() {
+            return Err(io::Error::other(format!("{} is not empty", path)));
+        }
+        self.dirs.borrow_mut().remove(&path);
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    path::Path,
+    rc::Rc,
+    sync::{Arc, atomic::AtomicBool},
+};
+
+use crate::{
+    capability::{Capabilities, Capability, Limits},
+    context::Context,
+    create_default_repl_env,
+    evaluator::{RuntimeError, apply, eval},
+    filesystem::FileSystem,
+    namespace::current_namespace,
+    native::{IntoNativeFunction, NativeFunction},
+    read, read_all,
+    runtime::Runtime,
+    variable_type::{DataType, Environment},
+};
+
+/// Definitions written in the language itself, run by every new interpreter.
+const PREAMBLE: [&str; 6] = [
+    "(def! not (fn* (a) (if a false true)))",
+    "(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \"\\nnil)\")))))",
+    "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
+    "(defmacro! time (fn* (expr) (list 'time-fn (list 'fn* '() expr))))",
+    "(defmacro! bench (fn* (expr & opts) (concat (list 'bench-fn (list 'fn* '() expr)) opts)))",
+    "(defmacro! profile (fn* (expr & opts) (concat (list 'profile-fn (list 'fn* '() expr)) opts)))",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(String),
+    Runtime(String),
+    Io(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg) => write!(f, "PARSE ERROR: {}", msg),
+            Error::Runtime(msg) => write!(f, "RUNTIME ERROR: {}", msg),
+            Error::Io(msg) => write!(f, "IO ERROR: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// An interpreter with the default environment, for embedding in a host
+/// program.
+pub struct Interpreter {
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        let env = create_default_repl_env();
+
+        for definition in PREAMBLE {
+            let ast = read(definition.to_string()).expect("The preamble should parse");
+            if let Err(e) = eval(&ast, env.clone(), env.clone()) {
+                panic!("Error in core function definition! {}", e.msg);
+            }
+        }
+
+        Self { env }
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Self::default()
+    }
+
+    pub fn env(&self) -> Rc<RefCell<Environment>> {
+        self.env.clone()
+    }
+
+    pub fn runtime(&self) -> Rc<Runtime> {
+        self.env
+            .borrow()
+            .runtime()
+            .expect("The interpreter's environment should be a root")
+    }
+
+    /// Evaluates every form in `source`, returning the value of the last.
+    pub fn eval_str(&self, source: &str) -> Result<DataType, Error> {
+        let forms = read_all(source.to_string()).map_err(|e| Error::Parse(e.msg))?;
+
+        self.runtime().reset_usage();
+        let mut result = DataType::Nil();
+        for form in forms {
+            // Each form sees any `ns` switch made by the one before
+            let env = current_namespace(&self.env);
+            result = eval(&form, env.clone(), env).map_err(|e| Error::Runtime(e.msg))?;
+        }
+        Ok(result)
+    }
+
+    pub fn eval_file(&self, path: impl AsRef<Path>) -> Result<DataType, Error> {
+        let path = path.as_ref();
+        let source = self
+            .runtime()
+            .filesystem()
+            .read_to_string(&path.to_string_lossy())
+            .map_err(|e| Error::Io(format!("Couldn't load {}: {}", path.display(), e)))?;
+
+        self.eval_str(&source)
+    }
+
+    /// Calls the global function `name` with `args`.
+    pub fn call(&self, name: &str, args: &[DataType]) -> Result<DataType, Error> {
+        let Some(function) = self.get_global(name) else {
+            return Err(Error::Runtime(format!("Unknown symbol: {}", name)));
+        };
+
+        self.runtime().reset_usage();
+        apply(&function, args, &self.env).map_err(|e| Error::Runtime(e.msg))
+    }
+
+    /// Makes a Rust closure callable from the program as `name`, converting
+    /// its arguments and return value with `FromDataType` and `IntoDataType`.
+    pub fn register_fn<Args>(&self, name: &str, function: impl IntoNativeFunction<Args>) {
+        let function = function.into_native_function(name);
+        self.set_global(name, DataType::NativeFunction(function));
+    }
+
+    /// Like `register_fn`, for functions that take their arguments as they
+    /// are and need the interpreter's `Context`.
+    pub fn register_native(
+        &self,
+        name: &str,
+        function: impl Fn(&mut Context, &[DataType]) -> Result<DataType, RuntimeError> + 'static,
+    ) {
+        self.set_global(
+            name,
+            DataType::NativeFunction(NativeFunction::new(name, function)),
+        );
+    }
+
+    pub fn set_global(&self, name: &str, value: DataType) {
+        self.env.borrow_mut().set(name.to_string(), value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<DataType> {
+        self.env.borrow().get(&name.to_string())
+    }
+
+    /// Sends everything the program prints to `sink` instead of stdout.
+    pub fn set_stdout(&self, sink: impl FnMut(&str) + 'static) {
+        self.runtime().set_stdout(Box::new(sink));
+    }
+
+    /// Reads the program's input from `source`, which is given the prompt to
+    /// show, instead of stdin.
+    pub fn set_stdin(&self, source: impl FnMut(&str) -> Option<String> + 'static) {
+        self.runtime().set_stdin(Box::new(source));
+    }
+
+    /// Sends every file builtin, and `require`, through `filesystem`.
+    pub fn set_filesystem(&self, filesystem: impl FileSystem + 'static) {
+        self.runtime().set_filesystem(Rc::new(filesystem));
+    }
+
+    /// Changes which groups of builtins the program may use. Calling a
+    /// builtin from a disabled group raises an error.
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        self.runtime().set_capabilities(capabilities);
+    }
+
+    /// Sets the budgets each call to `eval_str`, `eval_file` or `call` gets.
+    pub fn set_limits(&self, limits: Limits) {
+        self.runtime().set_limits(limits);
+    }
+
+    /// Lets the program take `fuel` more steps in total, across however many
+    /// evaluations, or any number if `None`. Running out stops evaluation
+    /// with an error `try*` can't catch.
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.runtime().set_fuel(fuel);
+    }
+
+    /// Caps how deep the program may recurse, or lets it use as much memory
+    /// as it needs if `None`, which is the default. Native functions calling
+    /// back into the program, like `swap!` or watches, are separately limited
+    /// to 50 levels either way, since those use the Rust stack.
+    pub fn set_max_depth(&self, max_depth: Option<usize>) {
+        self.runtime().set_max_depth(max_depth);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.runtime().fuel()
+    }
+
+    /// A flag that, once set from any thread, stops the current evaluation
+    /// with an `Interrupted` error.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.runtime().interrupt_flag()
+    }
+
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::default()
+    }
+}
+
+/// Sets up an `Interpreter` with a chosen set of capabilities and limits, for
+/// running code that isn't trusted.
+#[derive(Default)]
+pub struct InterpreterBuilder {
+    capabilities: Capabilities,
+    limits: Limits,
+    max_depth: Option<usize>,
+}
+
+impl InterpreterBuilder {
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn allow(mut self, capability: Capability) -> Self {
+        self.capabilities = self.capabilities.with(capability);
+        self
+    }
+
+    pub fn deny(mut self, capability: Capability) -> Self {
+        self.capabilities = self.capabilities.without(capability);
+        self
+    }
+
+    /// How deep the program may recurse before failing with an error. This
+    /// doesn't lift the 50-level limit on native functions calling back into
+    /// the program.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.limits.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn max_allocations(mut self, max_allocations: u64) -> Self {
+        self.limits.max_allocations = Some(max_allocations);
+        self
+    }
+
+    /// The most bytes the program may write through `prn`.
+    pub fn max_output(mut self, max_output: usize) -> Self {
+        self.limits.max_output = Some(max_output);
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let interpreter = Interpreter::new();
+        interpreter.set_capabilities(self.capabilities);
+        interpreter.set_limits(self.limits);
+        interpreter.set_max_depth(self.max_depth);
+        interpreter
+    }
+}
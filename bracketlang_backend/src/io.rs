@@ -0,0 +1,85 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    evaluator::RuntimeError,
+    multimethod::{list, symbol},
+    variable_type::DataType,
+};
+
+/// An open file, as returned by `open-reader` and `open-writer`.
+pub enum Handle {
+    Reader(Box<dyn BufRead>),
+    Writer(Box<dyn Write>),
+    Closed,
+}
+
+impl Handle {
+    /// Flushes a writer and drops the file. Closing twice is harmless.
+    pub fn close(&mut self) -> Result<(), RuntimeError> {
+        let handle = std::mem::replace(self, Handle::Closed);
+        if let Handle::Writer(mut writer) = handle {
+            writer.flush().map_err(|e| RuntimeError {
+                msg: format!("Couldn't write file: {}", e),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// `(with-open [name init ...] body...)`, which binds each `name` like `let*`
+/// and closes them in reverse order once the body is done, even if it throws.
+pub fn expand_with_open(args: &[DataType]) -> Result<DataType, RuntimeError> {
+    let Some(DataType::Vector(bindings, _)) = args.first() else {
+        return Err(RuntimeError {
+            msg: "with-open expects a vector of bindings".to_string(),
+        });
+    };
+    if bindings.len() % 2 != 0 {
+        return Err(RuntimeError {
+            msg: "with-open expects an even number of binding forms".to_string(),
+        });
+    }
+
+    let mut body = vec![symbol("do")];
+    body.extend(args[1..].iter().cloned());
+    if body.len() == 1 {
+        body.push(DataType::Nil());
+    }
+    let mut form = list(body);
+
+    for binding in bindings.chunks(2).rev() {
+        let name = binding[0].clone();
+        if !matches!(name, DataType::Symbol(..)) {
+            return Err(RuntimeError {
+                msg: format!("Can't bind {:?} in with-open", name),
+            });
+        }
+        let close = list(vec![symbol("close"), name.clone()]);
+
+        let guarded = list(vec![
+            symbol("try*"),
+            form,
+            list(vec![
+                symbol("fn*"),
+                list(vec![symbol("with-open-error")]),
+                list(vec![
+                    symbol("do"),
+                    close.clone(),
+                    list(vec![symbol("throw"), symbol("with-open-error")]),
+                ]),
+            ]),
+        ]);
+
+        form = list(vec![
+            symbol("let*"),
+            DataType::Vector(vec![name, binding[1].clone()], None),
+            list(vec![
+                symbol("let*"),
+                DataType::Vector(vec![symbol("with-open-result"), guarded], None),
+                list(vec![symbol("do"), close, symbol("with-open-result")]),
+            ]),
+        ]);
+    }
+
+    Ok(form)
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use crate::{
+    datetime,
+    evaluator::RuntimeError,
+    scanner::{MAX_DEPTH, Scanner},
+    variable_type::DataType,
+};
+
+// Objects are read as dictionaries, arrays as vectors and null as nil. Object
+// keys become keywords unless `keywordize` is off, in which case they are
+// kept as strings. Either way a key is written back out as its bare name, so a
+// dictionary with both `:a` and `"a"` as keys can't be written.
+
+pub fn parse(text: &str, keywordize: bool) -> Result<DataType, RuntimeError> {
+    let mut scanner = Scanner::new(text);
+    let value = parse_value(&mut scanner, keywordize)?;
+
+    skip_whitespace(&mut scanner);
+    if !scanner.at_end() {
+        return Err(scanner.unexpected());
+    }
+    Ok(value)
+}
+
+fn skip_whitespace(scanner: &mut Scanner) {
+    scanner.take_while(|c| matches!(c, ' ' | '\t' | '\n' | '\r'));
+}
+
+fn parse_value(scanner: &mut Scanner, keywordize: bool) -> Result<DataType, RuntimeError> {
+    skip_whitespace(scanner);
+
+    match scanner.peek() {
+        Some('{') => parse_object(scanner, keywordize),
+        Some('[') => parse_array(scanner, keywordize),
+        Some('"') => Ok(DataType::String(parse_string(scanner)?)),
+        Some('-' | '0'..='9') => parse_number(scanner),
+        Some('a'..='z') => {
+            let start = scanner.position();
+            match scanner.take_while(|c| c.is_ascii_alphabetic()).as_str() {
+                "true" => Ok(DataType::Bool(true)),
+                "false" => Ok(DataType::Bool(false)),
+                "null" => Ok(DataType::Nil()),
+                word => Err(scanner.error_at(start, &format!("Unknown literal {}", word))),
+            }
+        }
+        _ => Err(scanner.unexpected()),
+    }
+}
+
+fn parse_object(scanner: &mut Scanner, keywordize: bool) -> Result<DataType, RuntimeError> {
+    scanner.enter()?;
+    scanner.expect('{')?;
+    let mut dict = HashMap::new();
+
+    skip_whitespace(scanner);
+    if scanner.eat('}') {
+        scanner.leave();
+        return Ok(DataType::Dictionary(dict, None));
+    }
+
+    loop {
+        skip_whitespace(scanner);
+        if scanner.peek() != Some('"') {
+            return Err(scanner.error("Expected a string key"));
+        }
+        let key = parse_string(scanner)?;
+        let key = if keywordize {
+            DataType::Keyword(key)
+        } else {
+            DataType::String(key)
+        };
+
+        skip_whitespace(scanner);
+        scanner.expect(':')?;
+        let value = parse_value(scanner, keywordize)?;
+        dict.insert(format!("{:?}", key), value);
+
+        skip_whitespace(scanner);
+        if scanner.eat('}') {
+            scanner.leave();
+            return Ok(DataType::Dictionary(dict, None));
+        }
+        scanner.expect(',')?;
+    }
+}
+
+fn parse_array(scanner: &mut Scanner, keywordize: bool) -> Result<DataType, RuntimeError> {
+    scanner.enter()?;
+    scanner.expect('[')?;
+    let mut items = vec![];
+
+    skip_whitespace(scanner);
+    if scanner.eat(']') {
+        scanner.leave();
+        return Ok(DataType::Vector(items, None));
+    }
+
+    loop {
+        items.push(parse_value(scanner, keywordize)?);
+
+        skip_whitespace(scanner);
+        if scanner.eat(']') {
+            scanner.leave();
+            return Ok(DataType::Vector(items, None));
+        }
+        scanner.expect(',')?;
+    }
+}
+
+fn parse_string(scanner: &mut Scanner) -> Result<String, RuntimeError> {
+    scanner.expect('"')?;
+    let mut string = String::new();
+
+    loop {
+        match scanner.next() {
+            Some('"') => return Ok(string),
+            Some('\\') => {
+                let escaped = match scanner.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => parse_unicode_escape(scanner)?,
+                    _ => return Err(scanner.error("Invalid escape sequence")),
+                };
+                string.push(escaped);
+            }
+            Some(c) if (c as u32) < 0x20 => {
+                return Err(scanner.error("Control character in string"));
+            }
+            Some(c) => string.push(c),
+            None => return Err(scanner.error("Unterminated string")),
+        }
+    }
+}
+
+fn parse_hex(scanner: &mut Scanner) -> Result<u32, RuntimeError> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let Some(digit) = scanner.peek().and_then(|c| c.to_digit(16)) else {
+            return Err(scanner.error("Invalid unicode escape"));
+        };
+        scanner.next();
+        code = code * 16 + digit;
+    }
+    Ok(code)
+}
+
+fn parse_unicode_escape(scanner: &mut Scanner) -> Result<char, RuntimeError> {
+    let code = parse_hex(scanner)?;
+
+    // A surrogate pair, written as two escapes
+    let code = if (0xD800..0xDC00).contains(&code) {
+        if !(scanner.eat('\\') && scanner.eat('u')) {
+            return Err(scanner.error("Unpaired surrogate in unicode escape"));
+        }
+        let low = parse_hex(scanner)?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(scanner.error("Unpaired surrogate in unicode escape"));
+        }
+        0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+    } else {
+        code
+    };
+
+    char::from_u32(code).ok_or_else(|| scanner.error("Invalid unicode escape"))
+}
+
+fn parse_number(scanner: &mut Scanner) -> Result<DataType, RuntimeError> {
+    let start = scanner.position();
+    let mut text = String::new();
+    let mut is_float = false;
+
+    if scanner.eat('-') {
+        text.push('-');
+    }
+
+    let digits = scanner.take_while(|c| c.is_ascii_digit());
+    if digits.is_empty() || (digits.len() > 1 && digits.starts_with('0')) {
+        return Err(scanner.error_at(start, "Invalid number"));
+    }
+    text.push_str(&digits);
+
+    if scanner.eat('.') {
+        let fraction = scanner.take_while(|c| c.is_ascii_digit());
+        if fraction.is_empty() {
+            return Err(scanner.error("Expected digits after decimal point"));
+        }
+        text.push('.');
+        text.push_str(&fraction);
+        is_float = true;
+    }
+
+    if let Some(e @ ('e' | 'E')) = scanner.peek() {
+        scanner.next();
+        text.push(e);
+        if let Some(sign @ ('+' | '-')) = scanner.peek() {
+            scanner.next();
+            text.push(sign);
+        }
+        let exponent = scanner.take_while(|c| c.is_ascii_digit());
+        if exponent.is_empty() {
+            return Err(scanner.error("Expected digits in exponent"));
+        }
+        text.push_str(&exponent);
+        is_float = true;
+    }
+
+    if !is_float && let Ok(num) = text.parse() {
+        return Ok(DataType::Integer(num));
+    }
+    match text.parse() {
+        Ok(num) => Ok(DataType::Float(num)),
+        Err(_) => Err(scanner.error_at(start, "Invalid number")),
+    }
+}
+
+/// Writes `value` as JSON, indenting nested values by `indent` spaces per
+/// level when given.
+pub fn stringify(value: &DataType, indent: Option<usize>) -> Result<String, RuntimeError> {
+    let mut out = String::new();
+    write_value(value, indent, 0, &mut out)?;
+    Ok(out)
+}
+
+fn write_string(string: &str, out: &mut String) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Turns a dictionary key such as `":a"` or `"\"a\""` back into `a`.
+pub fn key_name(key: &str) -> &str {
+    if let Some(name) = key.strip_prefix(':') {
+        name
+    } else if let Some(name) = key.strip_prefix('"').and_then(|key| key.strip_suffix('"')) {
+        name
+    } else {
+        key
+    }
+}
+
+fn newline(indent: Option<usize>, depth: usize, out: &mut String) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent * depth));
+    }
+}
+
+fn write_value(
+    value: &DataType,
+    indent: Option<usize>,
+    depth: usize,
+    out: &mut String,
+) -> Result<(), RuntimeError> {
+    if depth > MAX_DEPTH {
+        return Err(RuntimeError {
+            msg: "Collections are nested too deeply to write as JSON".to_string(),
+        });
+    }
+
+    match value {
+        DataType::Nil() => out.push_str("null"),
+        DataType::Bool(value) => out.push_str(&value.to_string()),
+        DataType::Integer(num) => out.push_str(&num.to_string()),
+        DataType::Float(num) if num.is_finite() => out.push_str(&format!("{:?}", num)),
+        DataType::String(string) => write_string(string, out),
+        DataType::Keyword(name) | DataType::Symbol(name, _) => write_string(name, out),
+        DataType::Instant(instant) => {
+            write_string(&datetime::format(*instant, datetime::ISO_PATTERN)?, out)
+        }
+
+        DataType::List(items, _) | DataType::Vector(items, _) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(indent, depth + 1, out);
+                write_value(item, indent, depth + 1, out)?;
+            }
+            if !items.is_empty() {
+                newline(indent, depth, out);
+            }
+            out.push(']');
+        }
+
+        DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => {
+            let mut keys = dict.keys().collect::<Vec<_>>();
+            keys.sort_by_key(|key| (key_name(key), *key));
+            if let Some(pair) = keys
+                .windows(2)
+                .find(|pair| key_name(pair[0]) == key_name(pair[1]))
+            {
+                return Err(RuntimeError {
+                    msg: format!(
+                        "Keys {} and {} would both be written as {:?}",
+                        pair[0],
+                        pair[1],
+                        key_name(pair[0])
+                    ),
+                });
+            }
+
+            out.push('{');
+            for (i, key) in keys.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(indent, depth + 1, out);
+                write_string(key_name(key), out);
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                write_value(&dict[*key], indent, depth + 1, out)?;
+            }
+            if !keys.is_empty() {
+                newline(indent, depth, out);
+            }
+            out.push('}');
+        }
+
+        _ => {
+            return Err(RuntimeError {
+                msg: format!("Can't write {:?} as JSON", value),
+            });
+        }
+    }
+
+    Ok(())
+}
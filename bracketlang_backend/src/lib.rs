@@ -1,26 +1,68 @@
 use std::{cell::RefCell, rc::Rc};
 
 use evaluator::eval;
+use native::NativeFunction;
 use reader::{ParseError, Reader, get_regex, tokenize};
-use variable_type::DataType;
+use variable_type::{Builtin, DataType};
 use wasm_bindgen::prelude::wasm_bindgen;
 
-use crate::{env::*, variable_type::Environment};
+use crate::{
+    capability::{Capability, Limits},
+    context::Context,
+    env::*,
+    filesystem::{FileSystem, MemoryFileSystem},
+    namespace::{LOAD_PATH, current_namespace},
+    runtime::Runtime,
+    variable_type::Environment,
+};
 
+mod atom;
+pub mod capability;
+pub mod context;
+mod csv;
+mod datetime;
+mod edn;
 mod env;
 mod evaluator;
+pub mod filesystem;
+mod interpreter;
+mod io;
+mod json;
+mod multimethod;
+mod namespace;
+pub mod native;
+mod pattern;
+#[cfg(not(target_arch = "wasm32"))]
+mod process;
+mod profile;
 mod reader;
+mod record;
+mod runtime;
+mod scanner;
+#[cfg(feature = "serde")]
+mod serde_support;
 pub mod variable_type;
 
 #[cfg(test)]
 mod tests;
 
+pub use evaluator::RuntimeError;
+pub use interpreter::{Error, Interpreter, InterpreterBuilder};
+#[cfg(feature = "serde")]
+pub use serde_support::{from_value, to_value};
+
 fn read(input: String) -> Result<DataType, ParseError> {
     let tokens = tokenize(input, get_regex());
     let mut reader = Reader::new(tokens);
     reader.read()
 }
 
+fn read_all(input: String) -> Result<Vec<DataType>, ParseError> {
+    let tokens = tokenize(input, get_regex());
+    let mut reader = Reader::new(tokens);
+    reader.read_all()
+}
+
 fn print(input: DataType) -> String {
     format!("{:?}", input)
 }
@@ -49,7 +91,8 @@ impl EnvironmentHolder {
 #[wasm_bindgen(module = "/helper_functions.js")]
 extern "C" {
     pub fn js_print(string: &str);
-    pub fn js_get_time() -> i32;
+    pub fn js_get_time() -> f64;
+    pub fn js_performance_now() -> f64;
 }
 
 #[wasm_bindgen]
@@ -57,21 +100,21 @@ extern "C" {
     pub fn prompt(string: &str) -> Option<String>;
 }
 
+/// How many steps one evaluation in the playground may take. It runs on the
+/// page's only thread, so a loop that never ends would otherwise freeze it.
+const PLAYGROUND_MAX_STEPS: u64 = 50_000_000;
+
 #[wasm_bindgen]
 pub fn create_default_env() -> EnvironmentHolder {
-    let repl_env = create_default_repl_env();
-
-    run_preamble("(def! not (fn* (a) (if a false true)))", repl_env.clone());
-    run_preamble(
-        "(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \"\\nnil)\")))))",
-        repl_env.clone(),
-    );
-    run_preamble(
-        "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
-        repl_env.clone(),
-    );
+    let interpreter = Interpreter::new();
+    interpreter.set_limits(Limits {
+        max_steps: Some(PLAYGROUND_MAX_STEPS),
+        ..Limits::default()
+    });
 
-    EnvironmentHolder { env: repl_env }
+    EnvironmentHolder {
+        env: interpreter.env(),
+    }
 }
 
 #[wasm_bindgen]
@@ -82,13 +125,47 @@ pub fn evaluate_string(input: &str, env: &mut EnvironmentHolder) {
     }
 }
 
+/// Creates or replaces a file in the playground's file system, which
+/// `slurp`, `load-file` and `require` read from.
+#[wasm_bindgen]
+pub fn vfs_write(path: &str, contents: &str) -> Result<(), String> {
+    MemoryFileSystem::shared()
+        .write(path, contents, false)
+        .map_err(|e| e.to_string())
+}
+
+#[wasm_bindgen]
+pub fn vfs_read(path: &str) -> Option<String> {
+    MemoryFileSystem::shared().read_to_string(path).ok()
+}
+
+/// The names in a directory of the playground's file system, with `""` being
+/// the top level.
+#[wasm_bindgen]
+pub fn vfs_list(path: &str) -> Result<Vec<String>, String> {
+    MemoryFileSystem::shared()
+        .list_dir(path)
+        .map_err(|e| e.to_string())
+}
+
+#[wasm_bindgen]
+pub fn vfs_mkdir(path: &str) -> Result<(), String> {
+    MemoryFileSystem::shared()
+        .create_dir(path)
+        .map_err(|e| e.to_string())
+}
+
 pub fn rep(input: String, repl_env: Rc<RefCell<Environment>>) -> Option<String> {
     let ast = match read(input) {
         Ok(r) => r,
         Err(e) => return Some(format!("PARSE ERROR: {}", e.msg)),
     };
 
-    let eval_result = match eval(&ast, repl_env.clone(), repl_env.clone()) {
+    if let Some(runtime) = repl_env.borrow().runtime() {
+        runtime.reset_usage();
+    }
+    let env = current_namespace(&repl_env);
+    let eval_result = match eval(&ast, env.clone(), env) {
         Ok(r) => r,
         Err(e) => return Some(format!("RUNTIME ERROR: {}", e.msg)),
     };
@@ -115,16 +192,32 @@ pub fn re(input: String, repl_env: Rc<RefCell<Environment>>) -> Result<DataType,
     Ok(result)
 }
 
-#[allow(unused_assignments)]
+/// Wraps a builtin, checking the capability it needs on every call so that a
+/// host can change what's allowed after the environment is made.
+fn core_function(function: &CoreFunction) -> DataType {
+    let (id, func) = (function.id, function.func);
+
+    let native = match Capability::of_builtin(id) {
+        Some(capability) => NativeFunction::new(id, move |ctx: &mut Context, args: &[DataType]| {
+            ctx.require(capability, id)?;
+            func(ctx, args)
+        }),
+        None => NativeFunction::new(id, func),
+    };
+    DataType::NativeFunction(native)
+}
+
 pub fn create_default_repl_env() -> Rc<RefCell<Environment>> {
-    let mut repl_env = Environment::new(None);
+    let mut repl_env = Environment::new_root(Runtime::new());
+    repl_env.set(
+        LOAD_PATH.to_string(),
+        DataType::List(vec![DataType::String(".".to_string())], None),
+    );
 
     macro_rules! set_function {
         ($($l:ident),*) => {
-            let mut i = 0;
             $ (
-                repl_env.set($l.id.to_string(), DataType::NativeFunction((i, &$l.func)));
-                i += 1;
+                repl_env.set($l.id.to_string(), core_function(&$l));
             )*
         };
     }
@@ -151,15 +244,17 @@ pub fn create_default_repl_env() -> Rc<RefCell<Environment>> {
         CHECK_ATOM,
         DEREF,
         RESET_ATOM,
-        SWAP_ATOM,
+        COMPARE_AND_SET,
+        ADD_WATCH,
+        REMOVE_WATCH,
+        SET_VALIDATOR,
+        GET_VALIDATOR,
         CONS,
         CONCAT,
         NTH,
         FIRST,
         REST,
         THROW,
-        APPLY,
-        MAP,
         CHECK_NIL,
         CHECK_TRUE,
         CHECK_FALSE,
@@ -168,10 +263,16 @@ pub fn create_default_repl_env() -> Rc<RefCell<Environment>> {
         CHECK_DICTIONARY,
         CHECK_SEQUENTIAL,
         SYMBOL,
+        KEYWORD,
+        CHECK_KEYWORD,
         DICTIONARY,
         VECTOR,
         ASSOC,
         DISSOC,
+        TRANSIENT,
+        CONJ_TRANSIENT,
+        ASSOC_TRANSIENT,
+        PERSISTENT,
         GET,
         CONTAINS,
         KEYS,
@@ -183,8 +284,66 @@ pub fn create_default_repl_env() -> Rc<RefCell<Environment>> {
         CHECK_MACRO,
         TIME_MS,
         INPUT,
-        MODULO
+        MODULO,
+        META,
+        WITH_META,
+        VARY_META,
+        GENERATOR,
+        CHECK_GENERATOR,
+        NEXT,
+        TYPE,
+        MULTI_FN,
+        ADD_METHOD,
+        REMOVE_METHOD,
+        GET_METHOD,
+        METHODS,
+        PROTOCOL_METHOD,
+        RECORD_TYPE,
+        MAKE_RECORD,
+        DICT_TO_RECORD,
+        CHECK_RECORD,
+        JSON_PARSE,
+        JSON_STRINGIFY,
+        EDN_READ,
+        EDN_WRITE,
+        CSV_READ,
+        CSV_WRITE,
+        SPIT,
+        FILE_EXISTS,
+        LIST_DIR,
+        MKDIR,
+        DELETE_FILE,
+        READ_LINES,
+        OPEN_READER,
+        OPEN_WRITER,
+        READ_LINE,
+        WRITE_STRING,
+        CLOSE,
+        NOW,
+        NANO_TIME,
+        INSTANT,
+        INSTANT_MS,
+        CHECK_INSTANT,
+        DATE_TIME,
+        PARSE_TIME,
+        FORMAT_TIME,
+        TIME_PLUS,
+        TIME_BETWEEN,
+        TIME_FIELDS,
+        TIME_FN,
+        BENCH_FN,
+        PROFILE_FN
     );
 
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use crate::process::*;
+        set_function!(GETENV, SETENV, EXIT, SH, RUN_PROCESS);
+    }
+
+    for builtin in Builtin::ALL {
+        repl_env.set(builtin.name().to_string(), DataType::Builtin(builtin));
+    }
+
     Rc::new(RefCell::new(repl_env))
 }
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::{evaluator::RuntimeError, variable_type::DataType};
+
+// `defmulti`, `defmethod`, `defprotocol` and `extend-type` are rewritten into
+// calls to the `multi-fn`, `add-method!` and `protocol-method` builtins, which
+// the evaluator then evaluates in their place.
+
+pub(crate) fn symbol(name: &str) -> DataType {
+    DataType::Symbol(name.to_string(), None)
+}
+
+pub(crate) fn keyword(name: &str) -> DataType {
+    DataType::Keyword(name.to_string())
+}
+
+pub(crate) fn list(children: Vec<DataType>) -> DataType {
+    DataType::List(children, None)
+}
+
+/// Builds `(fn* (params) body)` from a method's parameter list and body forms.
+fn method_fn(params: &DataType, body: &[DataType]) -> Result<DataType, RuntimeError> {
+    let (DataType::List(params, _) | DataType::Vector(params, _)) = params else {
+        return Err(RuntimeError {
+            msg: format!("Expected parameter list for method, got {:?}", params),
+        });
+    };
+
+    let body = match body {
+        [] => DataType::Nil(),
+        [form] => form.clone(),
+        _ => {
+            let mut forms = vec![symbol("do")];
+            forms.extend(body.iter().cloned());
+            list(forms)
+        }
+    };
+
+    Ok(list(vec![symbol("fn*"), list(params.clone()), body]))
+}
+
+/// `(defmulti name dispatch-fn)`
+pub fn expand_defmulti(args: &[DataType]) -> Result<DataType, RuntimeError> {
+    let [name @ DataType::Symbol(sym, _), dispatch] = args else {
+        return Err(RuntimeError {
+            msg: "Incorrect usage of defmulti".to_string(),
+        });
+    };
+
+    Ok(list(vec![
+        symbol("def!"),
+        name.clone(),
+        list(vec![
+            symbol("multi-fn"),
+            DataType::String(sym.clone()),
+            dispatch.clone(),
+        ]),
+    ]))
+}
+
+/// `(defmethod name dispatch-value [params] body...)`
+pub fn expand_defmethod(args: &[DataType]) -> Result<DataType, RuntimeError> {
+    let [name, dispatch_value, params, body @ ..] = args else {
+        return Err(RuntimeError {
+            msg: "Incorrect usage of defmethod".to_string(),
+        });
+    };
+
+    Ok(list(vec![
+        symbol("add-method!"),
+        name.clone(),
+        dispatch_value.clone(),
+        method_fn(params, body)?,
+    ]))
+}
+
+/// `(defprotocol Name (method [this args...]) ...)`, which defines each method
+/// as a multimethod dispatching on `type`, and `Name` as a dictionary of them.
+pub fn expand_defprotocol(args: &[DataType]) -> Result<DataType, RuntimeError> {
+    let Some((DataType::Symbol(name, _), signatures)) = args.split_first() else {
+        return Err(RuntimeError {
+            msg: "Incorrect usage of defprotocol".to_string(),
+        });
+    };
+
+    let mut forms = vec![symbol("do")];
+    let mut methods = HashMap::new();
+
+    for signature in signatures {
+        let method = match signature {
+            DataType::List(children, _) => match children.first() {
+                Some(DataType::Symbol(method, _)) => method,
+                _ => {
+                    return Err(RuntimeError {
+                        msg: format!("Invalid method signature {:?}", signature),
+                    });
+                }
+            },
+            // Docstrings
+            DataType::String(_) => continue,
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!("Invalid method signature {:?}", signature),
+                });
+            }
+        };
+
+        forms.push(list(vec![
+            symbol("def!"),
+            symbol(method),
+            list(vec![symbol("multi-fn"), DataType::String(method.clone())]),
+        ]));
+        methods.insert(format!("{:?}", keyword(method)), symbol(method));
+    }
+
+    let mut protocol = HashMap::new();
+    protocol.insert(
+        format!("{:?}", keyword("name")),
+        DataType::String(name.clone()),
+    );
+    protocol.insert(
+        format!("{:?}", keyword("methods")),
+        DataType::Dictionary(methods, None),
+    );
+    forms.push(list(vec![
+        symbol("def!"),
+        symbol(name),
+        DataType::Dictionary(protocol, None),
+    ]));
+
+    Ok(list(forms))
+}
+
+/// `(extend-type type Protocol (method [this args...] body...) ...)`, where
+/// `type` is a keyword returned by `type`. Several protocols can be extended
+/// at once by listing each one before its methods.
+pub fn expand_extend_type(args: &[DataType]) -> Result<DataType, RuntimeError> {
+    let Some((type_name, implementations)) = args.split_first() else {
+        return Err(RuntimeError {
+            msg: "Incorrect usage of extend-type".to_string(),
+        });
+    };
+
+    let mut forms = vec![symbol("do")];
+    let mut protocol = None;
+
+    for implementation in implementations {
+        match implementation {
+            DataType::Symbol(..) => protocol = Some(implementation.clone()),
+
+            DataType::List(children, _) => {
+                let (Some(protocol), [DataType::Symbol(method, _), params, body @ ..]) =
+                    (&protocol, children.as_slice())
+                else {
+                    return Err(RuntimeError {
+                        msg: format!("Invalid method implementation {:?}", implementation),
+                    });
+                };
+
+                forms.push(list(vec![
+                    symbol("add-method!"),
+                    list(vec![
+                        symbol("protocol-method"),
+                        protocol.clone(),
+                        keyword(method),
+                    ]),
+                    type_name.clone(),
+                    method_fn(params, body)?,
+                ]));
+            }
+
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!("Invalid method implementation {:?}", implementation),
+                });
+            }
+        }
+    }
+
+    forms.push(DataType::Nil());
+    Ok(list(forms))
+}
@@ -0,0 +1,419 @@
+use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
+
+use crate::{
+    evaluator::{RuntimeError, eval},
+    filesystem::FileSystem,
+    read,
+    runtime::Runtime,
+    variable_type::{DataType, Environment},
+};
+
+/// Symbol holding the list of directories searched by `require`.
+pub const LOAD_PATH: &str = "*load-path*";
+pub const MODULE_EXTENSION: &str = "bl";
+
+#[derive(Clone)]
+pub struct Namespace {
+    pub name: String,
+    aliases: HashMap<String, String>,
+}
+
+impl Namespace {
+    pub fn new(name: &str) -> Namespace {
+        Self {
+            name: name.to_string(),
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+/// Every module loaded by `require`, keyed by namespace name.
+#[derive(Default)]
+pub struct ModuleRegistry {
+    namespaces: HashMap<String, Rc<RefCell<Environment>>>,
+    loading: Vec<String>,
+    /// The namespace last switched to with `ns` outside of a module
+    current: Option<Rc<RefCell<Environment>>>,
+}
+
+/// Finds the environment of the namespace that `env` belongs to.
+fn namespace_env(env: &Rc<RefCell<Environment>>) -> Option<Rc<RefCell<Environment>>> {
+    let mut env = env.clone();
+
+    loop {
+        if env.borrow().namespace.is_some() {
+            return Some(env);
+        }
+
+        let outer = env.borrow().get_outer()?;
+        env = outer;
+    }
+}
+
+fn root_env(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    let mut env = env.clone();
+
+    loop {
+        let Some(outer) = env.borrow().get_outer() else {
+            break;
+        };
+        env = outer;
+    }
+
+    env
+}
+
+/// Where top level forms should be evaluated: the namespace last switched to
+/// with `ns`, or the root environment itself.
+pub fn current_namespace(root: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    let Some(runtime) = root.borrow().runtime() else {
+        return root.clone();
+    };
+    let current = runtime.modules.borrow().current.clone();
+    current.unwrap_or_else(|| root.clone())
+}
+
+/// Switches to the namespace `name`, creating it if it doesn't exist yet.
+fn enter_namespace(
+    name: &str,
+    env: &Rc<RefCell<Environment>>,
+    runtime: &Runtime,
+) -> Rc<RefCell<Environment>> {
+    let root = root_env(env);
+    let is_root = match root.borrow().namespace {
+        Some(ref namespace) => namespace.name == name,
+        None => false,
+    };
+
+    let mut modules = runtime.modules.borrow_mut();
+    let target = match modules.namespaces.get(name) {
+        Some(target) => target.clone(),
+        None if is_root => root,
+        None => Rc::new(RefCell::new(Environment::new_namespace(name, root))),
+    };
+    modules.namespaces.insert(name.to_string(), target.clone());
+    modules.current = Some(target.clone());
+    target
+}
+
+fn runtime_of(env: &Rc<RefCell<Environment>>) -> Result<Rc<Runtime>, RuntimeError> {
+    match env.borrow().runtime() {
+        Some(runtime) => Ok(runtime),
+        None => Err(RuntimeError {
+            msg: "Modules are not available in this environment".to_string(),
+        }),
+    }
+}
+
+/// Whether the metadata given to `def!` marks the name as private.
+pub fn marks_private(meta: &DataType) -> bool {
+    match meta {
+        DataType::Dictionary(meta, _) => {
+            meta.get(&format!("{:?}", DataType::Keyword("private".to_string())))
+                == Some(&DataType::Bool(true))
+        }
+        _ => false,
+    }
+}
+
+/// Looks up a namespace qualified symbol such as `lib/fn` or `alias/fn`.
+/// Returns `Ok(None)` when the symbol isn't qualified or can't be resolved.
+pub fn resolve_qualified(
+    sym: &str,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Option<DataType>, RuntimeError> {
+    let Some((prefix, name)) = sym.split_once('/') else {
+        return Ok(None);
+    };
+    if prefix.is_empty() || name.is_empty() {
+        return Ok(None);
+    }
+
+    let Some(current) = namespace_env(env) else {
+        return Ok(None);
+    };
+    let target_name = match current.borrow().namespace {
+        Some(ref namespace) => match namespace.aliases.get(prefix) {
+            Some(target) => target.clone(),
+            None => prefix.to_string(),
+        },
+        None => prefix.to_string(),
+    };
+
+    let Some(runtime) = env.borrow().runtime() else {
+        return Ok(None);
+    };
+    let Some(target) = runtime
+        .modules
+        .borrow()
+        .namespaces
+        .get(&target_name)
+        .cloned()
+    else {
+        return Ok(None);
+    };
+
+    let Some(value) = target.borrow().get_local(&name.to_string()) else {
+        return Ok(None);
+    };
+    if target.borrow().is_private(name) && !Rc::ptr_eq(&target, &current) {
+        return Err(RuntimeError {
+            msg: format!("{}/{} is private", target_name, name),
+        });
+    }
+
+    Ok(Some(value))
+}
+
+pub fn eval_ns(args: &[DataType], env: Rc<RefCell<Environment>>) -> Result<DataType, RuntimeError> {
+    let Some(DataType::Symbol(name, _)) = args.first() else {
+        return Err(RuntimeError {
+            msg: "Incorrect usage of ns".to_string(),
+        });
+    };
+    let Some(ns_env) = namespace_env(&env) else {
+        return Err(RuntimeError {
+            msg: "ns used outside of a namespace".to_string(),
+        });
+    };
+    let runtime = runtime_of(&env)?;
+
+    let current_name = match ns_env.borrow().namespace {
+        Some(ref namespace) => namespace.name.clone(),
+        None => unreachable!(),
+    };
+    let is_module = runtime.modules.borrow().loading.contains(&current_name);
+    if is_module && current_name != *name {
+        return Err(RuntimeError {
+            msg: format!(
+                "Expected namespace {} but found declaration for {}",
+                current_name, name
+            ),
+        });
+    }
+    let ns_env = if is_module {
+        ns_env
+    } else {
+        enter_namespace(name, &env, &runtime)
+    };
+
+    for clause in &args[1..] {
+        match clause {
+            DataType::List(children, _) => match children.split_first() {
+                Some((DataType::Keyword(keyword), specs)) if keyword == "require" => {
+                    for spec in specs {
+                        require_spec(spec, &ns_env)?;
+                    }
+                }
+                _ => {
+                    return Err(RuntimeError {
+                        msg: format!("Unknown ns clause {:?}", clause),
+                    });
+                }
+            },
+            DataType::String(_) => {}
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!("Unknown ns clause {:?}", clause),
+                });
+            }
+        }
+    }
+
+    Ok(DataType::Nil())
+}
+
+pub fn eval_require(
+    args: &[DataType],
+    env: Rc<RefCell<Environment>>,
+) -> Result<DataType, RuntimeError> {
+    let Some(ns_env) = namespace_env(&env) else {
+        return Err(RuntimeError {
+            msg: "require used outside of a namespace".to_string(),
+        });
+    };
+
+    if args.is_empty() {
+        return Err(RuntimeError {
+            msg: "Incorrect usage of require".to_string(),
+        });
+    }
+
+    for spec in args {
+        require_spec(spec, &ns_env)?;
+    }
+
+    Ok(DataType::Nil())
+}
+
+/// Loads the module named by `spec` and applies its `:as` and `:refer` options
+/// to the requiring namespace.
+fn require_spec(spec: &DataType, ns_env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    let spec = match spec {
+        DataType::List(children, _) => match children.as_slice() {
+            [DataType::Symbol(quote, _), inner] if quote == "quote" => inner,
+            _ => spec,
+        },
+        _ => spec,
+    };
+
+    let (name, options) = match spec {
+        DataType::Symbol(name, _) => (name, &[][..]),
+        DataType::Vector(children, _) | DataType::List(children, _) => match children.split_first()
+        {
+            Some((DataType::Symbol(name, _), options)) => (name, options),
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!("Invalid require spec {:?}", spec),
+                });
+            }
+        },
+        _ => {
+            return Err(RuntimeError {
+                msg: format!("Invalid require spec {:?}", spec),
+            });
+        }
+    };
+
+    let module = load_module(name, ns_env)?;
+
+    for option in options.chunks(2) {
+        match option {
+            [DataType::Keyword(keyword), DataType::Symbol(alias, _)] if keyword == "as" => {
+                if let Some(ref mut namespace) = ns_env.borrow_mut().namespace {
+                    namespace.aliases.insert(alias.clone(), name.clone());
+                }
+            }
+
+            [DataType::Keyword(keyword), DataType::Keyword(all)]
+                if keyword == "refer" && all == "all" =>
+            {
+                for (sym, value) in module.borrow().local_bindings() {
+                    if !module.borrow().is_private(&sym) {
+                        ns_env.borrow_mut().set(sym, value);
+                    }
+                }
+            }
+
+            [
+                DataType::Keyword(keyword),
+                DataType::Vector(syms, _) | DataType::List(syms, _),
+            ] if keyword == "refer" => {
+                for sym in syms {
+                    let DataType::Symbol(sym, _) = sym else {
+                        return Err(RuntimeError {
+                            msg: format!("Cannot refer {:?}", sym),
+                        });
+                    };
+                    let Some(value) = module.borrow().get_local(sym) else {
+                        return Err(RuntimeError {
+                            msg: format!("{} does not exist in {}", sym, name),
+                        });
+                    };
+                    if module.borrow().is_private(sym) {
+                        return Err(RuntimeError {
+                            msg: format!("{}/{} is private", name, sym),
+                        });
+                    }
+                    ns_env.borrow_mut().set(sym.clone(), value);
+                }
+            }
+
+            _ => {
+                return Err(RuntimeError {
+                    msg: format!("Invalid require option {:?}", option),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the environment of the module `name`, evaluating its file the first
+/// time it is required.
+fn load_module(
+    name: &str,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+    let runtime = runtime_of(env)?;
+
+    {
+        let modules = runtime.modules.borrow();
+        if let Some(module) = modules.namespaces.get(name) {
+            return Ok(module.clone());
+        }
+
+        if modules.loading.iter().any(|loading| loading == name) {
+            let mut chain = modules.loading.clone();
+            chain.push(name.to_string());
+            return Err(RuntimeError {
+                msg: format!("Cyclic require: {}", chain.join(" -> ")),
+            });
+        }
+    }
+
+    let root = root_env(env);
+    let filesystem = runtime.filesystem();
+    let path = resolve_module_path(name, &root, filesystem.as_ref())?;
+    let source = match filesystem.read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => {
+            return Err(RuntimeError {
+                msg: format!("Couldn't load module {}: {}", name, e),
+            });
+        }
+    };
+    let ast = match read(format!("(do {}\nnil)", source)) {
+        Ok(ast) => ast,
+        Err(e) => {
+            return Err(RuntimeError {
+                msg: format!("Couldn't parse module {}: {}", name, e.msg),
+            });
+        }
+    };
+
+    let module = Rc::new(RefCell::new(Environment::new_namespace(name, root)));
+
+    runtime.modules.borrow_mut().loading.push(name.to_string());
+    let result = eval(&ast, module.clone(), module.clone());
+    let mut modules = runtime.modules.borrow_mut();
+    modules.loading.pop();
+    result?;
+
+    modules.namespaces.insert(name.to_string(), module.clone());
+    Ok(module)
+}
+
+fn resolve_module_path(
+    name: &str,
+    root: &Rc<RefCell<Environment>>,
+    filesystem: &dyn FileSystem,
+) -> Result<String, RuntimeError> {
+    let directories = match root.borrow().get(&LOAD_PATH.to_string()) {
+        Some(DataType::List(ref directories, _) | DataType::Vector(ref directories, _)) => {
+            directories.clone()
+        }
+        _ => vec![DataType::String(".".to_string())],
+    };
+
+    let relative_path = format!("{}.{}", name.replace('.', "/"), MODULE_EXTENSION);
+
+    for directory in directories {
+        let DataType::String(directory) = &directory else {
+            return Err(RuntimeError {
+                msg: format!("{} should only contain strings", LOAD_PATH),
+            });
+        };
+
+        let path = Path::new(&directory).join(&relative_path);
+        let path = path.to_string_lossy();
+        if filesystem.exists(&path) {
+            return Ok(path.to_string());
+        }
+    }
+
+    Err(RuntimeError {
+        msg: format!("Couldn't find module {} in {}", name, LOAD_PATH),
+    })
+}
@@ -0,0 +1,269 @@
+use std::{collections::HashMap, fmt::Display, rc::Rc};
+
+use crate::{context::Context, evaluator::RuntimeError, variable_type::DataType};
+
+type NativeClosure = dyn Fn(&mut Context, &[DataType]) -> Result<DataType, RuntimeError>;
+
+/// A function implemented in Rust. Unlike the `CoreFunction`s in `env`, it can
+/// capture state from the host program.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: Rc<str>,
+    pub func: Rc<NativeClosure>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        func: impl Fn(&mut Context, &[DataType]) -> Result<DataType, RuntimeError> + 'static,
+    ) -> NativeFunction {
+        NativeFunction {
+            name: name.into(),
+            func: Rc::new(func),
+        }
+    }
+
+    pub fn call(&self, context: &mut Context, args: &[DataType]) -> Result<DataType, RuntimeError> {
+        (self.func)(context, args)
+    }
+}
+
+/// Rust types that can be taken as arguments by a function registered with
+/// `Interpreter::register_fn`.
+pub trait FromDataType: Sized {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError>;
+}
+
+/// Rust types that can be returned to the program.
+pub trait IntoDataType {
+    fn into_data_type(self) -> DataType;
+}
+
+fn wrong_type(expected: &str, value: &DataType) -> RuntimeError {
+    RuntimeError {
+        msg: format!("Expected {}, got {:?}", expected, value),
+    }
+}
+
+impl FromDataType for DataType {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoDataType for DataType {
+    fn into_data_type(self) -> DataType {
+        self
+    }
+}
+
+macro_rules! integer_conversions {
+    ($($t:ty),*) => {
+        $(
+            impl FromDataType for $t {
+                fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+                    match value {
+                        DataType::Integer(num) => (*num).try_into().map_err(|_| RuntimeError {
+                            msg: format!("{} is out of range for {}", num, stringify!($t)),
+                        }),
+                        _ => Err(wrong_type("an integer", value)),
+                    }
+                }
+            }
+
+            impl IntoDataType for $t {
+                fn into_data_type(self) -> DataType {
+                    DataType::Integer(self as i128)
+                }
+            }
+        )*
+    };
+}
+
+integer_conversions!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, usize);
+
+impl FromDataType for f64 {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+        match value {
+            DataType::Float(num) => Ok(*num),
+            DataType::Integer(num) => Ok(*num as f64),
+            _ => Err(wrong_type("a number", value)),
+        }
+    }
+}
+
+impl IntoDataType for f64 {
+    fn into_data_type(self) -> DataType {
+        DataType::Float(self)
+    }
+}
+
+impl FromDataType for bool {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+        match value {
+            DataType::Bool(value) => Ok(*value),
+            _ => Err(wrong_type("a bool", value)),
+        }
+    }
+}
+
+impl IntoDataType for bool {
+    fn into_data_type(self) -> DataType {
+        DataType::Bool(self)
+    }
+}
+
+impl FromDataType for String {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+        match value {
+            DataType::String(string) => Ok(string.clone()),
+            _ => Err(wrong_type("a string", value)),
+        }
+    }
+}
+
+impl IntoDataType for String {
+    fn into_data_type(self) -> DataType {
+        DataType::String(self)
+    }
+}
+
+impl IntoDataType for &str {
+    fn into_data_type(self) -> DataType {
+        DataType::String(self.to_string())
+    }
+}
+
+impl IntoDataType for () {
+    fn into_data_type(self) -> DataType {
+        DataType::Nil()
+    }
+}
+
+impl<T: FromDataType> FromDataType for Option<T> {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+        match value {
+            DataType::Nil() => Ok(None),
+            _ => Ok(Some(T::from_data_type(value)?)),
+        }
+    }
+}
+
+impl<T: IntoDataType> IntoDataType for Option<T> {
+    fn into_data_type(self) -> DataType {
+        match self {
+            Some(value) => value.into_data_type(),
+            None => DataType::Nil(),
+        }
+    }
+}
+
+impl<T: FromDataType> FromDataType for Vec<T> {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+        match value {
+            DataType::List(items, _) | DataType::Vector(items, _) => {
+                items.iter().map(T::from_data_type).collect()
+            }
+            _ => Err(wrong_type("a list or vector", value)),
+        }
+    }
+}
+
+impl<T: IntoDataType> IntoDataType for Vec<T> {
+    fn into_data_type(self) -> DataType {
+        DataType::List(
+            self.into_iter().map(IntoDataType::into_data_type).collect(),
+            None,
+        )
+    }
+}
+
+/// Dictionaries, keyed as the program keys them, e.g. `":a"` for `:a`.
+impl<T: FromDataType> FromDataType for HashMap<String, T> {
+    fn from_data_type(value: &DataType) -> Result<Self, RuntimeError> {
+        match value {
+            DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => dict
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), T::from_data_type(value)?)))
+                .collect(),
+            _ => Err(wrong_type("a dict", value)),
+        }
+    }
+}
+
+impl<T: IntoDataType> IntoDataType for HashMap<String, T> {
+    fn into_data_type(self) -> DataType {
+        DataType::Dictionary(
+            self.into_iter()
+                .map(|(key, value)| (key, value.into_data_type()))
+                .collect(),
+            None,
+        )
+    }
+}
+
+/// What a registered function can return: any value, or a `Result` whose
+/// error is raised in the program.
+pub trait NativeReturn {
+    fn into_result(self) -> Result<DataType, RuntimeError>;
+}
+
+impl<T: IntoDataType> NativeReturn for T {
+    fn into_result(self) -> Result<DataType, RuntimeError> {
+        Ok(self.into_data_type())
+    }
+}
+
+impl<T: IntoDataType, E: Display> NativeReturn for Result<T, E> {
+    fn into_result(self) -> Result<DataType, RuntimeError> {
+        match self {
+            Ok(value) => Ok(value.into_data_type()),
+            Err(e) => Err(RuntimeError { msg: e.to_string() }),
+        }
+    }
+}
+
+/// Rust closures taking `FromDataType` arguments, which can be registered as
+/// functions.
+pub trait IntoNativeFunction<Args> {
+    fn into_native_function(self, name: &str) -> NativeFunction;
+}
+
+macro_rules! into_native_function {
+    ($($arg:ident $value:ident),*) => {
+        impl<F, R, $($arg),*> IntoNativeFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: NativeReturn,
+            $($arg: FromDataType,)*
+        {
+            fn into_native_function(self, name: &str) -> NativeFunction {
+                let id = name.to_string();
+
+                NativeFunction::new(name, move |_: &mut Context, values: &[DataType]| {
+                    let [$($value),*] = values else {
+                        let expected: &[&str] = &[$(stringify!($value)),*];
+                        return Err(RuntimeError {
+                            msg: format!(
+                                "{} expects {} arguments, got {}",
+                                id,
+                                expected.len(),
+                                values.len()
+                            ),
+                        });
+                    };
+
+                    self($($arg::from_data_type($value)?),*).into_result()
+                })
+            }
+        }
+    };
+}
+
+into_native_function!();
+into_native_function!(A a);
+into_native_function!(A a, B b);
+into_native_function!(A a, B b, C c);
+into_native_function!(A a, B b, C c, D d);
+into_native_function!(A a, B b, C c, D d, E e);
+into_native_function!(A a, B b, C c, D d, E e, G g);
@@ -0,0 +1,169 @@
+use crate::{evaluator::RuntimeError, variable_type::DataType};
+
+/// One `pattern [:when guard] body` clause of a `match` form.
+pub struct MatchClause {
+    pub pattern: DataType,
+    pub guard: Option<DataType>,
+    pub body: DataType,
+}
+
+/// What a successful structural match needs before its clause is chosen: the
+/// symbols it binds and the `(? pred x)` predicates still to be called.
+#[derive(Default)]
+pub struct PatternMatch {
+    pub bindings: Vec<(String, DataType)>,
+    pub checks: Vec<(DataType, DataType)>,
+}
+
+pub fn parse_clauses(args: &[DataType]) -> Result<Vec<MatchClause>, RuntimeError> {
+    let mut clauses = vec![];
+    let mut args = args.iter();
+
+    while let Some(pattern) = args.next() {
+        let mut guard = None;
+        let mut body = args.next();
+
+        if let Some(DataType::Keyword(keyword)) = body
+            && keyword == "when"
+        {
+            guard = args.next().cloned();
+            body = args.next();
+        }
+
+        let Some(body) = body else {
+            return Err(RuntimeError {
+                msg: format!("Missing body for match clause {:?}", pattern),
+            });
+        };
+
+        clauses.push(MatchClause {
+            pattern: pattern.clone(),
+            guard,
+            body: body.clone(),
+        });
+    }
+
+    Ok(clauses)
+}
+
+/// Matches the shape of `value` against `pattern`, returning `None` if it
+/// doesn't fit.
+pub fn match_pattern(
+    pattern: &DataType,
+    value: &DataType,
+) -> Result<Option<PatternMatch>, RuntimeError> {
+    let mut result = PatternMatch::default();
+
+    if match_into(pattern, value, &mut result)? {
+        Ok(Some(result))
+    } else {
+        Ok(None)
+    }
+}
+
+fn match_into(
+    pattern: &DataType,
+    value: &DataType,
+    result: &mut PatternMatch,
+) -> Result<bool, RuntimeError> {
+    match pattern {
+        DataType::Symbol(name, _) if name == "_" => Ok(true),
+
+        DataType::Symbol(name, _) if name == "&" => Err(RuntimeError {
+            msg: "& must be followed by a single pattern".to_string(),
+        }),
+
+        DataType::Symbol(name, _) => {
+            result.bindings.push((name.clone(), value.clone()));
+            Ok(true)
+        }
+
+        DataType::Nil()
+        | DataType::Bool(_)
+        | DataType::Integer(_)
+        | DataType::Float(_)
+        | DataType::String(_)
+        | DataType::Keyword(_) => Ok(pattern == value),
+
+        DataType::List(children, _) => match children.as_slice() {
+            [DataType::Symbol(quote, _), quoted] if quote == "quote" => Ok(quoted == value),
+
+            [DataType::Symbol(guard, _), predicate, rest @ ..] if guard == "?" => {
+                result.checks.push((predicate.clone(), value.clone()));
+                match rest {
+                    [] => Ok(true),
+                    [inner] => match_into(inner, value, result),
+                    _ => Err(RuntimeError {
+                        msg: "Type guards take a predicate and at most one pattern".to_string(),
+                    }),
+                }
+            }
+
+            _ => match_sequence(children, value, result),
+        },
+
+        DataType::Vector(children, _) => match_sequence(children, value, result),
+
+        DataType::Dictionary(entries, _) => {
+            let (DataType::Dictionary(dict, _) | DataType::Record(_, dict, _)) = value else {
+                return Ok(false);
+            };
+
+            for (key, inner) in entries {
+                let Some(inner_value) = dict.get(key) else {
+                    return Ok(false);
+                };
+                if !match_into(inner, inner_value, result)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }
+
+        _ => Err(RuntimeError {
+            msg: format!("Invalid pattern {:?}", pattern),
+        }),
+    }
+}
+
+fn match_sequence(
+    patterns: &[DataType],
+    value: &DataType,
+    result: &mut PatternMatch,
+) -> Result<bool, RuntimeError> {
+    let (DataType::List(values, _) | DataType::Vector(values, _)) = value else {
+        return Ok(false);
+    };
+
+    let rest = patterns
+        .iter()
+        .position(|pattern| matches!(pattern, DataType::Symbol(name, _) if name == "&"));
+
+    let fixed = match rest {
+        Some(index) if values.len() >= index => &patterns[..index],
+        None if values.len() == patterns.len() => patterns,
+        _ => return Ok(false),
+    };
+
+    for (pattern, value) in fixed.iter().zip(values) {
+        if !match_into(pattern, value, result)? {
+            return Ok(false);
+        }
+    }
+
+    let Some(index) = rest else {
+        return Ok(true);
+    };
+    let [rest_pattern] = &patterns[index + 1..] else {
+        return Err(RuntimeError {
+            msg: "& must be followed by a single pattern".to_string(),
+        });
+    };
+
+    match_into(
+        rest_pattern,
+        &DataType::List(values[index..].to_vec(), None),
+        result,
+    )
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    context::Context,
+    env::CoreFunction,
+    evaluator::RuntimeError,
+    variable_type::DataType::{self, *},
+};
+
+// These touch the host process, so they are left out of the wasm build and
+// need the `process` capability, which is off by default.
+
+fn string_argument<'a>(
+    name: &str,
+    values: &'a [DataType],
+    index: usize,
+) -> Result<&'a str, RuntimeError> {
+    match values.get(index) {
+        Some(String(string)) => Ok(string),
+        _ => Err(RuntimeError {
+            msg: format!("{} expects a string as argument {}", name, index + 1),
+        }),
+    }
+}
+
+pub const GETENV: CoreFunction = CoreFunction {
+    id: "getenv",
+    func: |_: &mut Context, values: &[DataType]| {
+        let name = string_argument("getenv", values, 0)?;
+
+        Ok(match std::env::var(name) {
+            Ok(value) => String(value),
+            Err(_) => Nil(),
+        })
+    },
+};
+
+pub const SETENV: CoreFunction = CoreFunction {
+    id: "setenv",
+    func: |_: &mut Context, values: &[DataType]| {
+        let name = string_argument("setenv", values, 0)?;
+
+        match values.get(1) {
+            // SAFETY: the interpreter runs on a single thread, and hosts that
+            // share the process with other threads shouldn't grant access.
+            Some(String(value)) => unsafe { std::env::set_var(name, value) },
+            Some(Nil()) => unsafe { std::env::remove_var(name) },
+            _ => {
+                return Err(RuntimeError {
+                    msg: "setenv expects a string or nil as the value".to_string(),
+                });
+            }
+        }
+        Ok(Nil())
+    },
+};
+
+pub const EXIT: CoreFunction = CoreFunction {
+    id: "exit",
+    func: |_: &mut Context, values: &[DataType]| {
+        let code = match values.first() {
+            None => 0,
+            Some(Integer(code)) => i32::try_from(*code).map_err(|_| RuntimeError {
+                msg: format!("Exit status {} is out of range", code),
+            })?,
+            Some(_) => {
+                return Err(RuntimeError {
+                    msg: "exit expects an integer status".to_string(),
+                });
+            }
+        };
+        std::process::exit(code)
+    },
+};
+
+/// Runs `command`, feeding it the `:in` option as stdin, and returns a dict of
+/// its `:exit` status along with what it wrote to `:out` and `:err`.
+fn run(mut command: Command, options: Option<&DataType>) -> Result<DataType, RuntimeError> {
+    let options = match options {
+        None => HashMap::new(),
+        Some(Dictionary(options, _)) => options.clone(),
+        Some(_) => {
+            return Err(RuntimeError {
+                msg: "Options should be a dict".to_string(),
+            });
+        }
+    };
+
+    let input = match options.get(":in") {
+        None | Some(Nil()) => None,
+        Some(String(input)) => Some(input.clone()),
+        Some(_) => {
+            return Err(RuntimeError {
+                msg: ":in should be a string".to_string(),
+            });
+        }
+    };
+    match options.get(":dir") {
+        None | Some(Nil()) => {}
+        Some(String(dir)) => {
+            command.current_dir(dir);
+        }
+        Some(_) => {
+            return Err(RuntimeError {
+                msg: ":dir should be a string".to_string(),
+            });
+        }
+    }
+
+    let program = command.get_program().to_string_lossy().to_string();
+    let error = |e: std::io::Error| RuntimeError {
+        msg: format!("Couldn't run {}: {}", program, e),
+    };
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(error)?;
+
+    // Written from another thread so a child filling its output pipe before
+    // reading all of its input can't deadlock us.
+    let writer = match (child.stdin.take(), input) {
+        (Some(mut stdin), Some(input)) => Some(std::thread::spawn(move || {
+            // The child may exit without reading everything, which is fine.
+            let _ = stdin.write_all(input.as_bytes());
+        })),
+        _ => None,
+    };
+
+    let output = child.wait_with_output().map_err(error)?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    let mut result = HashMap::new();
+    result.insert(
+        ":exit".to_string(),
+        match output.status.code() {
+            Some(code) => Integer(code.into()),
+            None => Nil(),
+        },
+    );
+    result.insert(
+        ":out".to_string(),
+        String(std::string::String::from_utf8_lossy(&output.stdout).to_string()),
+    );
+    result.insert(
+        ":err".to_string(),
+        String(std::string::String::from_utf8_lossy(&output.stderr).to_string()),
+    );
+    Ok(Dictionary(result, None))
+}
+
+/// `(sh "command line" {:in "..." :dir "..."})`, run by the system shell.
+pub const SH: CoreFunction = CoreFunction {
+    id: "sh",
+    func: |_: &mut Context, values: &[DataType]| {
+        let line = string_argument("sh", values, 0)?;
+
+        let command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.args(["/C", line]);
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.args(["-c", line]);
+            command
+        };
+
+        run(command, values.get(1))
+    },
+};
+
+/// `(run-process "program" ["args" ...] {:in "..." :dir "..."})`, which runs
+/// the program directly rather than through a shell.
+pub const RUN_PROCESS: CoreFunction = CoreFunction {
+    id: "run-process",
+    func: |_: &mut Context, values: &[DataType]| {
+        let program = string_argument("run-process", values, 0)?;
+
+        let (args, options) = match values.get(1) {
+            Some(List(args, _) | Vector(args, _)) => (args.as_slice(), values.get(2)),
+            options => (&[][..], options),
+        };
+
+        let mut command = Command::new(program);
+        for arg in args {
+            let String(arg) = arg else {
+                return Err(RuntimeError {
+                    msg: format!("Process arguments should be strings, got {:?}", arg),
+                });
+            };
+            command.arg(arg);
+        }
+
+        run(command, options)
+    },
+};
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::datetime::monotonic_nanos;
+
+/// Calls and time spent in one function while profiling.
+#[derive(Clone, Copy, Default)]
+struct Stats {
+    calls: u64,
+    /// Time from entering to leaving the function, only counted for the
+    /// outermost call when it recurses
+    inclusive: i128,
+    /// Inclusive time less the time spent in profiled functions it called
+    exclusive: i128,
+}
+
+struct Active {
+    name: Rc<str>,
+    start: i128,
+    children: i128,
+}
+
+/// Timings for every named closure called while `profile` runs. The
+/// evaluator reports each call through `enter` and `exit`.
+#[derive(Default)]
+pub struct Profiler {
+    stats: HashMap<Rc<str>, Stats>,
+    /// Exclusive time per call stack, such as `main;parse;token`
+    folded: HashMap<String, i128>,
+    active: Vec<Active>,
+}
+
+impl Profiler {
+    /// Starts timing a call to `name`, returning the depth to `exit` to.
+    pub fn enter(&mut self, name: &Rc<str>) -> usize {
+        self.count_call(name);
+        self.active.push(Active {
+            name: name.clone(),
+            start: monotonic_nanos(),
+            children: 0,
+        });
+        self.active.len() - 1
+    }
+
+    pub fn name_at(&self, depth: usize) -> Option<Rc<str>> {
+        self.active.get(depth).map(|active| active.name.clone())
+    }
+
+    /// Counts a call that doesn't get its own entry, like a self tail call.
+    pub fn count_call(&mut self, name: &Rc<str>) {
+        self.stats.entry(name.clone()).or_default().calls += 1;
+    }
+
+    /// Finishes every call entered at `depth` or deeper.
+    pub fn exit(&mut self, depth: usize) {
+        let now = monotonic_nanos();
+
+        while self.active.len() > depth {
+            let path = self
+                .active
+                .iter()
+                .map(|active| &*active.name)
+                .collect::<Vec<_>>()
+                .join(";");
+            let Some(call) = self.active.pop() else {
+                break;
+            };
+
+            let inclusive = now - call.start;
+            let exclusive = inclusive - call.children;
+            let recursive = self.active.iter().any(|active| active.name == call.name);
+
+            let stats = self.stats.entry(call.name).or_default();
+            stats.exclusive += exclusive;
+            if !recursive {
+                stats.inclusive += inclusive;
+            }
+            *self.folded.entry(path).or_default() += exclusive;
+
+            if let Some(caller) = self.active.last_mut() {
+                caller.children += inclusive;
+            }
+        }
+    }
+
+    /// A table of every function's calls and time, most exclusive time first.
+    pub fn report(&self) -> String {
+        let mut rows = self.stats.iter().collect::<Vec<_>>();
+        rows.sort_by(|(a_name, a), (b_name, b)| {
+            b.exclusive
+                .cmp(&a.exclusive)
+                .then_with(|| a_name.cmp(b_name))
+        });
+
+        let ms = |nanos: i128| nanos as f64 / 1_000_000.0;
+        let mut out = format!(
+            "{:<24} {:>8} {:>14} {:>14}\n",
+            "function", "calls", "inclusive ms", "exclusive ms"
+        );
+        for (name, stats) in rows {
+            out.push_str(&format!(
+                "{:<24} {:>8} {:>14.3} {:>14.3}\n",
+                name,
+                stats.calls,
+                ms(stats.inclusive),
+                ms(stats.exclusive)
+            ));
+        }
+        out
+    }
+
+    /// The call stacks in the folded format read by flamegraph tools, one
+    /// `a;b;c nanoseconds` line per stack.
+    pub fn folded(&self) -> String {
+        let mut stacks = self.folded.iter().collect::<Vec<_>>();
+        stacks.sort();
+
+        stacks
+            .into_iter()
+            .map(|(path, nanos)| format!("{} {}\n", path, nanos))
+            .collect()
+    }
+}
@@ -52,6 +52,31 @@ impl Reader {
         }
     }
 
+    /// Reads every remaining form, skipping comments. A closing bracket
+    /// without an opening one is an error rather than the end of the input.
+    pub fn read_all(&mut self) -> Result<Vec<DataType>, ParseError> {
+        let mut forms = vec![];
+
+        while let Some(token) = self.peek() {
+            match token.as_str() {
+                "" => {
+                    self.next();
+                }
+                ")" | "]" | "}" => {
+                    return Err(ParseError {
+                        msg: format!("Unexpected {}", token),
+                    });
+                }
+                _ => match self.read()? {
+                    DataType::Comment() => {}
+                    form => forms.push(form),
+                },
+            }
+        }
+
+        Ok(forms)
+    }
+
     pub fn read_list(
         &mut self,
         end_character: std::string::String,
@@ -76,9 +101,9 @@ impl Reader {
         }
         self.next();
         if end_character == ")" {
-            return Ok(DataType::List(children));
+            return Ok(DataType::List(children, None));
         } else {
-            return Ok(DataType::Vector(children));
+            return Ok(DataType::Vector(children, None));
         }
     }
 
@@ -116,7 +141,29 @@ impl Reader {
             children.insert(format!("{:?}", child1), child2);
         }
         self.next();
-        return Ok(DataType::Dictionary(children));
+        return Ok(DataType::Dictionary(children, None));
+    }
+
+    /// Reads `^meta form`, attaching the metadata to the form itself.
+    /// `^:flag` is shorthand for `^{:flag true}`, and any other
+    /// non-dictionary value for `^{:tag value}`.
+    fn read_with_meta(&mut self) -> Result<DataType, ParseError> {
+        let meta = match self.read()? {
+            DataType::Dictionary(ref mut dict, _) => {
+                DataType::Dictionary(std::mem::take(dict), None)
+            }
+            keyword @ DataType::Keyword(_) => DataType::Dictionary(
+                HashMap::from([(format!("{:?}", keyword), DataType::Bool(true))]),
+                None,
+            ),
+            tag => DataType::Dictionary(
+                HashMap::from([(format!("{:?}", DataType::Keyword("tag".to_string())), tag)]),
+                None,
+            ),
+        };
+
+        let form = self.read()?;
+        form.merge_meta(meta).map_err(|e| ParseError { msg: e.msg })
     }
 
     pub fn read_atom(&mut self) -> Result<DataType, ParseError> {
@@ -140,26 +187,36 @@ impl Reader {
                 "false" => Ok(DataType::Bool(false)),
                 "true" => Ok(DataType::Bool(true)),
                 "nil" => Ok(DataType::Nil()),
-                "@" => Ok(DataType::List(vec![
-                    DataType::Symbol(DEREF.id.to_string()),
-                    self.read_atom()?,
-                ])),
-                "'" => Ok(DataType::List(vec![
-                    DataType::Symbol("quote".to_string()),
-                    self.read()?,
-                ])),
-                "`" => Ok(DataType::List(vec![
-                    DataType::Symbol("quasiquote".to_string()),
-                    self.read()?,
-                ])),
-                "~" => Ok(DataType::List(vec![
-                    DataType::Symbol("unquote".to_string()),
-                    self.read()?,
-                ])),
-                "~@" => Ok(DataType::List(vec![
-                    DataType::Symbol("splice-unquote".to_string()),
-                    self.read()?,
-                ])),
+                "@" => Ok(DataType::List(
+                    vec![
+                        DataType::Symbol(DEREF.id.to_string(), None),
+                        self.read_atom()?,
+                    ],
+                    None,
+                )),
+                "'" => Ok(DataType::List(
+                    vec![DataType::Symbol("quote".to_string(), None), self.read()?],
+                    None,
+                )),
+                "`" => Ok(DataType::List(
+                    vec![
+                        DataType::Symbol("quasiquote".to_string(), None),
+                        self.read()?,
+                    ],
+                    None,
+                )),
+                "~" => Ok(DataType::List(
+                    vec![DataType::Symbol("unquote".to_string(), None), self.read()?],
+                    None,
+                )),
+                "~@" => Ok(DataType::List(
+                    vec![
+                        DataType::Symbol("splice-unquote".to_string(), None),
+                        self.read()?,
+                    ],
+                    None,
+                )),
+                "^" => self.read_with_meta(),
                 _ if first_char == '"' => {
                     let converted = token
                         .replace("\\n", "\n")
@@ -171,7 +228,10 @@ impl Reader {
                 _ if first_char == ';' => Ok(DataType::Comment()),
                 _ if first_char == '[' => self.read_list("]".to_string()),
                 _ if first_char == '{' => self.read_dictionary("}".to_string()),
-                _ => Ok(DataType::Symbol(token)),
+                _ if first_char == ':' && token.len() > 1 => {
+                    Ok(DataType::Keyword(token[1..].to_string()))
+                }
+                _ => Ok(DataType::Symbol(token, None)),
             }
         }
     }
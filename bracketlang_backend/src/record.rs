@@ -0,0 +1,96 @@
+use crate::{
+    evaluator::RuntimeError,
+    multimethod::{expand_extend_type, keyword, list, symbol},
+    variable_type::DataType,
+};
+
+/// `(defrecord Name [fields...] Protocol (method [this args...] body...) ...)`,
+/// which defines the `Name` record type along with `->Name` and `map->Name`
+/// constructors, a `Name?` predicate and a `Name-field` accessor per field.
+/// Any protocol implementations are passed on to `extend-type`.
+///
+/// `deftype` is the same, without the `map->Name` constructor.
+pub fn expand_defrecord(args: &[DataType], from_dict: bool) -> Result<DataType, RuntimeError> {
+    let [
+        DataType::Symbol(name, _),
+        DataType::Vector(fields, _),
+        implementations @ ..,
+    ] = args
+    else {
+        return Err(RuntimeError {
+            msg: "Incorrect usage of defrecord".to_string(),
+        });
+    };
+
+    let fields = fields
+        .iter()
+        .map(|field| match field {
+            DataType::Symbol(field, _) if field != "&" => Ok(field.clone()),
+            _ => Err(RuntimeError {
+                msg: format!("Invalid record field {:?}", field),
+            }),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut record_type = vec![symbol("record-type"), DataType::String(name.clone())];
+    record_type.extend(fields.iter().map(|field| keyword(field)));
+
+    let mut constructor = vec![symbol("make-record"), symbol(name)];
+    constructor.extend(fields.iter().map(|field| symbol(field)));
+
+    let mut forms = vec![
+        symbol("do"),
+        list(vec![symbol("def!"), symbol(name), list(record_type)]),
+        list(vec![
+            symbol("def!"),
+            symbol(&format!("->{}", name)),
+            list(vec![
+                symbol("fn*"),
+                list(fields.iter().map(|field| symbol(field)).collect()),
+                list(constructor),
+            ]),
+        ]),
+        list(vec![
+            symbol("def!"),
+            symbol(&format!("{}?", name)),
+            list(vec![
+                symbol("fn*"),
+                list(vec![symbol("value")]),
+                list(vec![symbol("record?"), symbol(name), symbol("value")]),
+            ]),
+        ]),
+    ];
+
+    if from_dict {
+        forms.push(list(vec![
+            symbol("def!"),
+            symbol(&format!("map->{}", name)),
+            list(vec![
+                symbol("fn*"),
+                list(vec![symbol("dict")]),
+                list(vec![symbol("dict->record"), symbol(name), symbol("dict")]),
+            ]),
+        ]));
+    }
+
+    for field in &fields {
+        forms.push(list(vec![
+            symbol("def!"),
+            symbol(&format!("{}-{}", name, field)),
+            list(vec![
+                symbol("fn*"),
+                list(vec![symbol("record")]),
+                list(vec![symbol("get"), symbol("record"), keyword(field)]),
+            ]),
+        ]));
+    }
+
+    if !implementations.is_empty() {
+        let mut extend = vec![keyword(name)];
+        extend.extend(implementations.iter().cloned());
+        forms.push(expand_extend_type(&extend)?);
+    }
+
+    forms.push(symbol(name));
+    Ok(list(forms))
+}
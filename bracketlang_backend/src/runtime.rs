@@ -0,0 +1,357 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::{
+    capability::{Capabilities, Capability, Limits},
+    evaluator::RuntimeError,
+    filesystem::FileSystem,
+    namespace::ModuleRegistry,
+    profile::Profiler,
+};
+
+/// How many evaluations may run inside one another, as when a native function
+/// calls back into the program. Each one uses the Rust stack, unlike frames
+/// on the evaluator's own stack.
+pub const MAX_NESTING: usize = 50;
+
+/// Where `prn` and friends send their text.
+pub type OutputSink = Box<dyn FnMut(&str)>;
+
+/// Where `input` reads from, given the prompt to show.
+pub type InputSource = Box<dyn FnMut(&str) -> Option<String>>;
+
+/// State shared by every environment belonging to one interpreter.
+pub struct Runtime {
+    pub modules: RefCell<ModuleRegistry>,
+    /// Frames allowed across every running evaluation, or `None` to only be
+    /// bounded by memory.
+    max_depth: Cell<Option<usize>>,
+    /// Frames held by evaluations waiting on the one nested inside them.
+    depth: Cell<usize>,
+    nesting: Cell<usize>,
+    stdout: RefCell<OutputSink>,
+    stdin: RefCell<InputSource>,
+    filesystem: RefCell<Rc<dyn FileSystem>>,
+    capabilities: Cell<Capabilities>,
+    limits: Cell<Limits>,
+    usage: Cell<Usage>,
+    /// Steps left across every evaluation until the host refuels, if limited.
+    fuel: Cell<Option<u64>>,
+    /// Set from any thread, such as a Ctrl-C handler, to stop the evaluation.
+    interrupt: Arc<AtomicBool>,
+    profiler: RefCell<Option<Profiler>>,
+}
+
+/// What the current evaluation has used of its `Limits`.
+#[derive(Clone, Copy, Default)]
+struct Usage {
+    steps: u64,
+    allocations: u64,
+    output: usize,
+    /// Whether the evaluation ran out of fuel or was interrupted.
+    stopped: bool,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            modules: RefCell::new(ModuleRegistry::default()),
+            max_depth: Cell::new(None),
+            depth: Cell::new(0),
+            nesting: Cell::new(0),
+            stdout: RefCell::new(Box::new(default_write)),
+            stdin: RefCell::new(Box::new(default_read_line)),
+            filesystem: RefCell::new(default_filesystem()),
+            capabilities: Cell::new(Capabilities::default()),
+            limits: Cell::new(Limits::default()),
+            usage: Cell::new(Usage::default()),
+            fuel: Cell::new(None),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            profiler: RefCell::new(None),
+        }
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Runtime {
+        Self::default()
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth.get()
+    }
+
+    pub fn set_max_depth(&self, max_depth: Option<usize>) {
+        self.max_depth.set(max_depth);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.get()
+    }
+
+    pub fn set_depth(&self, depth: usize) {
+        self.depth.set(depth);
+    }
+
+    /// Starts an evaluation inside the running one, failing if too many are
+    /// already running for the Rust stack to hold another.
+    pub fn enter_nested(&self) -> Result<(), RuntimeError> {
+        if self.nesting.get() >= MAX_NESTING {
+            return Err(RuntimeError {
+                msg: format!(
+                    "native functions called back into the program more than {} levels deep",
+                    MAX_NESTING
+                ),
+            });
+        }
+        self.nesting.set(self.nesting.get() + 1);
+        Ok(())
+    }
+
+    /// Ends an evaluation started with `enter_nested`, handing the stack back
+    /// to one holding `depth` frames.
+    pub fn exit_nested(&self, depth: usize) {
+        self.nesting.set(self.nesting.get() - 1);
+        self.depth.set(depth);
+    }
+
+    pub fn write(&self, text: &str) -> Result<(), RuntimeError> {
+        let mut usage = self.usage.get();
+        usage.output += text.len();
+        self.usage.set(usage);
+
+        if let Some(max_output) = self.limits.get().max_output
+            && usage.output > max_output
+        {
+            return Err(RuntimeError {
+                msg: format!("Output limit of {} bytes exceeded", max_output),
+            });
+        }
+
+        (self.stdout.borrow_mut())(text);
+        Ok(())
+    }
+
+    pub fn set_stdout(&self, sink: OutputSink) {
+        *self.stdout.borrow_mut() = sink;
+    }
+
+    pub fn read_line(&self, prompt: &str) -> Option<String> {
+        (self.stdin.borrow_mut())(prompt)
+    }
+
+    pub fn set_stdin(&self, source: InputSource) {
+        *self.stdin.borrow_mut() = source;
+    }
+
+    pub fn filesystem(&self) -> Rc<dyn FileSystem> {
+        self.filesystem.borrow().clone()
+    }
+
+    pub fn set_filesystem(&self, filesystem: Rc<dyn FileSystem>) {
+        *self.filesystem.borrow_mut() = filesystem;
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities.get()
+    }
+
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        self.capabilities.set(capabilities);
+    }
+
+    /// Fails with a message naming `name` unless `capability` is allowed.
+    pub fn require(&self, capability: Capability, name: &str) -> Result<(), RuntimeError> {
+        if self.capabilities().contains(capability) {
+            Ok(())
+        } else {
+            Err(RuntimeError {
+                msg: format!(
+                    "{} needs the {} capability, which is disabled",
+                    name,
+                    capability.name()
+                ),
+            })
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits.get()
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        self.limits.set(limits);
+    }
+
+    /// Starts counting against the limits from zero, for a new evaluation,
+    /// and forgets any interrupt that arrived before it began.
+    pub fn reset_usage(&self) {
+        self.usage.set(Usage::default());
+        self.interrupt.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether the current evaluation has gone over any of its limits.
+    pub fn over_limit(&self) -> bool {
+        let (usage, limits) = (self.usage.get(), self.limits.get());
+        usage.stopped
+            || limits.max_steps.is_some_and(|max| usage.steps > max)
+            || limits
+                .max_allocations
+                .is_some_and(|max| usage.allocations > max)
+            || limits.max_output.is_some_and(|max| usage.output > max)
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.get()
+    }
+
+    /// Gives the program `fuel` more steps to run, or unlimited steps if
+    /// `None`. Unlike `max_steps`, fuel isn't topped up between evaluations.
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.fuel.set(fuel);
+    }
+
+    /// The flag that stops the running evaluation when set. It is cleared
+    /// again once the evaluation has stopped.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profiler.borrow().is_some()
+    }
+
+    pub fn start_profiling(&self) -> Result<(), RuntimeError> {
+        let mut profiler = self.profiler.borrow_mut();
+        if profiler.is_some() {
+            return Err(RuntimeError {
+                msg: "The profiler is already running".to_string(),
+            });
+        }
+        *profiler = Some(Profiler::default());
+        Ok(())
+    }
+
+    pub fn stop_profiling(&self) -> Option<Profiler> {
+        self.profiler.borrow_mut().take()
+    }
+
+    pub fn profile_enter(&self, name: &Rc<str>) -> usize {
+        match self.profiler.borrow_mut().as_mut() {
+            Some(profiler) => profiler.enter(name),
+            None => 0,
+        }
+    }
+
+    pub fn profile_count_call(&self, name: &Rc<str>) {
+        if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+            profiler.count_call(name);
+        }
+    }
+
+    pub fn profile_exit(&self, depth: usize) {
+        if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+            profiler.exit(depth);
+        }
+    }
+
+    /// The function the profiler entered at `depth`, if it's still running.
+    pub fn profiled_name(&self, depth: usize) -> Option<Rc<str>> {
+        self.profiler.borrow().as_ref()?.name_at(depth)
+    }
+
+    fn stop(&self, msg: &str) -> Result<(), RuntimeError> {
+        let mut usage = self.usage.get();
+        usage.stopped = true;
+        self.usage.set(usage);
+
+        Err(RuntimeError {
+            msg: msg.to_string(),
+        })
+    }
+
+    pub fn count_step(&self) -> Result<(), RuntimeError> {
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            return self.stop("Interrupted");
+        }
+        match self.fuel.get() {
+            Some(0) => return self.stop("Out of fuel"),
+            Some(fuel) => self.fuel.set(Some(fuel - 1)),
+            None => {}
+        }
+
+        let mut usage = self.usage.get();
+        usage.steps += 1;
+        self.usage.set(usage);
+
+        match self.limits.get().max_steps {
+            Some(max_steps) if usage.steps > max_steps => Err(RuntimeError {
+                msg: format!("Step limit of {} exceeded", max_steps),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn count_allocation(&self, size: u64) -> Result<(), RuntimeError> {
+        let mut usage = self.usage.get();
+        usage.allocations += size;
+        self.usage.set(usage);
+
+        match self.limits.get().max_allocations {
+            Some(max_allocations) if usage.allocations > max_allocations => Err(RuntimeError {
+                msg: format!("Allocation limit of {} exceeded", max_allocations),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_write(text: &str) {
+    use std::io::{Write, stdout};
+
+    print!("{}", text);
+    stdout()
+        .flush()
+        .expect("Flushing stdout should have worked.");
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn default_write(text: &str) {
+    crate::js_print(text.strip_suffix('\n').unwrap_or(text));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_read_line(prompt: &str) -> Option<String> {
+    use std::io::stdin;
+
+    default_write(prompt);
+    let mut user_input = String::new();
+    match stdin().read_line(&mut user_input) {
+        Ok(_) => Some(user_input),
+        Err(_) => None,
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn default_read_line(prompt: &str) -> Option<String> {
+    crate::prompt(prompt)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_filesystem() -> Rc<dyn FileSystem> {
+    Rc::new(crate::filesystem::OsFileSystem)
+}
+
+/// There is no file system in the browser, so every runtime shares one held
+/// in memory.
+#[cfg(target_arch = "wasm32")]
+pub fn default_filesystem() -> Rc<dyn FileSystem> {
+    Rc::new(crate::filesystem::MemoryFileSystem::shared())
+}
@@ -0,0 +1,110 @@
+use crate::evaluator::RuntimeError;
+
+/// How deeply collections may be nested in the data formats. Their readers and
+/// writers recurse, so this keeps them well clear of the end of the stack.
+pub const MAX_DEPTH: usize = 128;
+
+/// Walks through the text given to one of the data format readers, keeping
+/// track of where it is so errors can point at the offending character.
+pub struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+    /// How many collections are open around the current position
+    depth: usize,
+}
+
+impl Scanner {
+    pub fn new(text: &str) -> Scanner {
+        Scanner {
+            chars: text.chars().collect(),
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    pub fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    pub fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    /// Consumes `c` if it's next.
+    pub fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn expect(&mut self, c: char) -> Result<(), RuntimeError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Consumes characters while `accept` holds, returning them.
+    pub fn take_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&accept) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// An error at the current position.
+    pub fn error(&self, msg: &str) -> RuntimeError {
+        self.error_at(self.pos, msg)
+    }
+
+    pub fn error_at(&self, pos: usize, msg: &str) -> RuntimeError {
+        let before = &self.chars[..pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+
+        RuntimeError {
+            msg: format!("{} at line {}, column {}", msg, line, column),
+        }
+    }
+
+    pub fn unexpected(&self) -> RuntimeError {
+        match self.peek() {
+            Some(c) => self.error(&format!("Unexpected character {:?}", c)),
+            None => self.error("Unexpected end of input"),
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Called as a collection opens at the current position, failing if that
+    /// would nest collections more than `MAX_DEPTH` deep.
+    pub fn enter(&mut self) -> Result<(), RuntimeError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("Collections are nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Called once the innermost open collection has been read.
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
@@ -0,0 +1,566 @@
+use std::{collections::HashMap, fmt};
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{
+        self, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+        value::{MapAccessDeserializer, MapDeserializer, SeqDeserializer},
+    },
+    forward_to_deserialize_any,
+    ser::{self, SerializeMap, SerializeSeq},
+};
+
+use crate::{evaluator::RuntimeError, json::key_name, variable_type::DataType};
+
+// Lists and vectors are sequences, dictionaries and records are maps and
+// keywords are strings. Values coming in become vectors and dictionaries with
+// keyword keys, so `{"a": [1]}` is read as `{:a [1]}`.
+
+impl ser::Error for RuntimeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        RuntimeError {
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl de::Error for RuntimeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        RuntimeError {
+            msg: msg.to_string(),
+        }
+    }
+}
+
+/// Converts any serializable Rust value into a `DataType`.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<DataType, RuntimeError> {
+    value.serialize(ValueSerializer)
+}
+
+/// Builds a Rust value out of a `DataType`.
+pub fn from_value<T: for<'de> Deserialize<'de>>(value: DataType) -> Result<T, RuntimeError> {
+    T::deserialize(value)
+}
+
+fn keyword_key(name: &str) -> String {
+    format!("{:?}", DataType::Keyword(name.to_string()))
+}
+
+impl Serialize for DataType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DataType::Nil() | DataType::Comment() => serializer.serialize_unit(),
+            DataType::Bool(value) => serializer.serialize_bool(*value),
+            DataType::Integer(num) => match i64::try_from(*num) {
+                Ok(num) => serializer.serialize_i64(num),
+                Err(_) => serializer.serialize_i128(*num),
+            },
+            DataType::Float(num) => serializer.serialize_f64(*num),
+            DataType::String(string) => serializer.serialize_str(string),
+            DataType::Keyword(name) | DataType::Symbol(name, _) => serializer.serialize_str(name),
+            DataType::List(items, _) | DataType::Vector(items, _) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => {
+                let mut map = serializer.serialize_map(Some(dict.len()))?;
+                for (key, value) in dict {
+                    map.serialize_entry(key_name(key), value)?;
+                }
+                map.end()
+            }
+            _ => Err(ser::Error::custom(format!("Can't serialize {:?}", self))),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = DataType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<DataType, E> {
+        Ok(DataType::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<DataType, E> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn visit_i128<E>(self, value: i128) -> Result<DataType, E> {
+        Ok(DataType::Integer(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<DataType, E> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<DataType, E> {
+        match i128::try_from(value) {
+            Ok(value) => Ok(DataType::Integer(value)),
+            Err(_) => Err(E::custom(format!("{} is too large for an integer", value))),
+        }
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<DataType, E> {
+        Ok(DataType::Float(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<DataType, E> {
+        Ok(DataType::String(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<DataType, E> {
+        Ok(DataType::String(value))
+    }
+
+    fn visit_unit<E>(self) -> Result<DataType, E> {
+        Ok(DataType::Nil())
+    }
+
+    fn visit_none<E>(self) -> Result<DataType, E> {
+        Ok(DataType::Nil())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<DataType, D::Error> {
+        DataType::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<DataType, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(DataType::Vector(items, None))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<DataType, A::Error> {
+        let mut dict = HashMap::new();
+        while let Some((key, value)) = map.next_entry::<String, DataType>()? {
+            dict.insert(keyword_key(&key), value);
+        }
+        Ok(DataType::Dictionary(dict, None))
+    }
+}
+
+impl<'de> Deserialize<'de> for DataType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<DataType, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, RuntimeError> for DataType {
+    type Deserializer = DataType;
+
+    fn into_deserializer(self) -> DataType {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for DataType {
+    type Error = RuntimeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RuntimeError> {
+        let mut value = self;
+        match &mut value {
+            DataType::Nil() | DataType::Comment() => visitor.visit_unit(),
+            DataType::Bool(value) => visitor.visit_bool(*value),
+            DataType::Integer(num) => match i64::try_from(*num) {
+                Ok(num) => visitor.visit_i64(num),
+                Err(_) => visitor.visit_i128(*num),
+            },
+            DataType::Float(num) => visitor.visit_f64(*num),
+            DataType::String(string) => visitor.visit_string(std::mem::take(string)),
+            DataType::Keyword(name) | DataType::Symbol(name, _) => {
+                visitor.visit_string(std::mem::take(name))
+            }
+            DataType::List(items, _) | DataType::Vector(items, _) => {
+                visitor.visit_seq(SeqDeserializer::new(std::mem::take(items).into_iter()))
+            }
+            DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => {
+                visitor.visit_map(MapDeserializer::new(std::mem::take(dict).into_iter().map(
+                    |(key, value)| (DataType::String(key_name(&key).to_string()), value),
+                )))
+            }
+            _ => Err(de::Error::custom(format!("Can't deserialize {:?}", value))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RuntimeError> {
+        match self {
+            DataType::Nil() => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RuntimeError> {
+        let mut value = self;
+        match &mut value {
+            // Unit variants
+            DataType::String(name) | DataType::Keyword(name) => visitor.visit_enum(
+                IntoDeserializer::<RuntimeError>::into_deserializer(std::mem::take(name)),
+            ),
+            // `{:Variant value}`
+            DataType::Dictionary(dict, _) if dict.len() == 1 => {
+                visitor.visit_enum(MapAccessDeserializer::new(MapDeserializer::new(
+                    std::mem::take(dict)
+                        .into_iter()
+                        .map(|(key, value)| (DataType::String(key_name(&key).to_string()), value)),
+                )))
+            }
+            _ => Err(de::Error::custom(format!(
+                "Expected an enum variant, got {:?}",
+                value
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Builds a `DataType` out of whatever is serialized into it.
+struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = DataType;
+    type Error = RuntimeError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
+
+    fn serialize_bool(self, value: bool) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Bool(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_i128(self, value: i128) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value))
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Integer(value.into()))
+    }
+
+    fn serialize_u128(self, value: u128) -> Result<DataType, RuntimeError> {
+        match i128::try_from(value) {
+            Ok(value) => Ok(DataType::Integer(value)),
+            Err(_) => Err(ser::Error::custom(format!(
+                "{} is too large for an integer",
+                value
+            ))),
+        }
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Float(value.into()))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Float(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<DataType, RuntimeError> {
+        Ok(DataType::String(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<DataType, RuntimeError> {
+        Ok(DataType::String(value.to_string()))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Vector(
+            value
+                .iter()
+                .map(|byte| DataType::Integer((*byte).into()))
+                .collect(),
+            None,
+        ))
+    }
+
+    fn serialize_none(self) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Nil())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<DataType, RuntimeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Nil())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Nil())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Keyword(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<DataType, RuntimeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<DataType, RuntimeError> {
+        let mut dict = HashMap::new();
+        dict.insert(keyword_key(variant), to_value(value)?);
+        Ok(DataType::Dictionary(dict, None))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, RuntimeError> {
+        Ok(SeqSerializer {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, RuntimeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, RuntimeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<SeqSerializer>, RuntimeError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, RuntimeError> {
+        Ok(MapSerializer {
+            dict: HashMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, RuntimeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<MapSerializer>, RuntimeError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+struct SeqSerializer {
+    items: Vec<DataType>,
+}
+
+impl SerializeSeq for SeqSerializer {
+    type Ok = DataType;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        self.items.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Vector(self.items, None))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = DataType;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<DataType, RuntimeError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = DataType;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<DataType, RuntimeError> {
+        SerializeSeq::end(self)
+    }
+}
+
+struct MapSerializer {
+    dict: HashMap<String, DataType>,
+    key: Option<String>,
+}
+
+impl SerializeMap for MapSerializer {
+    type Ok = DataType;
+    type Error = RuntimeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), RuntimeError> {
+        self.key = Some(match to_value(key)? {
+            DataType::String(ref name) => keyword_key(name),
+            key => format!("{:?}", key),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        let Some(key) = self.key.take() else {
+            return Err(ser::Error::custom("Map value serialized before its key"));
+        };
+        self.dict.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<DataType, RuntimeError> {
+        Ok(DataType::Dictionary(self.dict, None))
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = DataType;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), RuntimeError> {
+        self.dict.insert(keyword_key(key), to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<DataType, RuntimeError> {
+        SerializeMap::end(self)
+    }
+}
+
+/// Wraps a tuple or struct variant's fields as `{:Variant fields}`.
+struct VariantSerializer<T> {
+    variant: &'static str,
+    inner: T,
+}
+
+impl<T> VariantSerializer<T> {
+    fn wrap(variant: &str, value: DataType) -> DataType {
+        let mut dict = HashMap::new();
+        dict.insert(keyword_key(variant), value);
+        DataType::Dictionary(dict, None)
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = DataType;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RuntimeError> {
+        SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<DataType, RuntimeError> {
+        Ok(Self::wrap(self.variant, SerializeSeq::end(self.inner)?))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = DataType;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), RuntimeError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<DataType, RuntimeError> {
+        Ok(Self::wrap(self.variant, SerializeMap::end(self.inner)?))
+    }
+}
@@ -122,8 +122,8 @@ fn test_eval_simple_addition() {
 fn test_eval_simple_addition_with_strings() {
     let env = create_default_repl_env();
     let result = run_line("(+ \"Hello, \" \"World!\")", env);
-    if let DataType::String(num_result) = result {
-        assert_eq!(num_result, "Hello, World!".to_string());
+    if let DataType::String(num_result) = &result {
+        assert_eq!(*num_result, "Hello, World!".to_string());
     } else {
         assert!(false);
     }
@@ -381,9 +381,9 @@ fn test_reset_atom() {
 fn test_cons() {
     let env = create_default_repl_env();
     let result = run_line("(cons 1 (list 2 3))", env.clone());
-    if let DataType::List(list) = result {
+    if let DataType::List(list, _) = &result {
         assert_eq!(
-            list,
+            *list,
             vec![
                 DataType::Integer(1),
                 DataType::Integer(2),
@@ -399,12 +399,12 @@ fn test_cons() {
 fn test_quote() {
     let env = create_default_repl_env();
     let result = run_line("(quote (b c))", env.clone());
-    if let DataType::List(list) = result {
+    if let DataType::List(list, _) = &result {
         assert_eq!(
-            list,
+            *list,
             vec![
-                DataType::Symbol("b".to_string()),
-                DataType::Symbol("c".to_string())
+                DataType::Symbol("b".to_string(), None),
+                DataType::Symbol("c".to_string(), None)
             ]
         );
     } else {
@@ -417,13 +417,13 @@ fn test_quasiquote() {
     let env = create_default_repl_env();
     let result = run_line("(quasiquote (a lst d))", env.clone());
 
-    if let DataType::List(list) = result {
+    if let DataType::List(list, _) = &result {
         assert_eq!(
-            list,
+            *list,
             vec![
-                DataType::Symbol("a".to_string()),
-                DataType::Symbol("lst".to_string()),
-                DataType::Symbol("d".to_string())
+                DataType::Symbol("a".to_string(), None),
+                DataType::Symbol("lst".to_string(), None),
+                DataType::Symbol("d".to_string(), None)
             ]
         );
     } else {
@@ -437,16 +437,19 @@ fn test_quasiquote_unquote() {
     let _ = run_line("(def! lst (quote (b c)))", env.clone());
     let result = run_line("(quasiquote (a (unquote lst) d))", env.clone());
 
-    if let DataType::List(list) = result {
+    if let DataType::List(list, _) = &result {
         assert_eq!(
-            list,
+            *list,
             vec![
-                DataType::Symbol("a".to_string()),
-                DataType::List(vec![
-                    DataType::Symbol("b".to_string()),
-                    DataType::Symbol("c".to_string())
-                ]),
-                DataType::Symbol("d".to_string())
+                DataType::Symbol("a".to_string(), None),
+                DataType::List(
+                    vec![
+                        DataType::Symbol("b".to_string(), None),
+                        DataType::Symbol("c".to_string(), None)
+                    ],
+                    None
+                ),
+                DataType::Symbol("d".to_string(), None)
             ]
         );
     } else {
@@ -460,14 +463,14 @@ fn test_splice_unquote() {
     let _ = run_line("(def! lst (quote (b c)))", env.clone());
     let result = run_line("(quasiquote (a (splice-unquote lst) d))", env.clone());
 
-    if let DataType::List(list) = result {
+    if let DataType::List(list, _) = &result {
         assert_eq!(
-            list,
+            *list,
             vec![
-                DataType::Symbol("a".to_string()),
-                DataType::Symbol("b".to_string()),
-                DataType::Symbol("c".to_string()),
-                DataType::Symbol("d".to_string())
+                DataType::Symbol("a".to_string(), None),
+                DataType::Symbol("b".to_string(), None),
+                DataType::Symbol("c".to_string(), None),
+                DataType::Symbol("d".to_string(), None)
             ]
         );
     } else {
@@ -503,3 +506,1861 @@ fn test_macros_2() {
         panic!();
     }
 }
+
+#[test]
+fn test_with_meta() {
+    let env = create_default_repl_env();
+    let result = run_line("(meta (with-meta [1 2] {:a 1}))", env.clone());
+
+    let DataType::Dictionary(dict, _) = &result else {
+        panic!();
+    };
+    assert_eq!(dict.get(":a"), Some(&DataType::Integer(1)));
+}
+
+#[test]
+fn test_meta_ignored_by_equality() {
+    let env = create_default_repl_env();
+    let result = run_line("(= (with-meta (list 1 2) {:a 1}) (list 1 2))", env.clone());
+
+    assert_eq!(result, DataType::Bool(true));
+}
+
+#[test]
+fn test_reader_meta_on_def() {
+    let env = create_default_repl_env();
+    let _ = run_line("(def! ^:private ^:test f (fn* (x) x))", env.clone());
+
+    let result = run_line("(get (meta f) :private)", env.clone());
+    assert_eq!(result, DataType::Bool(true));
+    let result = run_line("(get (meta f) :test)", env.clone());
+    assert_eq!(result, DataType::Bool(true));
+}
+
+#[test]
+fn test_vary_meta() {
+    let env = create_default_repl_env();
+    let _ = run_line("(def! v ^{:count 1} [1 2 3])", env.clone());
+    let result = run_line(
+        "(get (meta (vary-meta v (fn* (m) {:count 2}))) :count)",
+        env.clone(),
+    );
+
+    assert_eq!(result, DataType::Integer(2));
+    assert_eq!(
+        run_line("(get (meta v) :count)", env.clone()),
+        DataType::Integer(1)
+    );
+    assert_eq!(
+        run_line("(meta (vary-meta [1] assoc :b 2))", env.clone()),
+        run_line("{:b 2}", env.clone())
+    );
+}
+
+fn module_env() -> Rc<RefCell<Environment>> {
+    let env = create_default_repl_env();
+    let _ = run_line("(def! *load-path* [\"test_modules\"])", env.clone());
+    env
+}
+
+#[test]
+fn test_require_as() {
+    let env = module_env();
+    let _ = run_line("(require [util.math :as m])", env.clone());
+    let result = run_line("(m/cube 3)", env.clone());
+
+    assert_eq!(result, DataType::Integer(27));
+    assert_eq!(
+        run_line("(util.math/square 4)", env.clone()),
+        DataType::Integer(16)
+    );
+}
+
+#[test]
+fn test_require_refer() {
+    let env = module_env();
+    let _ = run_line(
+        "(ns app (:require [util.math :refer [square]]))",
+        env.clone(),
+    );
+    let env = namespace::current_namespace(&env);
+    let result = run_line("(square 5)", env.clone());
+
+    assert_eq!(result, DataType::Integer(25));
+    assert!(eval(&read("cube".to_string()).unwrap(), env.clone(), env.clone()).is_err());
+}
+
+#[test]
+fn test_require_private() {
+    let env = module_env();
+    let _ = run_line("(require 'util.math)", env.clone());
+    let result = eval(
+        &read("(util.math/helper 2)".to_string()).unwrap(),
+        env.clone(),
+        env.clone(),
+    );
+
+    assert!(result.unwrap_err().msg.contains("private"));
+}
+
+#[test]
+fn test_private_scalar() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(ns lib) (def! ^:private secret 5) (def! shown (+ secret 1)) (ns user)")
+        .unwrap();
+
+    assert_eq!(interpreter.eval_str("lib/shown"), Ok(DataType::Integer(6)));
+    assert_eq!(
+        interpreter.eval_str("lib/secret"),
+        Err(Error::Runtime("lib/secret is private".to_string()))
+    );
+    assert_eq!(
+        interpreter.eval_str("(ns app (:require [lib :refer [secret]]))"),
+        Err(Error::Runtime("lib/secret is private".to_string()))
+    );
+    interpreter
+        .eval_str("(ns other (:require [lib :refer :all]))")
+        .unwrap();
+    assert!(interpreter.eval_str("secret").is_err());
+    assert_eq!(interpreter.eval_str("shown"), Ok(DataType::Integer(6)));
+
+    // Defining the name again without `^:private` makes it public
+    interpreter
+        .eval_str("(ns lib) (def! secret 7) (ns user)")
+        .unwrap();
+    assert_eq!(interpreter.eval_str("lib/secret"), Ok(DataType::Integer(7)));
+}
+
+#[test]
+fn test_ns_switches_environment() {
+    let interpreter = Interpreter::new();
+
+    interpreter
+        .eval_str("(ns foo) (def! x 1) (def! y 2) (ns bar) (def! x 10)")
+        .unwrap();
+
+    assert_eq!(interpreter.eval_str("x"), Ok(DataType::Integer(10)));
+    assert_eq!(interpreter.eval_str("foo/x"), Ok(DataType::Integer(1)));
+    assert!(interpreter.eval_str("bar/y").is_err());
+    assert_eq!(
+        interpreter.eval_str("(ns foo) (+ x y)"),
+        Ok(DataType::Integer(3))
+    );
+    assert!(interpreter.eval_str("(ns user) x").is_err());
+}
+
+#[test]
+fn test_require_loads_once() {
+    let env = module_env();
+    let _ = run_line("(require [counter :as c])", env.clone());
+    let _ = run_line("(reset! c/state 5)", env.clone());
+    let _ = run_line("(require [counter :as c])", env.clone());
+
+    assert_eq!(run_line("@c/state", env.clone()), DataType::Integer(5));
+}
+
+#[test]
+fn test_require_cycle() {
+    let env = module_env();
+    let result = eval(
+        &read("(require [cycle_a])".to_string()).unwrap(),
+        env.clone(),
+        env.clone(),
+    );
+
+    assert_eq!(
+        result.unwrap_err().msg,
+        "Cyclic require: cycle_a -> cycle_b -> cycle_a"
+    );
+}
+
+#[test]
+fn test_loop_recur() {
+    let env = create_default_repl_env();
+    let result = run_line(
+        "(loop (i 0 acc 0) (if (> i 10000) acc (recur (+ i 1) (+ acc i))))",
+        env.clone(),
+    );
+
+    assert_eq!(result, DataType::Integer(50005000));
+}
+
+#[test]
+fn test_fn_recur() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! countdown (fn* (n) (if (= n 0) :done (recur (- n 1)))))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(countdown 10000)", env.clone()),
+        DataType::Keyword("done".to_string())
+    );
+}
+
+#[test]
+fn test_recur_not_in_tail_position() {
+    let env = create_default_repl_env();
+    let result = eval(
+        &read("(loop (i 0) (+ 1 (recur (+ i 1))))".to_string()).unwrap(),
+        env.clone(),
+        env.clone(),
+    );
+
+    assert!(result.unwrap_err().msg.contains("tail position"));
+}
+
+#[test]
+fn test_recur_check_does_not_expand_macros() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str(
+            "(def! expansions (atom 0))
+             (defmacro! counted (fn* (x) (do (swap! expansions (fn* (n) (+ n 1))) x)))
+             (def! make (fn* () (fn* (n) (counted n))))",
+        )
+        .unwrap();
+
+    interpreter.eval_str("(make) (make) (make)").unwrap();
+    assert_eq!(
+        interpreter.eval_str("@expansions"),
+        Ok(DataType::Integer(0))
+    );
+
+    assert_eq!(
+        interpreter.eval_str(
+            "(def! down (fn* (n) (cond (= n 0) :done :else (recur (- n 1))))) (down 100)"
+        ),
+        Ok(DataType::Keyword("done".to_string()))
+    );
+    assert!(matches!(
+        interpreter.eval_str("(def! bad (fn* (n) (cond true (+ 1 (recur n)))))\n(bad 1)"),
+        Err(Error::Runtime(msg)) if msg.contains("tail position")
+    ));
+}
+
+#[test]
+fn test_recur_checked_when_defined() {
+    let interpreter = Interpreter::new();
+
+    assert!(matches!(
+        interpreter.eval_str("(def! g (fn* (n) (+ 1 (recur n))))"),
+        Err(Error::Runtime(msg)) if msg.contains("tail position")
+    ));
+    assert_eq!(interpreter.get_global("g"), None);
+
+    assert!(matches!(
+        interpreter.eval_str("(try* (fn* (n) (+ 1 (recur n))) (fn* (e) e))"),
+        Err(Error::Runtime(msg)) if msg.contains("tail position")
+    ));
+    assert!(matches!(
+        interpreter.eval_str("(try* 1 (fn* (e) (+ 1 (recur e))))"),
+        Err(Error::Runtime(msg)) if msg.contains("tail position")
+    ));
+}
+
+#[test]
+fn test_recursive_closure_calls() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! fib (fn* (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))",
+        env.clone(),
+    );
+
+    assert_eq!(run_line("(fib 10)", env.clone()), DataType::Integer(55));
+    assert_eq!(
+        run_line("(let* (a 2 b (+ a 1)) b)", env.clone()),
+        DataType::Integer(3)
+    );
+}
+
+#[test]
+fn test_max_depth_exceeded() {
+    let env = create_default_repl_env();
+    env.borrow().runtime().unwrap().set_max_depth(Some(1000));
+    let _ = run_line("(def! f (fn* (n) (+ 1 (f (- n 1)))))", env.clone());
+    let result = run_line("(try* (f 100000) (fn* (e) e))", env.clone());
+
+    assert_eq!(
+        result,
+        DataType::String("maximum recursion depth exceeded".to_string())
+    );
+    assert_eq!(run_line("(+ 1 2)", env.clone()), DataType::Integer(3));
+}
+
+#[test]
+fn test_recursion_through_native_callbacks() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! h (fn* () (vary-meta [1] (fn* (m) (h)))))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(try* (h) (fn* (e) e))", env.clone()),
+        DataType::String(
+            "native functions called back into the program more than 50 levels deep".to_string()
+        )
+    );
+    assert_eq!(run_line("(+ 1 2)", env.clone()), DataType::Integer(3));
+}
+
+#[test]
+fn test_max_depth_counts_nested_evaluations() {
+    let env = create_default_repl_env();
+    env.borrow().runtime().unwrap().set_max_depth(Some(200));
+    let _ = run_line(
+        "(def! f (fn* (n) (if (= n 0) 0 (+ 1 (get (meta (vary-meta [] (fn* (m) {:n (f (- n 1))}))) :n)))))",
+        env.clone(),
+    );
+
+    assert_eq!(run_line("(f 5)", env.clone()), DataType::Integer(5));
+    assert_eq!(
+        run_line("(try* (f 50) (fn* (e) e))", env.clone()),
+        DataType::String("maximum recursion depth exceeded".to_string())
+    );
+}
+
+#[test]
+fn test_print_deeply_nested_data() {
+    let mut value = DataType::Integer(1);
+    for _ in 0..100_000 {
+        value = DataType::Vector(vec![value], None);
+    }
+
+    let printed = format!("{:?}", value);
+    assert_eq!(printed.len(), 200_001);
+    assert_eq!(&printed[99_998..100_003], "[[1]]");
+}
+
+#[test]
+fn test_interpreter_drops_deeply_nested_data() {
+    let mut value = DataType::Integer(1);
+    for _ in 0..1_000_000 {
+        value = DataType::List(vec![value], None);
+    }
+    let mut dict = DataType::Nil();
+    for _ in 0..100_000 {
+        dict = DataType::Dictionary(
+            std::collections::HashMap::from([(":next".to_string(), dict)]),
+            None,
+        );
+    }
+
+    let interpreter = Interpreter::new();
+    interpreter.set_global("deep", value);
+    interpreter.set_global("deep-dict", dict);
+    assert_eq!(
+        interpreter.eval_str("(def! copy deep) (list? copy)"),
+        Ok(DataType::Bool(true))
+    );
+    assert_eq!(
+        interpreter.eval_str("(def! deep nil) (def! copy nil)"),
+        Ok(DataType::Nil())
+    );
+    drop(interpreter);
+}
+
+#[test]
+fn test_recursion_depth_defaults_to_unbounded() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(def! count-up (fn* (n) (if (= n 0) 0 (+ 1 (count-up (- n 1))))))")
+        .unwrap();
+
+    assert_eq!(
+        interpreter.eval_str("(count-up 120000)"),
+        Ok(DataType::Integer(120000))
+    );
+
+    let limited = Interpreter::builder().max_depth(1000).build();
+    limited
+        .eval_str("(def! count-up (fn* (n) (if (= n 0) 0 (+ 1 (count-up (- n 1))))))")
+        .unwrap();
+    assert_eq!(
+        limited.eval_str("(count-up 2000)"),
+        Err(Error::Runtime(
+            "maximum recursion depth exceeded".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_deep_recursion() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! count-up (fn* (n) (if (= n 0) 0 (+ 1 (count-up (- n 1))))))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(count-up 10000)", env.clone()),
+        DataType::Integer(10000)
+    );
+}
+
+#[test]
+fn test_deep_recursion_through_map() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! depth (fn* (n) (if (= n 0) 0 (+ 1 (first (map depth (list (- n 1))))))))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(depth 5000)", env.clone()),
+        DataType::Integer(5000)
+    );
+}
+
+#[test]
+fn test_shift_reset() {
+    let env = create_default_repl_env();
+    let result = run_line("(+ 1 (reset (+ 10 (shift k (k (k 100))))))", env.clone());
+
+    assert_eq!(result, DataType::Integer(121));
+}
+
+#[test]
+fn test_shift_early_exit() {
+    let env = create_default_repl_env();
+    let result = run_line(
+        "(reset (loop (i 0) (if (= i 5) (shift k (* i 100)) (recur (+ i 1)))))",
+        env.clone(),
+    );
+
+    assert_eq!(result, DataType::Integer(500));
+}
+
+#[test]
+fn test_continuation_is_reusable() {
+    let env = create_default_repl_env();
+    let _ = run_line("(def! k (reset (* 2 (shift k k))))", env.clone());
+
+    assert_eq!(run_line("(k 5)", env.clone()), DataType::Integer(10));
+    assert_eq!(
+        run_line("(map k [1 2 3])", env.clone()),
+        run_line("(list 2 4 6)", env.clone())
+    );
+}
+
+#[test]
+fn test_shift_outside_reset() {
+    let env = create_default_repl_env();
+    let result = eval(
+        &read("(shift k 1)".to_string()).unwrap(),
+        env.clone(),
+        env.clone(),
+    );
+
+    assert_eq!(result.unwrap_err().msg, "shift used outside of reset");
+}
+
+#[test]
+fn test_generator_next() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! g (generator (fn* () (do (yield 1) (yield 2) 3))))",
+        env.clone(),
+    );
+
+    assert_eq!(run_line("(next g)", env.clone()), DataType::Integer(1));
+    assert_eq!(run_line("(next g)", env.clone()), DataType::Integer(2));
+    assert_eq!(run_line("(next g)", env.clone()), DataType::Nil());
+}
+
+#[test]
+fn test_infinite_generator() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! naturals (generator (fn* () (loop (i 0) (do (yield i) (recur (+ i 1)))))))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(first (rest (rest naturals)))", env.clone()),
+        DataType::Integer(2)
+    );
+    assert_eq!(
+        run_line("(nth naturals 100)", env.clone()),
+        DataType::Integer(100)
+    );
+    assert_eq!(
+        run_line("(first naturals)", env.clone()),
+        DataType::Integer(0)
+    );
+}
+
+#[test]
+fn test_generator_tree_walk() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! walk (fn* (tree) (if (list? tree) (map walk tree) (yield tree))))",
+        env.clone(),
+    );
+    let result = run_line(
+        "(map (fn* (x) (* x 10)) (generator (fn* () (walk '(1 (2 3) ((4)))))))",
+        env.clone(),
+    );
+
+    assert_eq!(result, run_line("(list 10 20 30 40)", env.clone()));
+}
+
+#[test]
+fn test_match_literals_and_bindings() {
+    let env = create_default_repl_env();
+    let _ = run_line(
+        "(def! describe (fn* (x) (match x 0 :zero \"hi\" :greeting (? int? n) :when (> n 10) :big (? int?) :small _ :other)))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(describe 0)", env.clone()),
+        DataType::Keyword("zero".to_string())
+    );
+    assert_eq!(
+        run_line("(describe \"hi\")", env.clone()),
+        DataType::Keyword("greeting".to_string())
+    );
+    assert_eq!(
+        run_line("(describe 42)", env.clone()),
+        DataType::Keyword("big".to_string())
+    );
+    assert_eq!(
+        run_line("(describe 3)", env.clone()),
+        DataType::Keyword("small".to_string())
+    );
+    assert_eq!(
+        run_line("(describe 1.5)", env.clone()),
+        DataType::Keyword("other".to_string())
+    );
+}
+
+#[test]
+fn test_match_nested_sequences() {
+    let env = create_default_repl_env();
+    let result = run_line(
+        "(match (list 1 [2 3] 4 5) (a [b c] & more) (list a b c more))",
+        env.clone(),
+    );
+
+    assert_eq!(result, run_line("(list 1 2 3 (list 4 5))", env.clone()));
+}
+
+#[test]
+fn test_match_dictionary() {
+    let env = create_default_repl_env();
+    let result = run_line(
+        "(match {:type :circle :r 2} {:type :square :side s} (* s s) {:type :circle :r r} (* 3 (* r r)))",
+        env.clone(),
+    );
+
+    assert_eq!(result, DataType::Integer(12));
+}
+
+#[test]
+fn test_match_no_match() {
+    let env = create_default_repl_env();
+    let result = eval(
+        &read("(match [1 2] [a] a (x y z) x)".to_string()).unwrap(),
+        env.clone(),
+        env.clone(),
+    );
+
+    assert_eq!(result.unwrap_err().msg, "No match for [1 2]");
+}
+
+#[test]
+fn test_defmulti_dispatch() {
+    let env = create_default_repl_env();
+    run_line(
+        "(defmulti area (fn* (shape) (get shape :kind)))",
+        env.clone(),
+    );
+    run_line(
+        "(defmethod area :square [s] (* (get s :side) (get s :side)))",
+        env.clone(),
+    );
+    run_line("(defmethod area :default [s] 0)", env.clone());
+
+    assert_eq!(
+        run_line("(area {:kind :square :side 3})", env.clone()),
+        DataType::Integer(9)
+    );
+    assert_eq!(
+        run_line("(area {:kind :blob})", env.clone()),
+        DataType::Integer(0)
+    );
+}
+
+#[test]
+fn test_protocol_extend_type() {
+    let env = create_default_repl_env();
+    run_line("(defprotocol Sized (size [this]))", env.clone());
+    run_line(
+        "(extend-type :vector Sized (size [this] (count (apply list this))))",
+        env.clone(),
+    );
+    run_line("(extend-type :string Sized (size [this] -1))", env.clone());
+
+    assert_eq!(
+        run_line("(size [1 2 3])", env.clone()),
+        DataType::Integer(3)
+    );
+    assert_eq!(
+        run_line("(size \"abc\")", env.clone()),
+        DataType::Integer(-1)
+    );
+
+    let result = eval(
+        &read("(size 1)".to_string()).unwrap(),
+        env.clone(),
+        env.clone(),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_multimethod_introspection() {
+    let env = create_default_repl_env();
+    run_line("(defmulti describe type)", env.clone());
+    run_line("(defmethod describe :int [x] \"int\")", env.clone());
+    run_line("(defmethod describe :string [x] \"string\")", env.clone());
+
+    assert_eq!(
+        run_line("(count (keys (methods describe)))", env.clone()),
+        DataType::Integer(2)
+    );
+    assert_eq!(
+        run_line("(get-method describe :float)", env.clone()),
+        DataType::Nil()
+    );
+    run_line("(remove-method describe :int)", env.clone());
+    assert_eq!(
+        run_line("(count (keys (methods describe)))", env.clone()),
+        DataType::Integer(1)
+    );
+}
+
+#[test]
+fn test_defrecord() {
+    let env = create_default_repl_env();
+    run_line("(defrecord Point [x y])", env.clone());
+    run_line("(def! p (->Point 1 2))", env.clone());
+
+    assert_eq!(run_line("(Point-y p)", env.clone()), DataType::Integer(2));
+    assert_eq!(run_line("(Point? p)", env.clone()), DataType::Bool(true));
+    assert_eq!(
+        run_line("(Point? {:x 1 :y 2})", env.clone()),
+        DataType::Bool(false)
+    );
+    assert_eq!(
+        format!("{:?}", run_line("p", env.clone())),
+        "#Point{:x 1 :y 2}"
+    );
+    assert_eq!(
+        run_line("(type p)", env.clone()),
+        DataType::Keyword("Point".to_string())
+    );
+}
+
+#[test]
+fn test_record_dict_interop() {
+    let env = create_default_repl_env();
+    run_line("(defrecord Point [x y])", env.clone());
+    run_line("(def! p (assoc (->Point 1 2) :y 5))", env.clone());
+
+    assert_eq!(run_line("(get p :y)", env.clone()), DataType::Integer(5));
+    assert_eq!(run_line("(Point? p)", env.clone()), DataType::Bool(true));
+    assert_eq!(
+        run_line("(= p (map->Point {:x 1 :y 5}))", env.clone()),
+        DataType::Bool(true)
+    );
+    assert_eq!(
+        run_line("(dict? (dissoc p :x))", env.clone()),
+        DataType::Bool(true)
+    );
+}
+
+#[test]
+fn test_record_equality_includes_type() {
+    let env = create_default_repl_env();
+    run_line("(defrecord Point [x y])", env.clone());
+    run_line("(deftype Pair [x y])", env.clone());
+
+    assert_eq!(
+        run_line("(= (->Point 1 2) (->Pair 1 2))", env.clone()),
+        DataType::Bool(false)
+    );
+    assert_eq!(
+        run_line("(= (->Point 1 2) {:x 1 :y 2})", env.clone()),
+        DataType::Bool(false)
+    );
+}
+
+#[test]
+fn test_record_implements_protocol() {
+    let env = create_default_repl_env();
+    run_line("(defprotocol Shape (area [this]))", env.clone());
+    run_line(
+        "(defrecord Square [side] Shape (area [this] (* (Square-side this) (Square-side this))))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(area (->Square 4))", env.clone()),
+        DataType::Integer(16)
+    );
+}
+
+#[test]
+fn test_prn_writes_to_runtime_stdout() {
+    let env = create_default_repl_env();
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    env.borrow()
+        .runtime()
+        .unwrap()
+        .set_stdout(Box::new(move |text| sink.borrow_mut().push_str(text)));
+
+    run_line("(prn \"hello\" 1 :a)", env.clone());
+    run_line("(prn [1 2])", env.clone());
+
+    assert_eq!(*output.borrow(), "hello 1 :a\n[1 2]\n");
+}
+
+#[test]
+fn test_input_reads_from_runtime_stdin() {
+    let env = create_default_repl_env();
+    env.borrow()
+        .runtime()
+        .unwrap()
+        .set_stdin(Box::new(|prompt| Some(format!("answer to {}", prompt))));
+
+    assert_eq!(
+        run_line("(input \"name?\")", env.clone()),
+        DataType::String("answer to name?".to_string())
+    );
+}
+
+#[test]
+fn test_native_calls_back_into_closure() {
+    let env = create_default_repl_env();
+    let result = run_line(
+        "(get (meta (vary-meta (with-meta [1] {:n 1}) (fn* (m n) (assoc m :n (+ (get m :n) n))) 41)) :n)",
+        env.clone(),
+    );
+
+    assert_eq!(result, DataType::Integer(42));
+}
+
+#[test]
+fn test_interpreter_eval_and_call() {
+    let interpreter = Interpreter::new();
+    interpreter.set_global("base", DataType::Integer(10));
+
+    assert_eq!(
+        interpreter.eval_str("(def! add (fn* (a) (+ a base))) (add 1)"),
+        Ok(DataType::Integer(11))
+    );
+    assert_eq!(
+        interpreter.call("add", &[DataType::Integer(5)]),
+        Ok(DataType::Integer(15))
+    );
+    assert_eq!(interpreter.get_global("base"), Some(DataType::Integer(10)));
+    assert_eq!(
+        interpreter.eval_str("(not false)"),
+        Ok(DataType::Bool(true))
+    );
+}
+
+#[test]
+fn test_interpreter_errors() {
+    let interpreter = Interpreter::new();
+
+    assert!(matches!(interpreter.eval_str("(+ 1"), Err(Error::Parse(_))));
+    assert_eq!(
+        interpreter.eval_str("(undefined-fn)"),
+        Err(Error::Runtime("Unknown symbol: undefined-fn".to_string()))
+    );
+    assert!(matches!(
+        interpreter.call("missing", &[]),
+        Err(Error::Runtime(_))
+    ));
+    assert!(matches!(
+        interpreter.eval_file("does/not/exist.bl"),
+        Err(Error::Io(_))
+    ));
+}
+
+#[test]
+fn test_native_lookup_sees_locals() {
+    let interpreter = Interpreter::new();
+    interpreter.register_native("lookup-zz", |ctx, _| {
+        Ok(ctx.lookup("zz").unwrap_or(DataType::Nil()))
+    });
+
+    assert_eq!(
+        interpreter.eval_str("(let* [zz 5] (lookup-zz))"),
+        Ok(DataType::Integer(5))
+    );
+    interpreter.eval_str("(def! zz 1)").unwrap();
+    assert_eq!(
+        interpreter.eval_str("((fn* (zz) (lookup-zz)) 7)"),
+        Ok(DataType::Integer(7))
+    );
+    assert_eq!(
+        interpreter.eval_str("(let* [zz 9] (map (fn* (x) (lookup-zz)) [1]))"),
+        interpreter.eval_str("(list 9)")
+    );
+    assert_eq!(
+        interpreter.eval_str("(lookup-zz)"),
+        Ok(DataType::Integer(1))
+    );
+}
+
+#[test]
+fn test_interpreter_reads_each_form() {
+    let interpreter = Interpreter::new();
+
+    assert_eq!(
+        interpreter.eval_str("(+ 1 2) ; trailing"),
+        Ok(DataType::Integer(3))
+    );
+    assert_eq!(interpreter.eval_str("\n"), Ok(DataType::Nil()));
+    assert_eq!(
+        interpreter.eval_str("; only a comment"),
+        Ok(DataType::Nil())
+    );
+    assert_eq!(
+        interpreter.eval_str("(def! a 1)\n(+ a 9)"),
+        Ok(DataType::Integer(10))
+    );
+    assert!(matches!(
+        interpreter.eval_str("(+ 1 2))(+ 5 5)"),
+        Err(Error::Parse(_))
+    ));
+}
+
+#[test]
+fn test_interpreter_stdout() {
+    let interpreter = Interpreter::new();
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    interpreter.set_stdout(move |text| sink.borrow_mut().push_str(text));
+
+    interpreter.eval_str("(prn \"one\") (prn 2)").unwrap();
+
+    assert_eq!(*output.borrow(), "one\n2\n");
+}
+
+#[test]
+fn test_register_fn_captures_state() {
+    let interpreter = Interpreter::new();
+    let counter = Rc::new(std::cell::Cell::new(0));
+    let count = counter.clone();
+    interpreter.register_fn("bump!", move |by: i64| {
+        count.set(count.get() + by);
+        count.get()
+    });
+
+    interpreter.eval_str("(bump! 2) (bump! 3)").unwrap();
+
+    assert_eq!(counter.get(), 5);
+    assert_eq!(interpreter.eval_str("(bump! 1)"), Ok(DataType::Integer(6)));
+}
+
+#[test]
+fn test_register_fn_conversions() {
+    let interpreter = Interpreter::new();
+    interpreter.register_fn("total", |items: Vec<f64>| items.iter().sum::<f64>());
+    interpreter.register_fn("greet", |name: Option<String>| {
+        format!("hello {}", name.unwrap_or("world".to_string()))
+    });
+    interpreter.register_fn("checked-div", |a: i64, b: i64| {
+        a.checked_div(b).ok_or("division by zero")
+    });
+
+    assert_eq!(
+        interpreter.eval_str("(total [1 2.5])"),
+        Ok(DataType::Float(3.5))
+    );
+    assert_eq!(
+        interpreter.eval_str("(greet nil)"),
+        Ok(DataType::String("hello world".to_string()))
+    );
+    assert_eq!(
+        interpreter.eval_str("(checked-div 1 0)"),
+        Err(Error::Runtime("division by zero".to_string()))
+    );
+    assert_eq!(
+        interpreter.eval_str("(checked-div 1)"),
+        Err(Error::Runtime(
+            "checked-div expects 2 arguments, got 1".to_string()
+        ))
+    );
+    assert!(matches!(
+        interpreter.eval_str("(total \"abc\")"),
+        Err(Error::Runtime(_))
+    ));
+}
+
+#[test]
+fn test_register_native_with_context() {
+    let interpreter = Interpreter::new();
+    interpreter.register_native("call-twice", |ctx, args| {
+        let once = ctx.call(&args[0], &args[1..])?;
+        ctx.call(&args[0], &[once])
+    });
+
+    assert_eq!(
+        interpreter.eval_str("(call-twice (fn* (x) (* x 3)) 2)"),
+        Ok(DataType::Integer(18))
+    );
+    assert_eq!(
+        format!("{:?}", interpreter.get_global("call-twice").unwrap()),
+        "Fn(call-twice)"
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_to_and_from_value() {
+    use std::collections::HashMap;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    enum Level {
+        Low,
+        Custom(u8),
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Config {
+        name: String,
+        retries: Option<u32>,
+        ratios: Vec<f64>,
+        levels: Vec<Level>,
+        tags: HashMap<String, bool>,
+    }
+
+    let config = Config {
+        name: "svc".to_string(),
+        retries: None,
+        ratios: vec![0.5],
+        levels: vec![Level::Low, Level::Custom(3)],
+        tags: HashMap::from([("beta".to_string(), true)]),
+    };
+
+    let env = create_default_repl_env();
+    let value = to_value(&config).unwrap();
+    env.borrow_mut().set("config".to_string(), value.clone());
+
+    assert_eq!(
+        run_line("(get config :name)", env.clone()),
+        DataType::String("svc".to_string())
+    );
+    assert_eq!(
+        run_line("(get (get config :tags) :beta)", env.clone()),
+        DataType::Bool(true)
+    );
+    assert_eq!(
+        run_line("(nth (get config :levels) 0)", env.clone()),
+        DataType::Keyword("Low".to_string())
+    );
+    assert_eq!(from_value::<Config>(value).unwrap(), config);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_from_value_of_program_data() {
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    let env = create_default_repl_env();
+    let points = run_line("(list {:x 1 :y 2} {:x 3 :y 4})", env.clone());
+
+    assert_eq!(
+        from_value::<Vec<Point>>(points).unwrap(),
+        vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }]
+    );
+    assert!(from_value::<Point>(run_line("{:x \"1\" :y 2}", env.clone())).is_err());
+    assert!(to_value(&run_line("(fn* () 1)", env.clone())).is_err());
+}
+
+#[test]
+fn test_json_parse() {
+    let env = create_default_repl_env();
+    let result = run_line(
+        "(json-parse \"{\\\"a\\\": [1, 2.5, null, true], \\\"b\\\": {\\\"c\\\": \\\"x\\\"}}\")",
+        env.clone(),
+    );
+
+    assert_eq!(
+        result,
+        run_line("{:a [1 2.5 nil true] :b {:c \"x\"}}", env.clone())
+    );
+    assert_eq!(
+        run_line(
+            "(json-parse \"{\\\"a\\\": 1}\" {:keywordize false})",
+            env.clone()
+        ),
+        run_line("{\"a\" 1}", env.clone())
+    );
+}
+
+#[test]
+fn test_json_parse_error_position() {
+    let env = create_default_repl_env();
+    let result = eval(
+        &read("(json-parse \"[1,\n  2,]\")".to_string()).unwrap(),
+        env.clone(),
+        env.clone(),
+    );
+
+    assert_eq!(
+        result.unwrap_err().msg,
+        "Unexpected character ']' at line 2, column 5"
+    );
+}
+
+#[test]
+fn test_data_format_nesting_limit() {
+    let interpreter = Interpreter::new();
+    interpreter.set_global("deep", DataType::String("[".repeat(200_000)));
+    interpreter.set_global(
+        "deepest-allowed",
+        DataType::String(format!("{}{}", "[".repeat(128), "]".repeat(128))),
+    );
+
+    let error = Err(Error::Runtime(
+        "Collections are nested too deeply at line 1, column 129".to_string(),
+    ));
+    assert_eq!(interpreter.eval_str("(json-parse deep)"), error);
+    assert_eq!(interpreter.eval_str("(edn-read deep)"), error);
+    interpreter.set_global("deep-set", DataType::String("#{".repeat(200_000)));
+    assert_eq!(
+        interpreter.eval_str("(edn-read deep-set)"),
+        Err(Error::Runtime(
+            "Collections are nested too deeply at line 1, column 258".to_string()
+        ))
+    );
+
+    assert!(interpreter.eval_str("(json-parse deepest-allowed)").is_ok());
+    assert!(interpreter.eval_str("(edn-read deepest-allowed)").is_ok());
+    assert!(
+        interpreter
+            .eval_str("(json-stringify (json-parse deepest-allowed))")
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_json_stringify_key_collision() {
+    let interpreter = Interpreter::new();
+
+    assert_eq!(
+        interpreter.eval_str("(json-stringify (assoc (json-parse \"{\\\"a\\\": 1}\") \"a\" 2))"),
+        Err(Error::Runtime(
+            "Keys \"a\" and :a would both be written as \"a\"".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_json_stringify() {
+    let env = create_default_repl_env();
+
+    assert_eq!(
+        run_line("(json-stringify {:b [1 2.0 nil] :a \"q\"})", env.clone()),
+        DataType::String("{\"a\":\"q\",\"b\":[1,2.0,null]}".to_string())
+    );
+    assert_eq!(
+        run_line("(json-stringify {:a [1]} {:pretty true})", env.clone()),
+        DataType::String("{\n  \"a\": [\n    1\n  ]\n}".to_string())
+    );
+}
+
+#[test]
+fn test_edn_round_trip() {
+    let env = create_default_repl_env();
+    let value = run_line(
+        "(edn-read \"{:a [1 2.5 nil], :b (x \\\"y\\\"), :c #{1} #_ :skipped :d true}\")",
+        env.clone(),
+    );
+
+    assert_eq!(
+        value,
+        run_line(
+            "{:a [1 2.5 nil] :b (list 'x \"y\") :c [1] :d true}",
+            env.clone()
+        )
+    );
+    env.borrow_mut().set("value".to_string(), value.clone());
+    assert_eq!(run_line("(edn-read (edn-write value))", env.clone()), value);
+    assert_eq!(
+        run_line("(edn-write [:k \"s\" 1.5 (list 1)])", env.clone()),
+        DataType::String("[:k \"s\" 1.5 (1)]".to_string())
+    );
+}
+
+#[test]
+fn test_csv_read_quoted_fields() {
+    let env = create_default_repl_env();
+    env.borrow_mut().set(
+        "text".to_string(),
+        DataType::String("a,b\n\"x, \"\"y\"\"\",\"two\nlines\"\n".to_string()),
+    );
+
+    assert_eq!(
+        run_line("(nth (csv-read text) 1)", env.clone()),
+        run_line("[\"x, \\\"y\\\"\" \"two\nlines\"]", env.clone())
+    );
+    match &run_line("(csv-read text)", env.clone()) {
+        DataType::Vector(rows, _) => assert_eq!(rows.len(), 2),
+        other => panic!("expected a vector of rows, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_csv_read_file_with_header() {
+    let filesystem = filesystem::MemoryFileSystem::new();
+    filesystem
+        .write("stock.csv", "name;qty\nwidget;3\ngadget;5\n", false)
+        .unwrap();
+
+    let env = create_default_repl_env();
+    env.borrow()
+        .runtime()
+        .unwrap()
+        .set_filesystem(Rc::new(filesystem));
+    let rows = run_line(
+        "(csv-read \"stock.csv\" {:file true :header true :delimiter \";\"})",
+        env.clone(),
+    );
+    assert!(matches!(rows, DataType::Generator(_)));
+    env.borrow_mut().set("rows".to_string(), rows);
+
+    assert_eq!(
+        run_line("(first rows)", env.clone()),
+        run_line("{:name \"widget\" :qty \"3\"}", env.clone())
+    );
+    assert_eq!(
+        run_line("(get (nth rows 1) :qty)", env.clone()),
+        DataType::String("5".to_string())
+    );
+}
+
+#[test]
+fn test_csv_read_stray_quote() {
+    let env = create_default_repl_env();
+
+    assert!(matches!(
+        run_line("(csv-read \"x,y\")", env.clone()),
+        DataType::Vector(..)
+    ));
+    assert_eq!(
+        run_line(
+            "(try* (csv-read \"x\\na,b\\\"c,d\") (fn* (e) e))",
+            env.clone()
+        ),
+        DataType::String("Unexpected quote in unquoted field at line 2, column 4".to_string())
+    );
+}
+
+#[test]
+fn test_csv_write() {
+    let env = create_default_repl_env();
+
+    assert_eq!(
+        run_line("(csv-write [[\"a\" \"b,c\" nil 1]])", env.clone()),
+        DataType::String("a,\"b,c\",,1\n".to_string())
+    );
+    assert_eq!(
+        run_line(
+            "(csv-write [{:b \"x\" :a 1}] {:delimiter \"|\" :quote-all true})",
+            env.clone()
+        ),
+        DataType::String("\"a\"|\"b\"\n\"1\"|\"x\"\n".to_string())
+    );
+}
+
+#[test]
+fn test_memory_filesystem_builtins() {
+    let env = create_default_repl_env();
+    let filesystem = filesystem::MemoryFileSystem::new();
+    env.borrow()
+        .runtime()
+        .unwrap()
+        .set_filesystem(Rc::new(filesystem.clone()));
+
+    run_line("(mkdir \"notes\")", env.clone());
+    run_line("(spit \"notes/a.txt\" \"one\")", env.clone());
+    run_line(
+        "(spit \"./notes/a.txt\" \"|two\" {:append true})",
+        env.clone(),
+    );
+    run_line("(spit \"notes/b.txt\" \"x\")", env.clone());
+
+    assert_eq!(
+        run_line("(slurp \"notes/a.txt\")", env.clone()),
+        DataType::String("one|two".to_string())
+    );
+    assert_eq!(
+        run_line("(list-dir \"notes\")", env.clone()),
+        run_line("(list \"a.txt\" \"b.txt\")", env.clone())
+    );
+
+    run_line("(delete-file \"notes/b.txt\")", env.clone());
+    assert_eq!(
+        run_line("(file-exists? \"notes/b.txt\")", env.clone()),
+        DataType::Bool(false)
+    );
+    assert_eq!(
+        filesystem.paths(),
+        vec!["notes".to_string(), "notes/a.txt".to_string()]
+    );
+}
+
+#[test]
+fn test_with_open_closes_handles() {
+    let env = create_default_repl_env();
+    env.borrow()
+        .runtime()
+        .unwrap()
+        .set_filesystem(Rc::new(filesystem::MemoryFileSystem::new()));
+
+    run_line(
+        "(with-open [w (open-writer \"log\")] (write-string w \"a\") (write-string w \"b\"))",
+        env.clone(),
+    );
+    assert_eq!(
+        run_line(
+            "(with-open [r (open-reader \"log\")] (list (read-line r) (read-line r)))",
+            env.clone()
+        ),
+        run_line("(list \"ab\" nil)", env.clone())
+    );
+
+    run_line("(def! handle (atom nil))", env.clone());
+    run_line(
+        "(try* (with-open [w (open-writer \"log\" {:append true})] (reset! handle w) (throw \"oops\")) (fn* (e) e))",
+        env.clone(),
+    );
+    assert_eq!(
+        run_line(
+            "(try* (write-string @handle \"c\") (fn* (e) e))",
+            env.clone()
+        ),
+        DataType::String("write-string expects an open writer".to_string())
+    );
+}
+
+#[test]
+fn test_read_lines_from_disk() {
+    let path = std::env::temp_dir().join("bracketlang_read_lines_test.txt");
+    let env = create_default_repl_env();
+    env.borrow_mut().set(
+        "path".to_string(),
+        DataType::String(path.to_string_lossy().to_string()),
+    );
+
+    run_line("(spit path \"first\nsecond\n\")", env.clone());
+    run_line("(def! lines (read-lines path))", env.clone());
+    assert_eq!(
+        run_line("(nth lines 1)", env.clone()),
+        DataType::String("second".to_string())
+    );
+    assert_eq!(
+        run_line("(file-exists? path)", env.clone()),
+        DataType::Bool(true)
+    );
+
+    run_line("(delete-file path)", env.clone());
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_vfs_load_file() {
+    vfs_mkdir("lib").unwrap();
+    vfs_write("lib/math.bl", "(def! square (fn* (x) (* x x)))").unwrap();
+    vfs_write(
+        "main.bl",
+        "(load-file \"lib/math.bl\")\n(def! answer (square 7))",
+    )
+    .unwrap();
+
+    let interpreter = Interpreter::new();
+    interpreter.set_filesystem(MemoryFileSystem::shared());
+    interpreter.eval_str("(load-file \"main.bl\")").unwrap();
+
+    assert_eq!(
+        interpreter.get_global("answer"),
+        Some(DataType::Integer(49))
+    );
+    assert_eq!(vfs_list("").unwrap(), vec!["lib", "main.bl"]);
+    assert_eq!(vfs_list("lib").unwrap(), vec!["math.bl"]);
+}
+
+#[test]
+fn test_eval_file_uses_the_interpreter_filesystem() {
+    let filesystem = MemoryFileSystem::new();
+    filesystem
+        .write("main.bl", "(def! x 20)\n(+ x 1)", false)
+        .unwrap();
+
+    let interpreter = Interpreter::new();
+    interpreter.set_filesystem(filesystem);
+
+    assert_eq!(interpreter.eval_file("main.bl"), Ok(DataType::Integer(21)));
+    assert!(matches!(
+        interpreter.eval_file("other.bl"),
+        Err(Error::Io(_))
+    ));
+}
+
+#[test]
+fn test_vfs_sees_program_writes() {
+    let interpreter = Interpreter::new();
+    interpreter.set_filesystem(MemoryFileSystem::shared());
+    interpreter
+        .eval_str("(spit \"out.txt\" \"hello\")")
+        .unwrap();
+
+    assert_eq!(vfs_read("out.txt"), Some("hello".to_string()));
+    assert_eq!(vfs_read("missing.txt"), None);
+    assert!(vfs_list("out.txt").is_err());
+}
+
+#[test]
+fn test_process_builtins_need_permission() {
+    let interpreter = Interpreter::new();
+
+    assert_eq!(
+        interpreter.eval_str("(getenv \"PATH\")"),
+        Err(Error::Runtime(
+            "getenv needs the process capability, which is disabled".to_string()
+        ))
+    );
+
+    interpreter.set_capabilities(capability::Capabilities::all());
+    interpreter
+        .eval_str("(setenv \"BRACKETLANG_TEST_VAR\" \"hello\")")
+        .unwrap();
+    assert_eq!(
+        interpreter.eval_str("(getenv \"BRACKETLANG_TEST_VAR\")"),
+        Ok(DataType::String("hello".to_string()))
+    );
+    interpreter
+        .eval_str("(setenv \"BRACKETLANG_TEST_VAR\" nil)")
+        .unwrap();
+    assert_eq!(
+        interpreter.eval_str("(getenv \"BRACKETLANG_TEST_VAR\")"),
+        Ok(DataType::Nil())
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sh_pipes_stdin() {
+    let interpreter = Interpreter::builder()
+        .allow(capability::Capability::Process)
+        .build();
+
+    interpreter
+        .eval_str("(def! result (sh \"tr a-z A-Z; echo oops >&2; exit 3\" {:in \"shout\"}))")
+        .unwrap();
+    assert_eq!(
+        interpreter.eval_str("(get result :out)"),
+        Ok(DataType::String("SHOUT".to_string()))
+    );
+    assert_eq!(
+        interpreter.eval_str("(get result :err)"),
+        Ok(DataType::String("oops\n".to_string()))
+    );
+    assert_eq!(
+        interpreter.eval_str("(get result :exit)"),
+        Ok(DataType::Integer(3))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_process() {
+    let interpreter = Interpreter::builder()
+        .allow(capability::Capability::Process)
+        .build();
+
+    assert_eq!(
+        interpreter.eval_str("(get (run-process \"echo\" [\"a\" \"b c\"]) :out)"),
+        Ok(DataType::String("a b c\n".to_string()))
+    );
+    assert_eq!(
+        interpreter.eval_str("(get (run-process \"true\") :exit)"),
+        Ok(DataType::Integer(0))
+    );
+}
+
+#[test]
+fn test_denied_capabilities() {
+    let interpreter = Interpreter::builder()
+        .capabilities(capability::Capabilities::none())
+        .build();
+
+    assert_eq!(
+        interpreter.eval_str("(slurp \"anything.txt\")"),
+        Err(Error::Runtime(
+            "slurp needs the fs capability, which is disabled".to_string()
+        ))
+    );
+    assert_eq!(
+        interpreter.eval_str("(eval '(+ 1 2))"),
+        Err(Error::Runtime(
+            "eval needs the eval capability, which is disabled".to_string()
+        ))
+    );
+    assert_eq!(
+        interpreter.eval_str("(try* (time-ms) (fn* (e) e))"),
+        Ok(DataType::String(
+            "time-ms needs the time capability, which is disabled".to_string()
+        ))
+    );
+    assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(DataType::Integer(3)));
+}
+
+#[test]
+fn test_step_limit_cannot_be_caught() {
+    let interpreter = Interpreter::builder().max_steps(10_000).build();
+    interpreter
+        .eval_str("(def! spin (fn* (n) (spin (+ n 1))))")
+        .unwrap();
+
+    assert_eq!(
+        interpreter.eval_str("(try* (spin 0) (fn* (e) :caught))"),
+        Err(Error::Runtime("Step limit of 10000 exceeded".to_string()))
+    );
+    // Each evaluation gets a fresh budget
+    assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(DataType::Integer(3)));
+}
+
+#[test]
+fn test_allocation_and_output_limits() {
+    let interpreter = Interpreter::builder()
+        .max_allocations(1_000)
+        .max_output(10)
+        .build();
+    interpreter.set_stdout(|_| {});
+    interpreter
+        .eval_str("(def! grow (fn* (xs) (grow (cons 1 xs))))")
+        .unwrap();
+
+    assert_eq!(
+        interpreter.eval_str("(grow (list))"),
+        Err(Error::Runtime(
+            "Allocation limit of 1000 exceeded".to_string()
+        ))
+    );
+    assert_eq!(interpreter.eval_str("(prn \"short\")"), Ok(DataType::Nil()));
+    assert_eq!(
+        interpreter.eval_str("(prn \"far too long\")"),
+        Err(Error::Runtime(
+            "Output limit of 10 bytes exceeded".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_fuel_runs_out_across_evaluations() {
+    let interpreter = Interpreter::new();
+    interpreter.set_fuel(Some(1_000));
+
+    assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(DataType::Integer(3)));
+    let left = interpreter.fuel().unwrap();
+    assert!(left < 1_000);
+
+    interpreter.eval_str("(def! spin (fn* () (spin)))").unwrap();
+    assert_eq!(
+        interpreter.eval_str("(try* (spin) (fn* (e) :caught))"),
+        Err(Error::Runtime("Out of fuel".to_string()))
+    );
+    assert_eq!(interpreter.fuel(), Some(0));
+
+    interpreter.set_fuel(None);
+    assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(DataType::Integer(3)));
+}
+
+#[test]
+fn test_interrupt_from_another_thread() {
+    let interpreter = Interpreter::new();
+    interpreter.eval_str("(def! spin (fn* () (spin)))").unwrap();
+
+    let interrupt = interpreter.interrupt_flag();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        interrupt.store(true, std::sync::atomic::Ordering::Relaxed);
+    });
+
+    assert_eq!(
+        interpreter.eval_str("(try* (spin) (fn* (e) :caught))"),
+        Err(Error::Runtime("Interrupted".to_string()))
+    );
+    interrupter.join().unwrap();
+
+    // The flag is cleared once it has stopped an evaluation
+    assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(DataType::Integer(3)));
+}
+
+#[test]
+fn test_interrupt_before_evaluation_is_ignored() {
+    let interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(def! inc (fn* (x) (+ x 1)))")
+        .unwrap();
+    let interrupt = interpreter.interrupt_flag();
+
+    interrupt.store(true, std::sync::atomic::Ordering::Relaxed);
+    assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(DataType::Integer(3)));
+
+    interrupt.store(true, std::sync::atomic::Ordering::Relaxed);
+    assert_eq!(
+        interpreter.call("inc", &[DataType::Integer(1)]),
+        Ok(DataType::Integer(2))
+    );
+}
+
+#[test]
+fn test_interrupt_from_native_function() {
+    let interpreter = Interpreter::new();
+    let interrupt = interpreter.interrupt_flag();
+    interpreter.register_fn("stop!", move || {
+        interrupt.store(true, std::sync::atomic::Ordering::Relaxed);
+    });
+
+    assert_eq!(
+        interpreter.eval_str("(do (stop!) (prn \"never printed\"))"),
+        Err(Error::Runtime("Interrupted".to_string()))
+    );
+}
+
+#[test]
+fn test_parse_and_format_time() {
+    let env = create_default_repl_env();
+
+    assert_eq!(
+        run_line(
+            "(format-time (parse-time \"2024-02-29T13:45:07.25+01:00\"))",
+            env.clone()
+        ),
+        DataType::String("2024-02-29T12:45:07.250Z".to_string())
+    );
+    assert_eq!(
+        run_line(
+            "(format-time (date-time 1969 7 20 20 17) \"EEEE d MMMM yyyy 'at' HH:mm\")",
+            env.clone()
+        ),
+        DataType::String("Sunday 20 July 1969 at 20:17".to_string())
+    );
+    assert_eq!(
+        run_line(
+            "(inst-ms (parse-time \"03/Jan/1970 01\" \"dd/MMM/yyyy HH\"))",
+            env.clone()
+        ),
+        DataType::Integer(2 * 86_400_000 + 3_600_000)
+    );
+    assert_eq!(
+        run_line(
+            "(try* (parse-time \"2023-02-29\") (fn* (e) e))",
+            env.clone()
+        ),
+        DataType::String("Day 29 is out of range".to_string())
+    );
+}
+
+#[test]
+fn test_time_arithmetic() {
+    let env = create_default_repl_env();
+    run_line("(def! jan31 (date-time 2024 1 31 9 30))", env.clone());
+
+    assert_eq!(
+        run_line("(time-plus jan31 1 :months)", env.clone()),
+        run_line("(date-time 2024 2 29 9 30)", env.clone())
+    );
+    assert_eq!(
+        run_line("(time-plus jan31 -36 :hours)", env.clone()),
+        run_line("(date-time 2024 1 29 21 30)", env.clone())
+    );
+    assert_eq!(
+        run_line(
+            "(time-between jan31 (date-time 2025 1 30) :months)",
+            env.clone()
+        ),
+        DataType::Integer(11)
+    );
+    assert_eq!(
+        run_line(
+            "(time-between (date-time 2025 1 30) jan31 :days)",
+            env.clone()
+        ),
+        DataType::Integer(-364)
+    );
+    assert_eq!(
+        run_line("(< jan31 (time-plus jan31 1))", env.clone()),
+        DataType::Bool(true)
+    );
+}
+
+#[test]
+fn test_time_out_of_range() {
+    let env = create_default_repl_env();
+    let error = |line: &str| run_line(&format!("(try* {} (fn* (e) e))", line), env.clone());
+
+    assert_eq!(
+        error("(date-time 9223372036854775807 1 1)"),
+        DataType::String("Year 9223372036854775807 is out of range".to_string())
+    );
+    assert_eq!(
+        error("(date-time -9223372036854775808 1 1)"),
+        DataType::String("Year -9223372036854775808 is out of range".to_string())
+    );
+    assert_eq!(
+        error("(time-between (instant -9223372036854775807) (instant 9223372036854775807) :ms)"),
+        DataType::String("Time between the instants is out of range".to_string())
+    );
+    assert_eq!(
+        error("(time-plus (instant 0) 9223372036854775807 :months)"),
+        DataType::String("Instant is out of range".to_string())
+    );
+}
+
+#[test]
+fn test_time_fields_and_formats() {
+    let env = create_default_repl_env();
+    run_line("(def! moment (date-time 2000 3 1 12 0 0 5))", env.clone());
+
+    assert_eq!(
+        run_line("(time-fields moment)", env.clone()),
+        run_line(
+            "{:year 2000 :month 3 :day 1 :hour 12 :minute 0 :second 0 :ms 5 :weekday 3 :day-of-year 61}",
+            env.clone()
+        )
+    );
+    assert_eq!(
+        run_line("(edn-read (edn-write {:at moment}))", env.clone()),
+        run_line("{:at moment}", env.clone())
+    );
+    assert_eq!(
+        run_line("(json-stringify [moment])", env.clone()),
+        DataType::String("[\"2000-03-01T12:00:00.005Z\"]".to_string())
+    );
+    assert_eq!(
+        run_line("(<= (nano-time) (nano-time))", env.clone()),
+        DataType::Bool(true)
+    );
+}
+
+#[test]
+fn test_time_and_bench() {
+    let interpreter = Interpreter::new();
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    interpreter.set_stdout(move |text| sink.borrow_mut().push_str(text));
+
+    assert_eq!(
+        interpreter.eval_str("(time (+ 1 2))"),
+        Ok(DataType::Integer(3))
+    );
+    assert!(output.borrow().starts_with("Elapsed time: "));
+
+    assert_eq!(
+        interpreter.eval_str("(get (bench (+ 1 2) {:warmup 0 :iterations 7}) :iterations)"),
+        Ok(DataType::Integer(7))
+    );
+    assert!(output.borrow().contains("Bench: 7 iterations, mean "));
+
+    assert_eq!(
+        interpreter.eval_str("(bench 1 {:iterations 100000000000000000000000000000})"),
+        Err(Error::Runtime(":iterations is too large".to_string()))
+    );
+}
+
+#[test]
+fn test_profile_report() {
+    let interpreter = Interpreter::new();
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    interpreter.set_stdout(move |text| sink.borrow_mut().push_str(text));
+
+    interpreter
+        .eval_str(
+            "(def! fib (fn* (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))
+             (def! run (fn* () (fib 10)))",
+        )
+        .unwrap();
+
+    assert_eq!(
+        interpreter.eval_str("(profile (run))"),
+        Ok(DataType::Integer(55))
+    );
+    let report = output.borrow();
+    assert!(report.starts_with("function"));
+    let fib = report
+        .lines()
+        .find(|line| line.starts_with("fib "))
+        .unwrap();
+    assert_eq!(fib.split_whitespace().nth(1), Some("177"));
+    assert!(report.lines().any(|line| line.starts_with("run ")));
+}
+
+#[test]
+fn test_profile_folded_stacks() {
+    let interpreter = Interpreter::new();
+    let filesystem = filesystem::MemoryFileSystem::new();
+    interpreter.set_filesystem(filesystem.clone());
+
+    interpreter
+        .eval_str(
+            "(def! leaf (fn* (x) (* x 2)))
+             (def! walk (fn* (n acc) (if (= n 0) acc (walk (- n 1) (+ acc (leaf n))))))",
+        )
+        .unwrap();
+
+    assert_eq!(
+        interpreter.eval_str("(profile (walk 100 0) {:folded \"out.folded\"})"),
+        Ok(DataType::Integer(10100))
+    );
+    let folded = filesystem.read_to_string("out.folded").unwrap();
+    let stacks = folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(stacks, vec!["walk", "walk;leaf"]);
+    assert_eq!(
+        interpreter.eval_str("(try* (profile (throw \"boom\")) (fn* (e) e))"),
+        Ok(DataType::String("boom".to_string()))
+    );
+}
+
+#[test]
+fn test_swap_vals_and_compare_and_set() {
+    let env = create_default_repl_env();
+    run_line("(def! a (atom 1))", env.clone());
+
+    assert_eq!(
+        run_line("(swap! a + 10)", env.clone()),
+        DataType::Integer(11)
+    );
+    assert_eq!(
+        run_line("(swap-vals! a - 1)", env.clone()),
+        run_line("[11 10]", env.clone())
+    );
+    assert_eq!(
+        run_line("(compare-and-set! a 3 4)", env.clone()),
+        DataType::Bool(false)
+    );
+    assert_eq!(
+        run_line("(compare-and-set! a 10 4)", env.clone()),
+        DataType::Bool(true)
+    );
+    assert_eq!(run_line("@a", env.clone()), DataType::Integer(4));
+}
+
+#[test]
+fn test_atom_watches_and_validators() {
+    let env = create_default_repl_env();
+    run_line("(def! log (atom (list)))", env.clone());
+    run_line(
+        "(def! a (atom 1 {:validator (fn* (x) (< x 10))}))",
+        env.clone(),
+    );
+    run_line(
+        "(add-watch a :log (fn* (k r old new) (swap! log (fn* (l) (cons [k old new] l)))))",
+        env.clone(),
+    );
+
+    run_line("(reset! a 2)", env.clone());
+    run_line("(swap! a + 3)", env.clone());
+    assert_eq!(
+        run_line("@log", env.clone()),
+        run_line("(list [:log 2 5] [:log 1 2])", env.clone())
+    );
+
+    assert_eq!(
+        run_line("(try* (reset! a 20) (fn* (e) e))", env.clone()),
+        DataType::String("Invalid reference state 20".to_string())
+    );
+    assert_eq!(run_line("@a", env.clone()), DataType::Integer(5));
+
+    run_line("(remove-watch a :log)", env.clone());
+    run_line("(set-validator! a nil)", env.clone());
+    run_line("(reset! a 20)", env.clone());
+    assert_eq!(run_line("(count @log)", env.clone()), DataType::Integer(2));
+    assert_eq!(run_line("(get-validator a)", env.clone()), DataType::Nil());
+}
+
+#[test]
+fn test_atom_callbacks_changing_their_own_atom() {
+    let env = create_default_repl_env();
+    run_line("(def! a (atom 0))", env.clone());
+    run_line(
+        "(add-watch a :loop (fn* (k r old new) (reset! r (+ new 1))))",
+        env.clone(),
+    );
+
+    assert_eq!(
+        run_line("(try* (reset! a 1) (fn* (e) e))", env.clone()),
+        DataType::String(
+            "native functions called back into the program more than 50 levels deep".to_string()
+        )
+    );
+
+    run_line("(remove-watch a :loop)", env.clone());
+    run_line(
+        "(set-validator! a (fn* (x) (do (reset! a x) true)))",
+        env.clone(),
+    );
+    assert_eq!(
+        run_line("(try* (swap! a + 1) (fn* (e) e))", env.clone()),
+        DataType::String(
+            "native functions called back into the program more than 50 levels deep".to_string()
+        )
+    );
+
+    run_line("(set-validator! a nil)", env.clone());
+    assert_eq!(run_line("(reset! a 7)", env.clone()), DataType::Integer(7));
+}
+
+#[test]
+fn test_transients() {
+    let env = create_default_repl_env();
+    run_line(
+        "(def! build (fn* (n) (let* (t (transient [])) (loop (i 0) (if (< i n) (do (conj! t i) (recur (+ i 1))) (persistent! t))))))",
+        env.clone(),
+    );
+    assert_eq!(
+        run_line("(build 4)", env.clone()),
+        run_line("[0 1 2 3]", env.clone())
+    );
+
+    run_line("(def! t (transient {:a 1}))", env.clone());
+    run_line("(assoc! t :b 2 :a 3)", env.clone());
+    run_line("(conj! t [:c 4])", env.clone());
+    assert_eq!(
+        run_line("(persistent! t)", env.clone()),
+        run_line("{:a 3 :b 2 :c 4}", env.clone())
+    );
+    assert_eq!(
+        run_line("(try* (conj! t [:d 5]) (fn* (e) e))", env.clone()),
+        DataType::String("conj! called on a transient after persistent!".to_string())
+    );
+    assert_eq!(
+        run_line(
+            "(persistent! (assoc! (transient [1 2]) 0 :x 2 :y))",
+            env.clone()
+        ),
+        run_line("[:x 2 :y]", env.clone())
+    );
+}
@@ -1,11 +1,28 @@
-use std::{cell::RefCell, collections::HashMap, ptr::addr_of, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    ptr::addr_of,
+    rc::Rc,
+};
 
-use crate::evaluator::RuntimeError;
+use crate::{
+    atom::AtomState,
+    datetime,
+    evaluator::{Continuation, RuntimeError},
+    io::Handle,
+    namespace::Namespace,
+    native::NativeFunction,
+    runtime::Runtime,
+};
 
 #[derive(Clone)]
 pub struct Environment {
     outer: Option<Rc<RefCell<Self>>>,
     data: HashMap<String, DataType>,
+    /// Names defined with `^:private`, which other namespaces can't see
+    private: HashSet<String>,
+    pub namespace: Option<Namespace>,
+    runtime: Option<Rc<Runtime>>,
 }
 
 impl Environment {
@@ -13,13 +30,70 @@ impl Environment {
         Self {
             outer,
             data: HashMap::new(),
+            private: HashSet::new(),
+            namespace: None,
+            runtime: None,
         }
     }
 
+    /// Creates the top level environment of an interpreter, which owns the
+    /// shared runtime state and acts as the `user` namespace.
+    pub fn new_root(runtime: Runtime) -> Environment {
+        Self {
+            outer: None,
+            data: HashMap::new(),
+            private: HashSet::new(),
+            namespace: Some(Namespace::new("user")),
+            runtime: Some(Rc::new(runtime)),
+        }
+    }
+
+    pub fn new_namespace(name: &str, outer: Rc<RefCell<Self>>) -> Environment {
+        Self {
+            outer: Some(outer),
+            data: HashMap::new(),
+            private: HashSet::new(),
+            namespace: Some(Namespace::new(name)),
+            runtime: None,
+        }
+    }
+
+    pub fn runtime(&self) -> Option<Rc<Runtime>> {
+        match self.runtime {
+            Some(ref runtime) => Some(runtime.clone()),
+            None => self.outer.as_ref()?.borrow().runtime(),
+        }
+    }
+
+    pub fn get_local(&self, sym: &String) -> Option<DataType> {
+        self.data.get(sym).cloned()
+    }
+
+    pub fn local_bindings(&self) -> Vec<(String, DataType)> {
+        self.data
+            .iter()
+            .map(|(sym, value)| (sym.clone(), value.clone()))
+            .collect()
+    }
+
     pub fn set(&mut self, sym: String, value: DataType) {
         self.data.insert(sym, value);
     }
 
+    /// Binds `sym` as `def!` does, replacing whether it is private.
+    pub fn define(&mut self, sym: String, value: DataType, private: bool) {
+        if private {
+            self.private.insert(sym.clone());
+        } else {
+            self.private.remove(&sym);
+        }
+        self.data.insert(sym, value);
+    }
+
+    pub fn is_private(&self, sym: &str) -> bool {
+        self.private.contains(sym)
+    }
+
     pub fn get(&self, sym: &String) -> Option<DataType> {
         match self.data.get(sym) {
             Some(v) => Some(v.clone()),
@@ -40,93 +114,539 @@ impl Environment {
 
 #[derive(Clone)]
 pub struct Closure {
+    /// The name it was first defined under, for the profiler
+    pub name: Option<Rc<str>>,
     pub ast: Box<DataType>,
     pub params: Vec<String>,
     pub env: Rc<RefCell<Environment>>,
     pub repl_env: Rc<RefCell<Environment>>,
     pub is_macro: bool,
+    pub meta: Metadata,
+}
+
+/// Builtins that call other functions. These are run by the evaluator itself
+/// so that calling back into the program doesn't recurse on the Rust stack.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Builtin {
+    Apply,
+    Map,
+    Swap,
+    SwapVals,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 4] = [
+        Builtin::Apply,
+        Builtin::Map,
+        Builtin::Swap,
+        Builtin::SwapVals,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Apply => "apply",
+            Builtin::Map => "map",
+            Builtin::Swap => "swap!",
+            Builtin::SwapVals => "swap-vals!",
+        }
+    }
 }
 
+/// A sequence of the values yielded by a function, produced as they are
+/// needed. Handles returned by `rest` share the values produced so far.
 #[derive(Clone)]
+pub struct Generator {
+    pub state: Rc<RefCell<GeneratorState>>,
+    pub position: Rc<Cell<usize>>,
+}
+
+pub struct GeneratorState {
+    pub function: DataType,
+    pub values: Vec<DataType>,
+    pub resume: Resume,
+}
+
+/// How to carry on producing values for a generator.
+pub enum Resume {
+    Start,
+    Suspended(Rc<Continuation>),
+    /// Values produced by Rust code, such as the rows of a file.
+    Iterator(Box<dyn Iterator<Item = Result<DataType, RuntimeError>>>),
+    Running,
+    Finished,
+}
+
+impl Generator {
+    pub fn new(function: DataType) -> Generator {
+        Self {
+            state: Rc::new(RefCell::new(GeneratorState {
+                function,
+                values: vec![],
+                resume: Resume::Start,
+            })),
+            position: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// A generator producing the items of `iter` as they are needed.
+    pub fn streaming(
+        iter: impl Iterator<Item = Result<DataType, RuntimeError>> + 'static,
+    ) -> Generator {
+        Self {
+            state: Rc::new(RefCell::new(GeneratorState {
+                function: DataType::Nil(),
+                values: vec![],
+                resume: Resume::Iterator(Box::new(iter)),
+            })),
+            position: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// A new handle starting one value after this one.
+    pub fn rest(&self) -> Generator {
+        Self {
+            state: self.state.clone(),
+            position: Rc::new(Cell::new(self.position.get() + 1)),
+        }
+    }
+}
+
+/// A function that picks which of its methods to run by calling a dispatch
+/// function on its arguments, falling back to the `:default` method.
+pub struct MultiFn {
+    pub name: String,
+    pub dispatch: Dispatch,
+    pub methods: RefCell<Vec<(DataType, DataType)>>,
+}
+
+pub enum Dispatch {
+    Function(DataType),
+    /// Dispatch on the `type` of the first argument, as protocols do
+    Type,
+}
+
+impl MultiFn {
+    pub fn get_method(&self, dispatch_value: &DataType) -> Option<DataType> {
+        let methods = self.methods.borrow();
+        let default = DataType::Keyword("default".to_string());
+
+        methods
+            .iter()
+            .find(|(value, _)| value == dispatch_value)
+            .or_else(|| methods.iter().find(|(value, _)| *value == default))
+            .map(|(_, method)| method.clone())
+    }
+
+    pub fn add_method(&self, dispatch_value: DataType, method: DataType) {
+        let mut methods = self.methods.borrow_mut();
+        methods.retain(|(value, _)| *value != dispatch_value);
+        methods.push((dispatch_value, method));
+    }
+}
+
+/// A type defined by `defrecord` or `deftype`. Fields are kept in declaration
+/// order as dictionary keys, e.g. `":x"`.
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// Metadata attached to a value by `with-meta` or the `^` reader macro.
+/// It is carried along with the value but never affects equality.
+pub type Metadata = Option<Rc<DataType>>;
+
 pub enum DataType {
     Nil(),
-    List(Vec<DataType>),
-    Symbol(std::string::String),
+    List(Vec<DataType>, Metadata),
+    Symbol(std::string::String, Metadata),
+    Keyword(std::string::String),
     Integer(i128),
     Bool(bool),
     Float(f64),
     String(String),
     Comment(),
-    Vector(Vec<DataType>),
-    Dictionary(HashMap<String, DataType>),
+    Vector(Vec<DataType>, Metadata),
+    Dictionary(HashMap<String, DataType>, Metadata),
     Closure(Closure),
-    NativeFunction(
-        (
-            i8,
-            &'static fn(&[DataType]) -> Result<DataType, RuntimeError>,
-        ),
-    ),
-    Atom(Rc<RefCell<DataType>>),
+    NativeFunction(NativeFunction),
+    Atom(Rc<AtomState>),
+    Builtin(Builtin),
+    Continuation(Rc<Continuation>),
+    Generator(Generator),
+    MultiFn(Rc<MultiFn>),
+    RecordType(Rc<RecordType>),
+    Record(Rc<RecordType>, HashMap<String, DataType>, Metadata),
+    Handle(Rc<RefCell<Handle>>),
+    /// Milliseconds since the Unix epoch
+    Instant(i64),
+    /// A vector or dict being built in place, until `persistent!` takes it
+    Transient(Rc<RefCell<Option<DataType>>>),
+}
+
+impl DataType {
+    /// The keyword `type` returns for this value, which protocols dispatch on.
+    pub fn type_name(&self) -> &str {
+        match self {
+            DataType::Nil() => "nil",
+            DataType::List(..) => "list",
+            DataType::Symbol(..) => "symbol",
+            DataType::Keyword(_) => "keyword",
+            DataType::Integer(_) => "int",
+            DataType::Bool(_) => "bool",
+            DataType::Float(_) => "float",
+            DataType::String(_) => "string",
+            DataType::Comment() => "comment",
+            DataType::Vector(..) => "vector",
+            DataType::Dictionary(..) => "dict",
+            DataType::Closure(_) => "closure",
+            DataType::NativeFunction(_) | DataType::Builtin(_) => "native-fn",
+            DataType::Atom(_) => "atom",
+            DataType::Continuation(_) => "continuation",
+            DataType::Generator(_) => "generator",
+            DataType::MultiFn(_) => "multi-fn",
+            DataType::RecordType(_) => "record-type",
+            DataType::Record(record_type, ..) => &record_type.name,
+            DataType::Handle(_) => "handle",
+            DataType::Instant(_) => "instant",
+            DataType::Transient(_) => "transient",
+        }
+    }
+
+    pub fn meta(&self) -> DataType {
+        let meta = match self {
+            DataType::List(_, meta)
+            | DataType::Vector(_, meta)
+            | DataType::Dictionary(_, meta)
+            | DataType::Record(_, _, meta)
+            | DataType::Symbol(_, meta) => meta,
+            DataType::Closure(closure) => &closure.meta,
+            _ => &None,
+        };
+
+        match meta {
+            Some(meta) => (**meta).clone(),
+            None => DataType::Nil(),
+        }
+    }
+
+    pub fn with_meta(&self, meta: DataType) -> Result<DataType, RuntimeError> {
+        let meta = match meta {
+            DataType::Nil() => None,
+            DataType::Dictionary(..) => Some(Rc::new(meta)),
+            _ => {
+                return Err(RuntimeError {
+                    msg: "Metadata must be a dictionary or nil".to_string(),
+                });
+            }
+        };
+
+        match self {
+            DataType::List(list, _) => Ok(DataType::List(list.clone(), meta)),
+            DataType::Vector(list, _) => Ok(DataType::Vector(list.clone(), meta)),
+            DataType::Dictionary(dict, _) => Ok(DataType::Dictionary(dict.clone(), meta)),
+            DataType::Record(record_type, fields, _) => {
+                Ok(DataType::Record(record_type.clone(), fields.clone(), meta))
+            }
+            DataType::Symbol(sym, _) => Ok(DataType::Symbol(sym.clone(), meta)),
+            DataType::Closure(closure) => Ok(DataType::Closure(Closure {
+                meta,
+                ..closure.clone()
+            })),
+            _ => Err(RuntimeError {
+                msg: format!("{:?} cannot hold metadata", self),
+            }),
+        }
+    }
+
+    pub fn merge_meta(&self, meta: DataType) -> Result<DataType, RuntimeError> {
+        let mut meta = meta;
+        let DataType::Dictionary(new_meta, _) = &mut meta else {
+            return self.with_meta(meta);
+        };
+
+        let mut merged = match self.meta() {
+            DataType::Dictionary(ref mut old_meta, _) => std::mem::take(old_meta),
+            _ => HashMap::new(),
+        };
+        merged.extend(new_meta.drain());
+
+        self.with_meta(DataType::Dictionary(merged, None))
+    }
 }
 
 impl PartialEq for DataType {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Self::List(l0), Self::List(r0)) => l0 == r0,
-            (Self::Symbol(l0), Self::Symbol(r0)) => l0 == r0,
+            (Self::Nil(), Self::Nil()) => true,
+            (Self::List(l0, _), Self::List(r0, _)) => l0 == r0,
+            (Self::Symbol(l0, _), Self::Symbol(r0, _)) => l0 == r0,
+            (Self::Keyword(l0), Self::Keyword(r0)) => l0 == r0,
             (Self::Integer(l0), Self::Integer(r0)) => l0 == r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
             (Self::Float(l0), Self::Float(r0)) => l0 == r0,
             (Self::String(l0), Self::String(r0)) => l0 == r0,
-            (Self::Vector(l0), Self::Vector(r0)) => l0 == r0,
-            (Self::Dictionary(l0), Self::Dictionary(r0)) => l0 == r0,
+            (Self::Vector(l0, _), Self::Vector(r0, _)) => l0 == r0,
+            (Self::Dictionary(l0, _), Self::Dictionary(r0, _)) => l0 == r0,
             (Self::Closure(l0), Self::Closure(r0)) => addr_of!(l0) == addr_of!(r0),
-            (Self::NativeFunction(l0), Self::NativeFunction(r0)) => r0.0 == l0.0,
-            (Self::Atom(l0), Self::Atom(r0)) => l0 == r0,
+            (Self::NativeFunction(l0), Self::NativeFunction(r0)) => Rc::ptr_eq(&l0.func, &r0.func),
+            (Self::Atom(l0), Self::Atom(r0)) => *l0.value.borrow() == *r0.value.borrow(),
+            (Self::Builtin(l0), Self::Builtin(r0)) => l0 == r0,
+            (Self::Continuation(l0), Self::Continuation(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::MultiFn(l0), Self::MultiFn(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::RecordType(l0), Self::RecordType(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Record(l0, l1, _), Self::Record(r0, r1, _)) => Rc::ptr_eq(l0, r0) && l1 == r1,
+            (Self::Handle(l0), Self::Handle(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Instant(l0), Self::Instant(r0)) => l0 == r0,
+            (Self::Transient(l0), Self::Transient(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Generator(l0), Self::Generator(r0)) => {
+                Rc::ptr_eq(&l0.state, &r0.state) && l0.position == r0.position
+            }
             _ => false,
         }
     }
 }
 
-impl std::fmt::Debug for DataType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// A collection split up for printing: the text opening it, the separator
+/// between its items, each item with any text before it, and the text
+/// closing it.
+type PrintedParts<'a> = (
+    std::string::String,
+    &'static str,
+    Vec<(std::string::String, &'a DataType)>,
+    &'static str,
+);
+
+enum Piece<'a> {
+    Value(&'a DataType),
+    Text(std::string::String),
+}
+
+impl DataType {
+    fn printed_parts(&self) -> Option<PrintedParts<'_>> {
+        fn items(items: &[DataType]) -> Vec<(std::string::String, &DataType)> {
+            items
+                .iter()
+                .map(|item| (std::string::String::new(), item))
+                .collect()
+        }
+
         match self {
-            DataType::List(vector) => write!(
-                f,
-                "({})",
-                vector
-                    .iter()
-                    .map(|data_type| format!("{:?}", data_type))
-                    .collect::<Vec<std::string::String>>()
-                    .join(" ")
-            ),
-            DataType::Vector(vector) => write!(
-                f,
-                "[{}]",
-                vector
-                    .iter()
-                    .map(|data_type| format!("{:?}", data_type))
-                    .collect::<Vec<std::string::String>>()
-                    .join(" ")
-            ),
-            DataType::Dictionary(dict) => write!(
-                f,
-                "{{{}}}",
+            DataType::List(list, _) => Some(("(".to_string(), " ", items(list), ")")),
+            DataType::Vector(vector, _) => Some(("[".to_string(), " ", items(vector), "]")),
+            DataType::Dictionary(dict, _) => Some((
+                "{".to_string(),
+                ", ",
                 dict.iter()
-                    .map(|value| format!("{}: {:?}", value.0, value.1))
-                    .collect::<Vec<std::string::String>>()
-                    .join(", ")
-            ),
-            DataType::Symbol(symbol) => write!(f, "{}", symbol),
-            DataType::Comment() => write!(f, ""),
-            DataType::Nil() => write!(f, "nil"),
-            DataType::Bool(value) => write!(f, "{}", value),
-            DataType::Float(float) => write!(f, "{}", float),
-            DataType::Integer(num) => write!(f, "{}", num),
-            DataType::String(str) => write!(f, "\"{}\"", str),
-            DataType::Closure(func) => write!(f, "Closure({:p})", func),
-            DataType::NativeFunction(func) => write!(f, "Fn{}", func.0),
-            DataType::Atom(atom) => write!(f, "Atom({:p})", *atom),
+                    .map(|(key, value)| (format!("{}: ", key), value))
+                    .collect(),
+                "}",
+            )),
+            DataType::Record(record_type, fields, _) => {
+                let mut extra = fields
+                    .keys()
+                    .filter(|key| !record_type.fields.contains(key))
+                    .collect::<Vec<_>>();
+                extra.sort();
+
+                Some((
+                    format!("#{}{{", record_type.name),
+                    " ",
+                    record_type
+                        .fields
+                        .iter()
+                        .chain(extra)
+                        .filter_map(|key| fields.get(key).map(|value| (format!("{} ", key), value)))
+                        .collect(),
+                    "}",
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for DataType {
+    // Collections are printed from a stack of the pieces left to write rather
+    // than by recursing, so any depth of nesting can be printed.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut pieces = vec![Piece::Value(self)];
+
+        while let Some(piece) = pieces.pop() {
+            let value = match piece {
+                Piece::Text(text) => {
+                    f.write_str(&text)?;
+                    continue;
+                }
+                Piece::Value(value) => value,
+            };
+
+            if let Some((open, separator, items, close)) = value.printed_parts() {
+                f.write_str(&open)?;
+                pieces.push(Piece::Text(close.to_string()));
+                for (i, (prefix, item)) in items.into_iter().enumerate().rev() {
+                    pieces.push(Piece::Value(item));
+                    let separator = if i > 0 { separator } else { "" };
+                    pieces.push(Piece::Text(format!("{}{}", separator, prefix)));
+                }
+                continue;
+            }
+
+            match value {
+                DataType::Symbol(symbol, _) => write!(f, "{}", symbol),
+                DataType::Keyword(keyword) => write!(f, ":{}", keyword),
+                DataType::Comment() => write!(f, ""),
+                DataType::Nil() => write!(f, "nil"),
+                DataType::Bool(value) => write!(f, "{}", value),
+                DataType::Float(float) => write!(f, "{}", float),
+                DataType::Integer(num) => write!(f, "{}", num),
+                DataType::String(str) => write!(f, "\"{}\"", str),
+                DataType::Closure(func) => write!(f, "Closure({:p})", func),
+                DataType::NativeFunction(func) => write!(f, "Fn({})", func.name),
+                DataType::Atom(atom) => write!(f, "Atom({:p})", *atom),
+                DataType::Builtin(builtin) => write!(f, "Fn({})", builtin.name()),
+                DataType::Continuation(continuation) => {
+                    write!(f, "Continuation({:p})", *continuation)
+                }
+                DataType::Generator(generator) => write!(f, "Generator({:p})", generator.state),
+                DataType::MultiFn(multi) => write!(f, "MultiFn({})", multi.name),
+                DataType::RecordType(record_type) => write!(f, "{}", record_type.name),
+                DataType::Handle(handle) => write!(f, "Handle({:p})", *handle),
+                DataType::Transient(transient) => write!(f, "Transient({:p})", *transient),
+                DataType::Instant(instant) => {
+                    match datetime::format(*instant, datetime::ISO_PATTERN) {
+                        Ok(text) => write!(f, "#inst \"{}\"", text),
+                        Err(_) => write!(f, "#inst {}", instant),
+                    }
+                }
+                DataType::List(..)
+                | DataType::Vector(..)
+                | DataType::Dictionary(..)
+                | DataType::Record(..) => unreachable!("collections have printed parts"),
+            }?;
+        }
+
+        Ok(())
+    }
+}
+
+// Collections are cloned and dropped a level at a time using a stack on the
+// heap, so deeply nested values don't overflow the Rust stack.
+
+impl DataType {
+    /// A copy of anything but a collection, which holds no nested values.
+    fn clone_leaf(&self) -> Option<DataType> {
+        Some(match self {
+            DataType::Nil() => DataType::Nil(),
+            DataType::Symbol(sym, meta) => DataType::Symbol(sym.clone(), meta.clone()),
+            DataType::Keyword(keyword) => DataType::Keyword(keyword.clone()),
+            DataType::Integer(int) => DataType::Integer(*int),
+            DataType::Bool(bool) => DataType::Bool(*bool),
+            DataType::Float(float) => DataType::Float(*float),
+            DataType::String(string) => DataType::String(string.clone()),
+            DataType::Comment() => DataType::Comment(),
+            DataType::Closure(closure) => DataType::Closure(closure.clone()),
+            DataType::NativeFunction(function) => DataType::NativeFunction(function.clone()),
+            DataType::Atom(atom) => DataType::Atom(atom.clone()),
+            DataType::Builtin(builtin) => DataType::Builtin(*builtin),
+            DataType::Continuation(continuation) => DataType::Continuation(continuation.clone()),
+            DataType::Generator(generator) => DataType::Generator(generator.clone()),
+            DataType::MultiFn(multi) => DataType::MultiFn(multi.clone()),
+            DataType::RecordType(record_type) => DataType::RecordType(record_type.clone()),
+            DataType::Handle(handle) => DataType::Handle(handle.clone()),
+            DataType::Instant(instant) => DataType::Instant(*instant),
+            DataType::Transient(transient) => DataType::Transient(transient.clone()),
+            DataType::List(..)
+            | DataType::Vector(..)
+            | DataType::Dictionary(..)
+            | DataType::Record(..) => return None,
+        })
+    }
+}
+
+impl Clone for DataType {
+    fn clone(&self) -> Self {
+        if let Some(leaf) = self.clone_leaf() {
+            return leaf;
+        }
+
+        enum Task<'a> {
+            Clone(&'a DataType),
+            /// Rebuilds a collection from the copies of its items, which are
+            /// the last ones made, in reverse
+            Build(&'a DataType),
+        }
+
+        let mut tasks = vec![Task::Clone(self)];
+        let mut copies = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Clone(value) => {
+                    if let Some(leaf) = value.clone_leaf() {
+                        copies.push(leaf);
+                        continue;
+                    }
+                    tasks.push(Task::Build(value));
+                    match value {
+                        DataType::List(items, _) | DataType::Vector(items, _) => {
+                            tasks.extend(items.iter().map(Task::Clone));
+                        }
+                        DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => {
+                            tasks.extend(dict.values().map(Task::Clone));
+                        }
+                        _ => unreachable!("only collections aren't leaves"),
+                    }
+                }
+                Task::Build(value) => {
+                    let count = match value {
+                        DataType::List(items, _) | DataType::Vector(items, _) => items.len(),
+                        DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => dict.len(),
+                        _ => unreachable!("only collections are built"),
+                    };
+                    let mut items = copies.split_off(copies.len() - count);
+                    items.reverse();
+
+                    copies.push(match value {
+                        DataType::List(_, meta) => DataType::List(items, meta.clone()),
+                        DataType::Vector(_, meta) => DataType::Vector(items, meta.clone()),
+                        DataType::Dictionary(dict, meta) => DataType::Dictionary(
+                            dict.keys().cloned().zip(items).collect(),
+                            meta.clone(),
+                        ),
+                        DataType::Record(record_type, dict, meta) => DataType::Record(
+                            record_type.clone(),
+                            dict.keys().cloned().zip(items).collect(),
+                            meta.clone(),
+                        ),
+                        _ => unreachable!("only collections are built"),
+                    });
+                }
+            }
+        }
+
+        copies.pop().expect("Cloning should leave exactly one copy")
+    }
+}
+
+impl Drop for DataType {
+    fn drop(&mut self) {
+        let mut items = match self {
+            DataType::List(items, _) | DataType::Vector(items, _) => std::mem::take(items),
+            DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => {
+                dict.drain().map(|(_, value)| value).collect()
+            }
+            _ => return,
+        };
+
+        // Each item is emptied before it is dropped, so its own drop returns
+        // straight away
+        while let Some(mut item) = items.pop() {
+            match &mut item {
+                DataType::List(nested, _) | DataType::Vector(nested, _) => items.append(nested),
+                DataType::Dictionary(dict, _) | DataType::Record(_, dict, _) => {
+                    items.extend(dict.drain().map(|(_, value)| value));
+                }
+                _ => {}
+            }
         }
     }
 }
@@ -1,35 +1,83 @@
-use bracketlang_backend::{create_default_repl_env, rep, run_preamble, variable_type::DataType};
-use std::io::{Write, stdin, stdout};
+use bracketlang_backend::{Interpreter, capability::Capability, variable_type::DataType};
+use project::{Project, ProjectError, VENDOR_DIR};
+use std::{
+    io::{Write, stdin, stdout},
+    path::Path,
+};
+
+mod project;
+
+#[cfg(test)]
+mod tests;
+
+/// Reading and comparing deeply nested values still recurses, so the
+/// interpreter runs on a thread with a far larger stack than the main one.
+/// Only the pages actually used are allocated.
+const INTERPRETER_STACK_SIZE: usize = 1 << 30;
 
 fn main() {
-    let repl_env = create_default_repl_env();
-
-    run_preamble("(def! not (fn* (a) (if a false true)))", repl_env.clone());
-    run_preamble(
-        "(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \"\\nnil)\")))))",
-        repl_env.clone(),
-    );
-    run_preamble(
-        "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
-        repl_env.clone(),
-    );
+    let interpreter = std::thread::Builder::new()
+        .name("interpreter".to_string())
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(run)
+        .expect("Starting the interpreter thread should have worked.");
+
+    if interpreter.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn run() {
+    let interpreter = Interpreter::builder().allow(Capability::Process).build();
 
     let args: Vec<String> = std::env::args().collect();
-    if let Some(filename) = args.get(1) {
-        let mut repl_args = vec![];
-        for arg in &args[2..] {
-            repl_args.push(DataType::String(arg.clone()));
-        }
-        repl_env
-            .borrow_mut()
-            .set("*ARGV*".to_string(), DataType::List(repl_args));
-        match rep(format!("(load-file \"{}\")", filename), repl_env.clone()) {
-            Some(res) => {
-                println!("Error: {}", res);
+
+    if let Some("lock" | "run") = args.get(1).map(String::as_str) {
+        let project = match load_project() {
+            Ok(project) => project,
+            Err(e) => {
+                println!("Error: {}", e.msg);
                 return;
             }
-            None => return,
+        };
+
+        if args[1] == "lock" {
+            if let Err(e) = project.write_lockfile() {
+                println!("Error: {}", e.msg);
+            }
+            return;
         }
+
+        if let Err(e) = project.check_lockfile() {
+            println!("Error: {}", e.msg);
+            return;
+        }
+
+        let Some(entry) = project.entry() else {
+            println!("Error: No entry point given in the manifest");
+            return;
+        };
+
+        let load_path = project
+            .load_path()
+            .into_iter()
+            .map(DataType::String)
+            .collect();
+        interpreter.set_global("*load-path*", DataType::List(load_path, None));
+
+        run_file(&entry, &args[2..], &interpreter);
+        return;
+    }
+
+    if let Some(filename) = args.get(1) {
+        run_file(filename, &args[2..], &interpreter);
+        return;
+    }
+
+    // Ctrl-C stops whatever the REPL is evaluating rather than the process
+    let interrupt = interpreter.interrupt_flag();
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGINT, interrupt) {
+        println!("Couldn't set up Ctrl-C handling: {}", e);
     }
 
     loop {
@@ -50,8 +98,35 @@ fn main() {
             break;
         }
 
-        if let Some(result) = rep(user_input, repl_env.clone()) {
-            println!("{}", result);
+        match interpreter.eval_str(&user_input) {
+            Ok(DataType::Nil()) => {}
+            Ok(result) => println!("{:?}", result),
+            Err(e) => println!("{}", e),
         }
     }
 }
+
+fn load_project() -> Result<Project, ProjectError> {
+    let root = match std::env::current_dir() {
+        Ok(root) => root,
+        Err(e) => {
+            return Err(ProjectError {
+                msg: format!("Couldn't find the current directory: {}", e),
+            });
+        }
+    };
+
+    Project::load(&root, &root.join(VENDOR_DIR))
+}
+
+fn run_file(filename: impl AsRef<Path>, args: &[String], interpreter: &Interpreter) {
+    let mut repl_args = vec![];
+    for arg in args {
+        repl_args.push(DataType::String(arg.clone()));
+    }
+    interpreter.set_global("*ARGV*", DataType::List(repl_args, None));
+
+    if let Err(e) = interpreter.eval_file(filename) {
+        println!("Error: {}", e);
+    }
+}
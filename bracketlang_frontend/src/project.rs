@@ -0,0 +1,453 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const MANIFEST_FILE: &str = "bracket.toml";
+pub const LOCK_FILE: &str = "bracket.lock";
+/// Directory (relative to the project root) that vendored archives are unpacked into.
+pub const VENDOR_DIR: &str = ".bracket/deps";
+
+#[derive(Debug)]
+pub struct ProjectError {
+    pub msg: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub package: Package,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Package {
+    pub name: String,
+    #[serde(default = "default_source_dirs")]
+    pub source_dirs: Vec<String>,
+    pub entry: Option<String>,
+}
+
+/// A dependency on either a local directory or a vendored `.tar` archive,
+/// both relative to the manifest declaring it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dependency {
+    pub path: Option<String>,
+    pub archive: Option<String>,
+}
+
+fn default_source_dirs() -> Vec<String> {
+    vec!["src".to_string()]
+}
+
+impl Manifest {
+    pub fn read(dir: &Path) -> Result<Manifest, ProjectError> {
+        let path = dir.join(MANIFEST_FILE);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                return Err(ProjectError {
+                    msg: format!("Couldn't read {}: {}", path.display(), e),
+                });
+            }
+        };
+
+        match toml::from_str(&contents) {
+            Ok(manifest) => Ok(manifest),
+            Err(e) => Err(ProjectError {
+                msg: format!("Invalid manifest {}: {}", path.display(), e),
+            }),
+        }
+    }
+}
+
+pub struct ResolvedPackage {
+    pub name: String,
+    pub source: String,
+    /// `None` for path dependencies, which are locked by path only since
+    /// they're expected to be edited alongside the project.
+    pub checksum: Option<String>,
+    pub source_dirs: Vec<PathBuf>,
+}
+
+pub struct Project {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+    /// Every dependency, transitive ones included, with dependencies listed
+    /// before the packages that use them.
+    pub packages: Vec<ResolvedPackage>,
+}
+
+impl Project {
+    pub fn load(root: &Path, vendor_dir: &Path) -> Result<Project, ProjectError> {
+        let root = canonicalize(root)?;
+        let manifest = Manifest::read(&root)?;
+
+        let mut resolver = Resolver {
+            root: root.clone(),
+            vendor_dir: vendor_dir.to_path_buf(),
+            packages: vec![],
+            visiting: vec![manifest.package.name.clone()],
+        };
+        resolver.resolve(&root, &manifest)?;
+
+        Ok(Project {
+            root,
+            manifest,
+            packages: resolver.packages,
+        })
+    }
+
+    /// Directories modules are looked up in: the project's own source
+    /// directories first, followed by those of its dependencies.
+    pub fn load_path(&self) -> Vec<String> {
+        let own_dirs = self
+            .manifest
+            .package
+            .source_dirs
+            .iter()
+            .map(|dir| self.root.join(dir));
+        let dependency_dirs = self
+            .packages
+            .iter()
+            .flat_map(|package| package.source_dirs.iter().cloned());
+
+        own_dirs
+            .chain(dependency_dirs)
+            .map(|dir| dir.to_string_lossy().to_string())
+            .collect()
+    }
+
+    pub fn entry(&self) -> Option<PathBuf> {
+        let entry = self.manifest.package.entry.as_ref()?;
+        Some(self.root.join(entry))
+    }
+
+    pub fn lockfile(&self) -> Lockfile {
+        Lockfile {
+            packages: self
+                .packages
+                .iter()
+                .map(|package| LockedPackage {
+                    name: package.name.clone(),
+                    source: package.source.clone(),
+                    checksum: package.checksum.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes `bracket.lock` if it doesn't exist yet, otherwise checks that the
+    /// resolved dependencies still match it.
+    pub fn check_lockfile(&self) -> Result<(), ProjectError> {
+        let path = self.root.join(LOCK_FILE);
+        if !path.exists() {
+            return self.write_lockfile();
+        }
+
+        let locked = Lockfile::read(&path)?;
+        let resolved = self.lockfile();
+
+        for package in &resolved.packages {
+            match locked.packages.iter().find(|p| p.name == package.name) {
+                Some(locked_package) if locked_package.matches(package) => {}
+                Some(_) => {
+                    return Err(ProjectError {
+                        msg: format!(
+                            "Dependency {} has changed since {} was written, run `lock` to update it",
+                            package.name, LOCK_FILE
+                        ),
+                    });
+                }
+                None => {
+                    return Err(ProjectError {
+                        msg: format!(
+                            "Dependency {} is missing from {}, run `lock` to update it",
+                            package.name, LOCK_FILE
+                        ),
+                    });
+                }
+            }
+        }
+
+        if locked.packages.len() != resolved.packages.len() {
+            return Err(ProjectError {
+                msg: format!(
+                    "{} lists dependencies that are no longer used, run `lock` to update it",
+                    LOCK_FILE
+                ),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn write_lockfile(&self) -> Result<(), ProjectError> {
+        let path = self.root.join(LOCK_FILE);
+
+        match fs::write(&path, self.lockfile().to_string()) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(ProjectError {
+                msg: format!("Couldn't write {}: {}", path.display(), e),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Lockfile {
+    #[serde(rename = "package", default)]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct LockedPackage {
+    pub name: String,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+impl LockedPackage {
+    /// Checksums are only compared when the resolved package has one, so
+    /// lockfiles written before path dependencies stopped being hashed still
+    /// match.
+    fn matches(&self, resolved: &LockedPackage) -> bool {
+        self.name == resolved.name
+            && self.source == resolved.source
+            && (resolved.checksum.is_none() || self.checksum == resolved.checksum)
+    }
+}
+
+impl Lockfile {
+    pub fn read(path: &Path) -> Result<Lockfile, ProjectError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                return Err(ProjectError {
+                    msg: format!("Couldn't read {}: {}", path.display(), e),
+                });
+            }
+        };
+
+        match toml::from_str(&contents) {
+            Ok(lockfile) => Ok(lockfile),
+            Err(e) => Err(ProjectError {
+                msg: format!("Invalid lockfile {}: {}", path.display(), e),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Lockfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "# This file is generated by bracketlang. Do not edit it by hand."
+        )?;
+        writeln!(f)?;
+
+        match toml::to_string(self) {
+            Ok(contents) => write!(f, "{}", contents),
+            Err(_) => Err(std::fmt::Error),
+        }
+    }
+}
+
+struct Resolver {
+    root: PathBuf,
+    vendor_dir: PathBuf,
+    packages: Vec<ResolvedPackage>,
+    visiting: Vec<String>,
+}
+
+impl Resolver {
+    fn resolve(&mut self, manifest_dir: &Path, manifest: &Manifest) -> Result<(), ProjectError> {
+        for (name, dependency) in &manifest.dependencies {
+            let (source, package_dir, checksum) = match (&dependency.path, &dependency.archive) {
+                (Some(path), None) => {
+                    let package_dir = canonicalize(&manifest_dir.join(path))?;
+                    let source = format!("path+{}", relative_path(&self.root, &package_dir));
+                    (source, package_dir, None)
+                }
+
+                (None, Some(archive)) => {
+                    let archive_path = canonicalize(&manifest_dir.join(archive))?;
+                    let bytes = match fs::read(&archive_path) {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            return Err(ProjectError {
+                                msg: format!("Couldn't read {}: {}", archive_path.display(), e),
+                            });
+                        }
+                    };
+                    let checksum = format!("fnv1a64:{:016x}", fnv1a64(&bytes, FNV_OFFSET));
+                    let package_dir = self.unpack_archive(name, &checksum, &bytes)?;
+                    let source = format!("archive+{}", relative_path(&self.root, &archive_path));
+                    (source, package_dir, Some(checksum))
+                }
+
+                _ => {
+                    return Err(ProjectError {
+                        msg: format!(
+                            "Dependency {} should have exactly one of `path` or `archive`",
+                            name
+                        ),
+                    });
+                }
+            };
+
+            if let Some(existing) = self.packages.iter().find(|p| p.name == *name) {
+                if existing.source == source {
+                    continue;
+                }
+                return Err(ProjectError {
+                    msg: format!(
+                        "Dependency {} is required from both {} and {}",
+                        name, existing.source, source
+                    ),
+                });
+            }
+
+            if self.visiting.contains(name) {
+                let mut chain = self.visiting.clone();
+                chain.push(name.clone());
+                return Err(ProjectError {
+                    msg: format!("Cyclic dependency: {}", chain.join(" -> ")),
+                });
+            }
+
+            let dependency_manifest = Manifest::read(&package_dir)?;
+            if dependency_manifest.package.name != *name {
+                return Err(ProjectError {
+                    msg: format!(
+                        "Dependency {} points at package {}",
+                        name, dependency_manifest.package.name
+                    ),
+                });
+            }
+
+            self.visiting.push(name.clone());
+            self.resolve(&package_dir, &dependency_manifest)?;
+            self.visiting.pop();
+
+            let source_dirs = dependency_manifest
+                .package
+                .source_dirs
+                .iter()
+                .map(|dir| package_dir.join(dir))
+                .collect();
+
+            self.packages.push(ResolvedPackage {
+                name: name.clone(),
+                source,
+                checksum,
+                source_dirs,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Unpacks an archive into the vendor directory, keyed by its checksum so
+    /// an unchanged archive is only ever extracted once.
+    fn unpack_archive(
+        &self,
+        name: &str,
+        checksum: &str,
+        bytes: &[u8],
+    ) -> Result<PathBuf, ProjectError> {
+        let digest = checksum.trim_start_matches("fnv1a64:");
+        let target = self.vendor_dir.join(format!("{}-{}", name, digest));
+
+        if !target.exists() {
+            let staging = self.vendor_dir.join(format!("{}-{}.partial", name, digest));
+            let _ = fs::remove_dir_all(&staging);
+
+            let unpacked = fs::create_dir_all(&staging)
+                .and_then(|_| tar::Archive::new(bytes).unpack(&staging))
+                .and_then(|_| fs::rename(&staging, &target));
+            if let Err(e) = unpacked {
+                return Err(ProjectError {
+                    msg: format!("Couldn't unpack archive for {}: {}", name, e),
+                });
+            }
+        }
+
+        if target.join(MANIFEST_FILE).exists() {
+            return Ok(target);
+        }
+
+        // Archives commonly wrap everything in a single top level directory
+        let entries = match fs::read_dir(&target) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.join(MANIFEST_FILE).exists())
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                return Err(ProjectError {
+                    msg: format!("Couldn't read {}: {}", target.display(), e),
+                });
+            }
+        };
+
+        match entries.as_slice() {
+            [package_dir] => Ok(package_dir.clone()),
+            _ => Err(ProjectError {
+                msg: format!("Archive for {} doesn't contain a {}", name, MANIFEST_FILE),
+            }),
+        }
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, ProjectError> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(e) => Err(ProjectError {
+            msg: format!("Couldn't find {}: {}", path.display(), e),
+        }),
+    }
+}
+
+/// Renders `to` relative to `from` so lockfiles don't depend on where the
+/// project is checked out.
+fn relative_path(from: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut parts: Vec<String> = vec![];
+    for _ in common..from.len() {
+        parts.push("..".to_string());
+    }
+    for component in &to[common..] {
+        parts.push(component.as_os_str().to_string_lossy().to_string());
+    }
+
+    if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a is used over `DefaultHasher` because lockfile checksums have to stay
+/// the same across Rust releases.
+fn fnv1a64(bytes: &[u8], mut hash: u64) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
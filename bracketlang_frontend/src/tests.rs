@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+use crate::project::*;
+
+fn vendor_dir(test_name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bracketlang-{}-{}", test_name, std::process::id()))
+}
+
+#[test]
+fn test_resolve_dependencies() {
+    let project = Project::load(
+        Path::new("test_projects/app"),
+        &vendor_dir("resolve_dependencies"),
+    )
+    .unwrap();
+
+    let names: Vec<&str> = project.packages.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["mathlib", "strings"]);
+
+    let load_path = project.load_path();
+    assert_eq!(load_path.len(), 3);
+    assert!(load_path[0].ends_with("app/src"));
+    assert!(load_path[1].ends_with("libs/mathlib/src"));
+    assert!(load_path[2].ends_with("strings/src"));
+    assert!(Path::new(&load_path[2]).join("strings/core.bl").exists());
+}
+
+#[test]
+fn test_lockfile_round_trip() {
+    let project = Project::load(
+        Path::new("test_projects/app"),
+        &vendor_dir("lockfile_round_trip"),
+    )
+    .unwrap();
+    let lockfile = project.lockfile();
+
+    assert_eq!(lockfile.packages[0].source, "path+libs/mathlib");
+    assert_eq!(lockfile.packages[1].source, "archive+vendor/strings.tar");
+
+    let parsed: Lockfile = toml::from_str(&lockfile.to_string()).unwrap();
+    assert_eq!(parsed.packages, lockfile.packages);
+}
+
+#[test]
+fn test_cyclic_dependency() {
+    let result = Project::load(
+        Path::new("test_projects/cycle"),
+        &vendor_dir("cyclic_dependency"),
+    );
+
+    assert_eq!(
+        result.err().unwrap().msg,
+        "Cyclic dependency: cycle -> a -> cycle"
+    );
+}
+
+#[test]
+fn test_edited_path_dependency_still_matches_lockfile() {
+    let root = vendor_dir("edited_path_dependency");
+    let lib = root.join("libs/mathlib");
+    std::fs::create_dir_all(lib.join("src")).unwrap();
+    std::fs::write(
+        root.join("bracket.toml"),
+        "[package]\nname = \"app\"\n\n[dependencies]\nmathlib = { path = \"libs/mathlib\" }\n",
+    )
+    .unwrap();
+    std::fs::write(lib.join("bracket.toml"), "[package]\nname = \"mathlib\"\n").unwrap();
+    std::fs::write(lib.join("src/core.bl"), "(def! x 1)").unwrap();
+
+    let project = Project::load(&root, &root.join(VENDOR_DIR)).unwrap();
+    project.check_lockfile().unwrap();
+    assert_eq!(project.lockfile().packages[0].checksum, None);
+
+    std::fs::write(lib.join("src/core.bl"), "(def! x 2)").unwrap();
+    let project = Project::load(&root, &root.join(VENDOR_DIR)).unwrap();
+    let result = project.check_lockfile();
+
+    std::fs::remove_dir_all(&root).unwrap();
+    result.unwrap();
+}